@@ -0,0 +1,17 @@
+BEGIN; -- Work in a transaction
+
+-- The balance a bank statement reports for an account at the end of a day,
+-- to compare with the balance the account has in the books
+CREATE TABLE StatementCheckpoints (
+  id BIGSERIAL PRIMARY KEY,
+  account_id BIGINT NOT NULL,
+  day DATE NOT NULL,
+  -- Exactly same numeric as AccountChanges
+  balance NUMERIC(32,2) NOT NULL,
+
+  UNIQUE (account_id, day),
+
+  FOREIGN KEY (account_id) REFERENCES Accounts(id)
+);
+
+COMMIT; -- Apply the transaction
@@ -0,0 +1,26 @@
+BEGIN; -- Work in a transaction
+
+-- One uploaded file, which the imported account changes from it reference
+CREATE TABLE ImportBatches (
+  id BIGSERIAL PRIMARY KEY,
+  bookkeeping_id BIGINT NOT NULL,
+  file_name VARCHAR(256) NOT NULL,
+  -- SHA-256 of the file, hex encoded
+  file_hash VARCHAR(64) NOT NULL,
+  uploader_id BIGINT NOT NULL,
+  uploaded_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  -- Number of imported account changes created from the file
+  row_count INTEGER NOT NULL DEFAULT 0,
+
+  FOREIGN KEY (bookkeeping_id) REFERENCES Bookkeepings(id),
+  FOREIGN KEY (uploader_id) REFERENCES Users(id)
+);
+
+-- Lines imported before batches existed have no batch
+ALTER TABLE ImportedAccountChanges
+  ADD COLUMN batch_id BIGINT REFERENCES ImportBatches(id);
+-- The statement balances a file reported go away when its batch is undone
+ALTER TABLE StatementCheckpoints
+  ADD COLUMN batch_id BIGINT REFERENCES ImportBatches(id);
+
+COMMIT; -- Apply the transaction
@@ -0,0 +1,57 @@
+BEGIN; -- Work in a transaction
+
+-- Transactions start as drafts, and are posted once their account changes sum
+-- to zero. Only posted transactions count towards balances.
+ALTER TABLE Transactions ADD COLUMN posted BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Existing transactions that balance are taken to be done
+UPDATE Transactions SET posted = TRUE
+WHERE EXISTS (
+  SELECT 1 FROM AccountChanges WHERE AccountChanges.transaction_id = Transactions.id
+) AND (
+  SELECT SUM(AccountChanges.amount) FROM AccountChanges
+  WHERE AccountChanges.transaction_id = Transactions.id
+) = 0;
+
+-- Posted transactions must stay balanced
+-- Checked at commit, so a transaction's changes can be written one at a time
+CREATE FUNCTION check_posted_transaction_balanced() RETURNS TRIGGER
+LANGUAGE plpgsql AS $$
+DECLARE
+  -- The transactions the changed row belongs to, before and after the change
+  checked BIGINT[];
+  unbalanced BIGINT;
+BEGIN
+  IF TG_TABLE_NAME = 'transactions' THEN
+    checked := ARRAY[NEW.id];
+  ELSIF TG_OP = 'DELETE' THEN
+    checked := ARRAY[OLD.transaction_id];
+  ELSIF TG_OP = 'UPDATE' THEN
+    checked := ARRAY[OLD.transaction_id, NEW.transaction_id];
+  ELSE
+    checked := ARRAY[NEW.transaction_id];
+  END IF;
+  SELECT Transactions.id INTO unbalanced FROM Transactions
+  WHERE Transactions.id = ANY(checked) AND Transactions.posted
+    AND COALESCE((
+      SELECT SUM(AccountChanges.amount) FROM AccountChanges
+      WHERE AccountChanges.transaction_id = Transactions.id
+    ), 0) != 0
+  LIMIT 1;
+  IF unbalanced IS NOT NULL THEN
+    RAISE EXCEPTION 'Posted transaction % does not balance', unbalanced
+      USING ERRCODE = 'BKUNB';
+  END IF;
+  RETURN NULL;
+END
+$$;
+CREATE CONSTRAINT TRIGGER AccountChangesKeepPostedBalanced
+  AFTER INSERT OR UPDATE OR DELETE ON AccountChanges
+  DEFERRABLE INITIALLY DEFERRED
+  FOR EACH ROW EXECUTE FUNCTION check_posted_transaction_balanced();
+CREATE CONSTRAINT TRIGGER TransactionsPostOnlyBalanced
+  AFTER INSERT OR UPDATE OF posted ON Transactions
+  DEFERRABLE INITIALLY DEFERRED
+  FOR EACH ROW EXECUTE FUNCTION check_posted_transaction_balanced();
+
+COMMIT; -- Apply the transaction
@@ -0,0 +1,128 @@
+BEGIN; -- Work in a transaction
+
+-- Every change to the data of a bookkeeping, to know who changed what
+-- Written by triggers, so nothing can change the data without being logged
+CREATE TABLE AuditLog (
+  id BIGSERIAL PRIMARY KEY,
+  -- Not a foreign key, as the log is kept when the bookkeeping is deleted
+  -- (including the log of deleting it)
+  bookkeeping_id BIGINT NOT NULL,
+  -- Set by the application per transaction, NULL for changes made outside it
+  user_id BIGINT,
+  changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  -- The table changed and the id of the changed row in it
+  entity VARCHAR(64) NOT NULL,
+  entity_id BIGINT NOT NULL,
+  operation VARCHAR(6) NOT NULL,
+  -- The row as JSON, before is NULL for inserts and after for deletes
+  before JSONB,
+  after JSONB,
+
+  CHECK (operation IN ('INSERT', 'UPDATE', 'DELETE')),
+
+  FOREIGN KEY (user_id) REFERENCES Users(id)
+);
+CREATE INDEX AuditLogBookkeeping ON AuditLog(bookkeeping_id, changed_at);
+CREATE INDEX AuditLogEntity ON AuditLog(entity, entity_id);
+-- The history of a transaction includes that of its account changes
+CREATE INDEX AuditLogBeforeTransaction ON AuditLog(((before->>'transaction_id')::BIGINT))
+  WHERE entity = 'accountchanges';
+CREATE INDEX AuditLogAfterTransaction ON AuditLog(((after->>'transaction_id')::BIGINT))
+  WHERE entity = 'accountchanges';
+
+-- The log is append-only
+CREATE FUNCTION audit_log_append_only() RETURNS TRIGGER
+LANGUAGE plpgsql AS $$
+BEGIN
+  RAISE EXCEPTION 'The audit log can only be appended to';
+END
+$$;
+CREATE TRIGGER AuditLogAppendOnly
+  BEFORE UPDATE OR DELETE OR TRUNCATE ON AuditLog
+  FOR EACH STATEMENT EXECUTE FUNCTION audit_log_append_only();
+
+-- The logged row with every value as text, to show values exactly as stored
+-- (amounts would lose their decimals as JSON numbers)
+CREATE FUNCTION audit_fields_as_text(fields JSONB) RETURNS JSONB
+LANGUAGE SQL IMMUTABLE AS $$
+  SELECT jsonb_object_agg(key, value #>> '{}') FROM jsonb_each(fields)
+$$;
+
+CREATE FUNCTION audit_change() RETURNS TRIGGER
+LANGUAGE plpgsql AS $$
+DECLARE
+  before JSONB;
+  after JSONB;
+  changed JSONB;
+  bookkeeping BIGINT;
+BEGIN
+  IF TG_OP != 'INSERT' THEN before := to_jsonb(OLD); END IF;
+  IF TG_OP != 'DELETE' THEN after := to_jsonb(NEW); END IF;
+  changed := COALESCE(after, before);
+  -- Updates that don't change anything aren't worth logging
+  IF before = after THEN RETURN NULL; END IF;
+  -- Find the bookkeeping through the row's links up to it
+  bookkeeping := CASE TG_TABLE_NAME
+    WHEN 'bookkeepings' THEN (changed->>'id')::BIGINT
+    WHEN 'transactions' THEN (
+      SELECT Groupings.bookkeeping_id FROM Groupings
+      WHERE Groupings.id = (changed->>'grouping_id')::BIGINT
+    )
+    WHEN 'accountchanges' THEN (
+      SELECT Groupings.bookkeeping_id FROM Transactions
+        INNER JOIN Groupings ON Groupings.id = Transactions.grouping_id
+      WHERE Transactions.id = (changed->>'transaction_id')::BIGINT
+    )
+    WHEN 'accountimportprofiles' THEN (
+      SELECT Accounts.bookkeeping_id FROM Accounts
+      WHERE Accounts.id = (changed->>'account_id')::BIGINT
+    )
+    WHEN 'importedaccountchanges' THEN (
+      SELECT Accounts.bookkeeping_id FROM Accounts
+      WHERE Accounts.id = (changed->>'account_id')::BIGINT
+    )
+    WHEN 'statementcheckpoints' THEN (
+      SELECT Accounts.bookkeeping_id FROM Accounts
+      WHERE Accounts.id = (changed->>'account_id')::BIGINT
+    )
+    ELSE (changed->>'bookkeeping_id')::BIGINT
+  END;
+  INSERT INTO AuditLog(bookkeeping_id, user_id, entity, entity_id, operation,
+      before, after)
+    VALUES(
+      bookkeeping,
+      NULLIF(current_setting('bookkeep.user_id', TRUE), '')::BIGINT,
+      TG_TABLE_NAME,
+      -- Import profiles are identified by their account
+      COALESCE(changed->>'id', changed->>'account_id')::BIGINT,
+      TG_OP,
+      before,
+      after
+    );
+  RETURN NULL;
+END
+$$;
+CREATE TRIGGER BookkeepingsAudit AFTER INSERT OR UPDATE OR DELETE
+  ON Bookkeepings FOR EACH ROW EXECUTE FUNCTION audit_change();
+CREATE TRIGGER UsersBookkeepingsAccessAudit AFTER INSERT OR UPDATE OR DELETE
+  ON UsersBookkeepingsAccess FOR EACH ROW EXECUTE FUNCTION audit_change();
+CREATE TRIGGER AccountsAudit AFTER INSERT OR UPDATE OR DELETE
+  ON Accounts FOR EACH ROW EXECUTE FUNCTION audit_change();
+CREATE TRIGGER GroupingsAudit AFTER INSERT OR UPDATE OR DELETE
+  ON Groupings FOR EACH ROW EXECUTE FUNCTION audit_change();
+CREATE TRIGGER TransactionsAudit AFTER INSERT OR UPDATE OR DELETE
+  ON Transactions FOR EACH ROW EXECUTE FUNCTION audit_change();
+CREATE TRIGGER AccountChangesAudit AFTER INSERT OR UPDATE OR DELETE
+  ON AccountChanges FOR EACH ROW EXECUTE FUNCTION audit_change();
+CREATE TRIGGER AccountImportProfilesAudit AFTER INSERT OR UPDATE OR DELETE
+  ON AccountImportProfiles FOR EACH ROW EXECUTE FUNCTION audit_change();
+CREATE TRIGGER ImportedAccountChangesAudit AFTER INSERT OR UPDATE OR DELETE
+  ON ImportedAccountChanges FOR EACH ROW EXECUTE FUNCTION audit_change();
+CREATE TRIGGER ImportRulesAudit AFTER INSERT OR UPDATE OR DELETE
+  ON ImportRules FOR EACH ROW EXECUTE FUNCTION audit_change();
+CREATE TRIGGER ImportBatchesAudit AFTER INSERT OR UPDATE OR DELETE
+  ON ImportBatches FOR EACH ROW EXECUTE FUNCTION audit_change();
+CREATE TRIGGER StatementCheckpointsAudit AFTER INSERT OR UPDATE OR DELETE
+  ON StatementCheckpoints FOR EACH ROW EXECUTE FUNCTION audit_change();
+
+COMMIT; -- Apply the transaction
@@ -0,0 +1,60 @@
+BEGIN; -- Work in a transaction
+
+-- Reported periods must not change, so transactions and account changes dated
+-- on or before the lock date of the bookkeeping (or in a locked grouping) can't
+-- be created, updated or deleted
+ALTER TABLE Bookkeepings ADD COLUMN locked_until DATE;
+ALTER TABLE Groupings ADD COLUMN locked BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Raises SQLSTATE BKLCK, which the application reports as PeriodLocked
+CREATE FUNCTION assert_period_unlocked(entity TEXT, fields JSONB) RETURNS VOID
+LANGUAGE plpgsql AS $$
+DECLARE
+  day DATE := (fields->>'day')::DATE;
+  grouping BIGINT;
+  grouping_name TEXT;
+  grouping_locked BOOLEAN;
+  locked_until DATE;
+BEGIN
+  IF entity = 'transactions' THEN
+    grouping := (fields->>'grouping_id')::BIGINT;
+  ELSE
+    SELECT Transactions.grouping_id INTO grouping FROM Transactions
+    WHERE Transactions.id = (fields->>'transaction_id')::BIGINT;
+  END IF;
+  SELECT Groupings.name, Groupings.locked, Bookkeepings.locked_until
+    INTO grouping_name, grouping_locked, locked_until
+    FROM Groupings
+    INNER JOIN Bookkeepings ON Bookkeepings.id = Groupings.bookkeeping_id
+  WHERE Groupings.id = grouping;
+  IF grouping_locked THEN
+    RAISE EXCEPTION 'The grouping % is locked', grouping_name
+      USING ERRCODE = 'BKLCK';
+  END IF;
+  IF day <= locked_until THEN
+    RAISE EXCEPTION 'The bookkeeping is locked up to and including %', locked_until
+      USING ERRCODE = 'BKLCK';
+  END IF;
+END
+$$;
+-- Both the old and the new row must be outside locked periods, so nothing can
+-- be moved into or out of one
+CREATE FUNCTION check_period_unlocked() RETURNS TRIGGER
+LANGUAGE plpgsql AS $$
+BEGIN
+  IF TG_OP != 'INSERT' THEN
+    PERFORM assert_period_unlocked(TG_TABLE_NAME, to_jsonb(OLD));
+  END IF;
+  IF TG_OP != 'DELETE' THEN
+    PERFORM assert_period_unlocked(TG_TABLE_NAME, to_jsonb(NEW));
+    RETURN NEW;
+  END IF;
+  RETURN OLD;
+END
+$$;
+CREATE TRIGGER TransactionsPeriodLock BEFORE INSERT OR UPDATE OR DELETE
+  ON Transactions FOR EACH ROW EXECUTE FUNCTION check_period_unlocked();
+CREATE TRIGGER AccountChangesPeriodLock BEFORE INSERT OR UPDATE OR DELETE
+  ON AccountChanges FOR EACH ROW EXECUTE FUNCTION check_period_unlocked();
+
+COMMIT; -- Apply the transaction
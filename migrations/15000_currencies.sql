@@ -0,0 +1,33 @@
+BEGIN; -- Work in a transaction
+
+-- Amounts of account changes stay in the base currency of the bookkeeping, so
+-- transactions balance no matter the currencies of their accounts. Changes to
+-- accounts in other currencies also keep the amount in the account's currency.
+ALTER TABLE Bookkeepings ADD COLUMN base_currency CHAR(3) NOT NULL DEFAULT 'SEK'
+  CHECK (base_currency ~ '^[A-Z]{3}$');
+-- NULL for accounts in the base currency
+ALTER TABLE Accounts ADD COLUMN currency CHAR(3)
+  CHECK (currency ~ '^[A-Z]{3}$');
+-- The amount in the account's currency, NULL for accounts in the base currency
+ALTER TABLE AccountChanges ADD COLUMN original_amount NUMERIC(32,2);
+
+-- Rates to convert amounts in other currencies with, the latest rate on or
+-- before the day of an account change is used for it
+CREATE TABLE ExchangeRates (
+  id BIGSERIAL PRIMARY KEY,
+  bookkeeping_id BIGINT NOT NULL,
+  currency CHAR(3) NOT NULL,
+  day DATE NOT NULL,
+  -- Units of the base currency for one unit of the currency
+  rate NUMERIC(24,10) NOT NULL,
+
+  CHECK (currency ~ '^[A-Z]{3}$'),
+  CHECK (rate > 0),
+  UNIQUE (bookkeeping_id, currency, day),
+
+  FOREIGN KEY (bookkeeping_id) REFERENCES Bookkeepings(id)
+);
+CREATE TRIGGER ExchangeRatesAudit AFTER INSERT OR UPDATE OR DELETE
+  ON ExchangeRates FOR EACH ROW EXECUTE FUNCTION audit_change();
+
+COMMIT; -- Apply the transaction
@@ -0,0 +1,12 @@
+BEGIN; -- Work in a transaction
+
+-- Accounts can be put under another account of the same type, whose balance
+-- then includes theirs. Changing the type of an account moves its
+-- sub-accounts along with it.
+ALTER TABLE Accounts ADD COLUMN parent_id BIGINT;
+ALTER TABLE Accounts ADD UNIQUE (id, bookkeeping_id, type);
+ALTER TABLE Accounts ADD FOREIGN KEY (parent_id, bookkeeping_id, type)
+  REFERENCES Accounts(id, bookkeeping_id, type) ON UPDATE CASCADE;
+ALTER TABLE Accounts ADD CHECK (parent_id != id);
+
+COMMIT; -- Apply the transaction
@@ -0,0 +1,36 @@
+BEGIN; -- Work in a transaction
+
+-- Accounts can have a number, as accountants and SIE files identify them by,
+-- which also orders them in listings
+ALTER TABLE Accounts ADD COLUMN number INTEGER CHECK (number >= 0);
+ALTER TABLE Accounts ADD UNIQUE (bookkeeping_id, number);
+
+-- Accounts imported from SIE files or charts of accounts so far have their
+-- number at the start of the name, so move it over where that is unambiguous:
+-- no other account gets the same number or name, nor already has that name
+WITH Numbered AS (
+  SELECT id, bookkeeping_id,
+    substring(name FROM '^([0-9]{1,9}) ')::INTEGER AS number,
+    substring(name FROM '^[0-9]{1,9} +(.*)$') AS bare_name
+  FROM Accounts
+  WHERE name ~ '^[0-9]{1,9} +[^ ]'
+), Counted AS (
+  SELECT id, number, bare_name,
+    COUNT(*) OVER (PARTITION BY bookkeeping_id, number) AS same_number,
+    COUNT(*) OVER (PARTITION BY bookkeeping_id, bare_name) AS same_name
+  FROM Numbered
+)
+UPDATE Accounts SET
+  number = Counted.number,
+  name = Counted.bare_name
+FROM Counted
+WHERE Accounts.id = Counted.id
+  AND Counted.same_number = 1
+  AND Counted.same_name = 1
+  AND NOT EXISTS (
+    SELECT 1 FROM Accounts AS Other
+    WHERE Other.bookkeeping_id = Accounts.bookkeeping_id AND Other.name = Counted.bare_name
+  )
+;
+
+COMMIT; -- Apply the transaction
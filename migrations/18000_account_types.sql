@@ -0,0 +1,34 @@
+BEGIN; -- Work in a transaction
+
+-- Each base type has the side its balance is normally on, so balances can be
+-- shown as positive when they are as expected. Amounts are positive on the
+-- debit side.
+ALTER TABLE AccountTypes ADD COLUMN normal_balance VARCHAR(6) NOT NULL DEFAULT 'Debit'
+  CHECK (normal_balance IN ('Debit', 'Credit'));
+UPDATE AccountTypes SET normal_balance = 'Credit' WHERE name IN ('Debt', 'Income');
+INSERT INTO AccountTypes(name, normal_balance) VALUES ('Equity', 'Credit');
+ALTER TABLE AccountTypes ALTER COLUMN normal_balance DROP DEFAULT;
+
+-- Subtypes a bookkeeping defines to classify its accounts further, each under
+-- one of the base types
+CREATE TABLE AccountSubtypes (
+  id BIGSERIAL PRIMARY KEY,
+  bookkeeping_id BIGINT NOT NULL,
+  name VARCHAR(64) NOT NULL,
+  type VARCHAR(64) NOT NULL,
+
+  UNIQUE (bookkeeping_id, name),
+  UNIQUE (id, bookkeeping_id, type),
+
+  FOREIGN KEY (bookkeeping_id) REFERENCES Bookkeepings(id),
+  FOREIGN KEY (type) REFERENCES AccountTypes(name)
+);
+CREATE TRIGGER AccountSubtypesAudit AFTER INSERT OR UPDATE OR DELETE
+  ON AccountSubtypes FOR EACH ROW EXECUTE FUNCTION audit_change();
+
+-- An account's subtype must be of the bookkeeping and of the account's type
+ALTER TABLE Accounts ADD COLUMN subtype_id BIGINT;
+ALTER TABLE Accounts ADD FOREIGN KEY (subtype_id, bookkeeping_id, type)
+  REFERENCES AccountSubtypes(id, bookkeeping_id, type);
+
+COMMIT; -- Apply the transaction
@@ -0,0 +1,56 @@
+BEGIN; -- Work in a transaction
+
+-- Transactions that recur on a schedule, which are created as drafts by a
+-- background task as their days come
+CREATE TABLE RecurringTransactions (
+  id BIGSERIAL PRIMARY KEY,
+  bookkeeping_id BIGINT NOT NULL,
+  -- Of the transactions created
+  name VARCHAR(64) NOT NULL,
+  -- Monthly on a day of the month (the last day for shorter months), or every
+  -- number of weeks counted from the start date
+  schedule VARCHAR(16) NOT NULL,
+  day_of_month SMALLINT,
+  interval_weeks SMALLINT,
+  start_date DATE NOT NULL,
+  -- The last day an occurrence can be on, if any
+  end_date DATE,
+  -- The grouping to create transactions in, or NULL for the grouping named
+  -- after the year of the occurrence (created if missing)
+  grouping_id BIGINT,
+  -- Occurrences on or before this day have been created, which is updated in
+  -- the same transaction as they are so each is created once
+  generated_until DATE,
+
+  UNIQUE (bookkeeping_id, name),
+  UNIQUE (id, bookkeeping_id),
+  CHECK (
+    (schedule = 'Monthly' AND day_of_month BETWEEN 1 AND 31 AND interval_weeks IS NULL)
+    OR (schedule = 'Weekly' AND interval_weeks >= 1 AND day_of_month IS NULL)
+  ),
+  CHECK (end_date >= start_date),
+
+  FOREIGN KEY (bookkeeping_id) REFERENCES Bookkeepings(id),
+  FOREIGN KEY (grouping_id) REFERENCES Groupings(id)
+);
+-- The account changes of each occurrence. The bookkeeping is repeated so the
+-- lines can be found in its history.
+CREATE TABLE RecurringTransactionLines (
+  id BIGSERIAL PRIMARY KEY,
+  recurring_transaction_id BIGINT NOT NULL,
+  bookkeeping_id BIGINT NOT NULL,
+  account_id BIGINT NOT NULL,
+  message TEXT NOT NULL DEFAULT '',
+  -- In the account's currency, converted at the rate of each occurrence
+  amount NUMERIC(32,2) NOT NULL,
+
+  FOREIGN KEY (recurring_transaction_id, bookkeeping_id)
+    REFERENCES RecurringTransactions(id, bookkeeping_id),
+  FOREIGN KEY (account_id) REFERENCES Accounts(id)
+);
+CREATE TRIGGER RecurringTransactionsAudit AFTER INSERT OR UPDATE OR DELETE
+  ON RecurringTransactions FOR EACH ROW EXECUTE FUNCTION audit_change();
+CREATE TRIGGER RecurringTransactionLinesAudit AFTER INSERT OR UPDATE OR DELETE
+  ON RecurringTransactionLines FOR EACH ROW EXECUTE FUNCTION audit_change();
+
+COMMIT; -- Apply the transaction
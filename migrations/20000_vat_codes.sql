@@ -0,0 +1,51 @@
+BEGIN; -- Work in a transaction
+
+-- So tables can require their accounts to be in the same bookkeeping
+ALTER TABLE Accounts ADD UNIQUE (id, bookkeeping_id);
+
+-- VAT codes of a bookkeeping, telling how account changes are taxed so VAT
+-- lines can be created for them and the VAT return can be summed up
+CREATE TABLE VatCodes (
+  id BIGSERIAL PRIMARY KEY,
+  bookkeeping_id BIGINT NOT NULL,
+  name VARCHAR(64) NOT NULL,
+  -- In percent
+  rate NUMERIC(5,2) NOT NULL CHECK (rate >= 0),
+  -- Output for sales, Input for purchases
+  direction VARCHAR(16) NOT NULL CHECK (direction IN ('Output', 'Input')),
+  -- Where the sale or purchase is made, and so who pays the VAT
+  kind VARCHAR(16) NOT NULL CHECK (kind IN (
+    'Domestic', 'ReverseCharge', 'EUGoods', 'EUServices', 'NonEUGoods', 'NonEUServices'
+  )),
+  -- The accounts VAT lines are created on. Purchases where the buyer pays
+  -- the VAT use both, as the VAT is both owed and deducted.
+  output_account_id BIGINT,
+  input_account_id BIGINT,
+
+  UNIQUE (bookkeeping_id, name),
+  UNIQUE (id, bookkeeping_id),
+  -- VAT on sales abroad or to buyers paying the VAT is not charged
+  CHECK (direction = 'Input' OR kind = 'Domestic' OR rate = 0),
+
+  FOREIGN KEY (bookkeeping_id) REFERENCES Bookkeepings(id),
+  FOREIGN KEY (output_account_id, bookkeeping_id) REFERENCES Accounts(id, bookkeeping_id),
+  FOREIGN KEY (input_account_id, bookkeeping_id) REFERENCES Accounts(id, bookkeeping_id)
+);
+CREATE TRIGGER VatCodesAudit AFTER INSERT OR UPDATE OR DELETE
+  ON VatCodes FOR EACH ROW EXECUTE FUNCTION audit_change();
+
+-- The amount VAT is charged on is the Base, the VAT created for it is Output
+-- or Input VAT
+ALTER TABLE AccountChanges ADD COLUMN vat_code_id BIGINT REFERENCES VatCodes(id);
+ALTER TABLE AccountChanges ADD COLUMN vat_role VARCHAR(16)
+  CHECK (vat_role IN ('Base', 'Output', 'Input'));
+ALTER TABLE AccountChanges ADD CHECK ((vat_code_id IS NULL) = (vat_role IS NULL));
+-- The VAT lines refer to the account change they were created for, so they
+-- can be recreated when it is updated and go away when it is deleted
+ALTER TABLE AccountChanges ADD COLUMN vat_base_id BIGINT
+  REFERENCES AccountChanges(id) ON DELETE CASCADE;
+ALTER TABLE AccountChanges ADD CHECK (
+  (vat_base_id IS NOT NULL) = COALESCE(vat_role IN ('Output', 'Input'), false)
+);
+
+COMMIT; -- Apply the transaction
@@ -0,0 +1,31 @@
+BEGIN; -- Work in a transaction
+
+-- Lines that seem to be imported already can be forced in on review, so the
+-- FITID only refuses lines that weren't forced
+ALTER TABLE ImportedAccountChanges ADD COLUMN forced BOOLEAN NOT NULL DEFAULT false;
+DROP INDEX ImportedAccountChangesFitid;
+CREATE UNIQUE INDEX ImportedAccountChangesFitid
+  ON ImportedAccountChanges(account_id, (other_data->>'fitid'))
+  WHERE other_data ? 'fitid' AND NOT forced
+;
+
+-- Uploads waiting for the user to choose which lines to import, kept here
+-- rather than sent through the review form so what is imported is what was
+-- parsed from the file
+CREATE TABLE PendingImports (
+  bookkeeping_id BIGINT NOT NULL,
+  -- SHA-256 of the file, hex encoded
+  file_hash VARCHAR(64) NOT NULL,
+  file_name VARCHAR(256) NOT NULL,
+  uploader_id BIGINT NOT NULL,
+  uploaded_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  -- The lines parsed from the file per account, and which seem imported
+  imports JSONB NOT NULL,
+  duplicates JSONB NOT NULL,
+
+  PRIMARY KEY (bookkeeping_id, file_hash),
+  FOREIGN KEY (bookkeeping_id) REFERENCES Bookkeepings(id),
+  FOREIGN KEY (uploader_id) REFERENCES Users(id)
+);
+
+COMMIT; -- Apply the transaction
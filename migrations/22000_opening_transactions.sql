@@ -0,0 +1,15 @@
+BEGIN; -- Work in a transaction
+
+-- Opening balances carried over from the year before (by closing the year or
+-- from a SIE import), which give the balances a grouping starts from rather
+-- than change them
+ALTER TABLE Transactions ADD COLUMN opening BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Both have so far named the transaction the same, so mark those already made
+-- Marking doesn't change what the locked periods report, so the lock is
+-- lifted for it
+ALTER TABLE Transactions DISABLE TRIGGER TransactionsPeriodLock;
+UPDATE Transactions SET opening = TRUE WHERE name = 'Opening balances';
+ALTER TABLE Transactions ENABLE TRIGGER TransactionsPeriodLock;
+
+COMMIT; -- Apply the transaction
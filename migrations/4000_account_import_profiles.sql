@@ -0,0 +1,23 @@
+BEGIN; -- Work in a transaction
+
+-- How to read the CSV files a bank exports for an account
+-- Columns are numbered from 1, as they would be in a spreadsheet
+CREATE TABLE AccountImportProfiles (
+  account_id BIGINT PRIMARY KEY,
+  delimiter VARCHAR(1) NOT NULL DEFAULT ',',
+  decimal_separator VARCHAR(1) NOT NULL DEFAULT '.',
+  -- In the format description syntax of the rust time crate
+  date_format VARCHAR(64) NOT NULL DEFAULT '[year]-[month]-[day]',
+  -- Rows before the data, the last of which is used as header if any
+  skip_rows INTEGER NOT NULL DEFAULT 1,
+  date_column INTEGER NOT NULL,
+  amount_column INTEGER NOT NULL,
+  -- Columns to keep in ImportedAccountChanges.other_data
+  other_data_columns INTEGER[] NOT NULL DEFAULT '{}',
+
+  CHECK (skip_rows >= 0 AND date_column > 0 AND amount_column > 0),
+
+  FOREIGN KEY (account_id) REFERENCES Accounts(id)
+);
+
+COMMIT; -- Apply the transaction
@@ -0,0 +1,10 @@
+BEGIN; -- Work in a transaction
+
+-- OFX statements give every line a FITID that is stable between downloads,
+-- which we use to refuse importing the same line twice
+CREATE UNIQUE INDEX ImportedAccountChangesFitid
+  ON ImportedAccountChanges(account_id, (other_data->>'fitid'))
+  WHERE other_data ? 'fitid'
+;
+
+COMMIT; -- Apply the transaction
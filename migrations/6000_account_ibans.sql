@@ -0,0 +1,8 @@
+BEGIN; -- Work in a transaction
+
+-- Bank statements identify the account by IBAN, so we store it on the account
+-- to be able to import statements into the right account automatically
+ALTER TABLE Accounts ADD COLUMN iban VARCHAR(34);
+ALTER TABLE Accounts ADD UNIQUE (bookkeeping_id, iban);
+
+COMMIT; -- Apply the transaction
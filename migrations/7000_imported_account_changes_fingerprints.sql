@@ -0,0 +1,29 @@
+BEGIN; -- Work in a transaction
+
+-- Identifies an imported line well enough to tell when it is imported again:
+-- The day and amount, followed by the values of the given other_data fields
+-- Defined here so that stored and incoming lines are fingerprinted the same
+CREATE FUNCTION import_fingerprint(
+  day DATE,
+  amount NUMERIC,
+  other_data JSONB,
+  fields TEXT[]
+) RETURNS TEXT LANGUAGE SQL STABLE AS $$
+  SELECT to_char(day, 'YYYY-MM-DD') || ' ' || ROUND(amount, 2)::TEXT || COALESCE((
+    SELECT string_agg(E'\t' || COALESCE(other_data->>field, ''), '' ORDER BY n)
+      FROM UNNEST(fields) WITH ORDINALITY AS f(field, n)
+  ), '')
+$$;
+
+-- Which other_data fields to include in the fingerprints of an account
+ALTER TABLE AccountImportProfiles
+  ADD COLUMN fingerprint_fields TEXT[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE ImportedAccountChanges ADD COLUMN fingerprint TEXT;
+UPDATE ImportedAccountChanges
+  SET fingerprint = import_fingerprint(day, amount, other_data, '{}');
+ALTER TABLE ImportedAccountChanges ALTER COLUMN fingerprint SET NOT NULL;
+CREATE INDEX ImportedAccountChangesFingerprint
+  ON ImportedAccountChanges(account_id, fingerprint);
+
+COMMIT; -- Apply the transaction
@@ -0,0 +1,9 @@
+BEGIN; -- Work in a transaction
+
+-- Imported account changes are migrated into transactions by creating an
+-- account change for them. The imported row is kept, marked by the account
+-- change it became, so that later imports still recognise it as imported.
+ALTER TABLE ImportedAccountChanges
+  ADD COLUMN account_change_id BIGINT UNIQUE REFERENCES AccountChanges(id);
+
+COMMIT; -- Apply the transaction
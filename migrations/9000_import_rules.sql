@@ -0,0 +1,32 @@
+BEGIN; -- Work in a transaction
+
+-- Rules proposing how to migrate imported account changes into transactions
+-- Tried in order of position, the first matching rule is used
+CREATE TABLE ImportRules (
+  id BIGSERIAL PRIMARY KEY,
+  bookkeeping_id BIGINT NOT NULL,
+  position INTEGER NOT NULL,
+  -- The other_data field to match, or all of them if empty
+  field VARCHAR(64) NOT NULL DEFAULT '',
+  -- A case insensitive substring, or a regular expression
+  pattern VARCHAR(256) NOT NULL,
+  is_regex BOOLEAN NOT NULL DEFAULT FALSE,
+  -- Optional bounds on the size of the amount, regardless of sign
+  min_amount NUMERIC(32,2),
+  max_amount NUMERIC(32,2),
+  -- 1 to only match deposits, -1 to only match withdrawals
+  sign SMALLINT,
+  -- What to propose for matching lines
+  account_id BIGINT NOT NULL,
+  transaction_name VARCHAR(64) NOT NULL,
+  grouping_id BIGINT NOT NULL,
+
+  UNIQUE (bookkeeping_id, position) DEFERRABLE INITIALLY DEFERRED,
+  CHECK (sign IN (-1, 1)),
+
+  FOREIGN KEY (bookkeeping_id) REFERENCES Bookkeepings(id),
+  FOREIGN KEY (account_id) REFERENCES Accounts(id),
+  FOREIGN KEY (grouping_id) REFERENCES Groupings(id)
+);
+
+COMMIT; -- Apply the transaction
@@ -27,6 +27,7 @@ type OIDCRequestError = openidconnect::RequestTokenError<
 use crate::traits::{
   Request,
   Response,
+  full,
 };
 
 // Error representation for internal errors
@@ -82,10 +83,44 @@ pub enum ClientError {
   InvalidContentType(String),
   InvalidJson(String),
   InvalidUrlEncoding(String),
+  InvalidMultipart(String),
   InvalidIndexPath(String),
+  InvalidImportFile(String), // Uploaded file could not be parsed for import
+  InvalidImportProfile(String), // Import profile can't be used to parse files
+  InvalidImportRule(String), // Import rule pattern or bounds are invalid
 
   // Request processing errors
   AlreadyExists(String), // For example uniqueness error on name column
+  StillReferenced(String), // Can't delete what other rows still refer to
+  AccountNotFound(i64), // Given account doesn't exist in this bookkeeping
+  GroupingNotFound(i64), // Given grouping doesn't exist in this bookkeeping
+  BookkeepingNotFound(i64), // Or the user has no access to it
+  ChartTemplateNotFound(String), // No built-in chart of accounts by that id
+  ImportedAccountChangeNotFound(i64), // Or it isn't in this bookkeeping
+  AlreadyMigrated(i64), // Imported account change is already in a transaction
+  BatchPartlyMigrated(i64), // Import batch can't be undone, lines are in transactions
+  PendingImportNotFound(String), // Upload to review was already imported or forgotten
+  NothingChosen, // Form requires choosing at least one item
+  TransactionUnbalanced(i64), // Only transactions summing to zero can be posted
+  TransactionEmpty(i64), // Transactions without account changes can't be posted
+  PostedUnbalanced(String), // Change would leave a posted transaction unbalanced
+  TransactionPosted(i64), // Posted transactions must be unposted to be changed
+  PeriodLocked(String), // Change is dated in a locked period or grouping
+  InvalidYearEnd(String), // Year can't be closed as chosen
+  InvalidCurrency(String), // Malformed currency code, or currency can't change
+  InvalidParentAccount(String), // Parent must have the same type and not be below the account
+  InvalidAccountNumber(String), // Account numbers are non-negative integers
+  InvalidAccountSubtype(String), // Subtype must be of the bookkeeping and the account's type
+  InvalidSchedule(String), // Recurring transaction schedule can't be used
+  InvalidVatCode(String), // VAT code lacks the accounts its VAT lines need
+  VatCodeNotFound(i64), // Given VAT code doesn't exist in this bookkeeping
+  GeneratedVatLine(i64), // VAT lines change with the account change they were created for
+  InvalidOrganisationNumber(String), // Swedish organisation numbers have 10 or 12 digits and a check digit
+  InvalidVatPeriod(String), // VAT returns are for a whole month, calendar quarter or year
+  AccountNumberNotFound(i32), // No account in this bookkeeping has the given number
+  ExchangeRateMissing(String), // No rate to convert an amount into the base currency
+  UnknownIban(String), // No account in this bookkeeping has the given IBAN
+  IbanMismatch(String), // Statement's IBAN is of another account than the chosen one
 
   // Non-parsing user-caused errors (but probably not intentional)
   UnknownOIDCProcess, // Post-login OIDC handler did not find the OIDC login in DB
@@ -96,11 +131,10 @@ pub enum ClientError {
 }
 impl Reply for ClientError {
   fn into_response(self) -> Response {
-    let mut re = Response::new(
+    let mut re = Response::new(full(
       serde_json::to_string(&self)
         .unwrap() // Only errors if self cannot be represented as json
-        .into()
-    );
+    ));
     *re.status_mut() = match self {
       Self::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
 
@@ -127,6 +161,14 @@ pub enum Error {
   InternalError(InternalError),
   ClientError(ClientError),
 }
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      Self::InternalError(e) => write!(f, "{}", e),
+      Self::ClientError(e) => write!(f, "{:?}", e),
+    }
+  }
+}
 // Utility constructors
 // We use .into() to convert ClientError into Error
 impl Error {
@@ -194,6 +236,57 @@ impl Error {
       parsed, expected
     )).into()
   }
+  pub fn multipart_boundary_missing() -> Self {
+    ClientError::InvalidMultipart(
+      "No boundary given in Content-Type".to_string()
+    ).into()
+  }
+  pub fn invalid_multipart(reason: &str) -> Self {
+    ClientError::InvalidMultipart(reason.to_string()).into()
+  }
+  pub fn multipart_field_missing(name: &str) -> Self {
+    ClientError::InvalidMultipart(format!(
+      "Missing field {}",
+      name,
+    )).into()
+  }
+  pub fn invalid_import_profile(reason: &str) -> Self {
+    ClientError::InvalidImportProfile(reason.to_string()).into()
+  }
+  pub fn invalid_import_rule(reason: &str) -> Self {
+    ClientError::InvalidImportRule(reason.to_string()).into()
+  }
+  // Foreign key errors only name the referring table, so we describe it
+  pub fn still_referenced(what: &str, table: Option<&str>) -> Self {
+    let referrers = match table {
+      Some("accountchanges") => "account changes",
+      Some("accounts") => "sub-accounts",
+      Some("transactions") => "transactions",
+      Some("importedaccountchanges") => "imported account changes",
+      Some("importrules") => "import rules",
+      Some("statementcheckpoints") => "statement checkpoints",
+      Some("recurringtransactions") => "recurring transactions",
+      Some("recurringtransactionlines") => "recurring transactions",
+      Some("vatcodes") => "VAT codes",
+      _ => "other rows",
+    };
+    ClientError::StillReferenced(format!(
+      "{} is still used by {}, which must be deleted first.",
+      what, referrers,
+    )).into()
+  }
+  pub fn exchange_rate_missing(currency: &str, day: time::Date) -> Self {
+    ClientError::ExchangeRateMissing(format!(
+      "No exchange rate for {} on or before {}",
+      currency, day,
+    )).into()
+  }
+  pub fn invalid_import_file(line: usize, reason: &str) -> Self {
+    ClientError::InvalidImportFile(format!(
+      "Line {}: {}",
+      line, reason,
+    )).into()
+  }
 }
 
 // Implementing Reply on this error type enables rust to convert any error into
@@ -219,6 +312,11 @@ impl From<ClientError> for Error {
   }
 }
 
+impl From<std::convert::Infallible> for Error {
+  fn from(e: std::convert::Infallible) -> Self {
+    match e {}
+  }
+}
 impl From<JsonError> for Error {
   fn from(e: JsonError) -> Self {
     ClientError::InvalidJson(format!("{}", e)).into()
@@ -234,9 +332,21 @@ impl From<ParseIntError> for Error {
     ClientError::InvalidIndexPath(format!("{}", e)).into()
   }
 }
+// SQLSTATE raised by the database when changing a locked period
+const PERIOD_LOCKED_SQLSTATE: &str = "BKLCK";
+// SQLSTATE raised by the database when a posted transaction wouldn't balance
+const POSTED_UNBALANCED_SQLSTATE: &str = "BKUNB";
 impl From<SqlxError> for Error {
   fn from(e: SqlxError) -> Self {
-    InternalError::Db(e).into()
+    match e {
+      SqlxError::Database(ref dbe) if dbe.code().as_deref() == Some(PERIOD_LOCKED_SQLSTATE) => {
+        ClientError::PeriodLocked(dbe.message().to_owned()).into()
+      },
+      SqlxError::Database(ref dbe) if dbe.code().as_deref() == Some(POSTED_UNBALANCED_SQLSTATE) => {
+        ClientError::PostedUnbalanced(dbe.message().to_owned()).into()
+      },
+      e => InternalError::Db(e).into(),
+    }
   }
 }
 impl From<ConnectionError> for Error {
@@ -16,6 +16,9 @@ use traits::*;
 mod error;
 use error::*;
 
+// Parsing of the SIE bookkeeping exchange format
+mod sie;
+
 // Define how to handle the actual requests
 mod routes;
 
@@ -38,6 +41,7 @@ async fn run_server(
 
   // Create whatever background tasks are needed
   tokio::task::spawn(database_cleaner(state));
+  tokio::task::spawn(recurring_generator(state));
 
   // Loop forever, spawning a task for every request we get
   loop {
@@ -90,6 +94,29 @@ async fn database_cleaner(
       Ok(r) => { println!("Cleaned {} old login processes", r.rows_affected()); },
       Err(e) => { eprintln!("Error when cleaning outdated login procedures\n  error: {e}"); },
     }
+    // Imports left waiting for review for a day are taken to be abandoned
+    match sqlx::query("DELETE FROM PendingImports WHERE uploaded_at < NOW() - INTERVAL '1 day'")
+      .execute(&state.db)
+      .await
+    {
+      Ok(r) => { println!("Cleaned {} abandoned imports", r.rows_affected()); },
+      Err(e) => { eprintln!("Error when cleaning abandoned imports\n  error: {e}"); },
+    }
+  }
+}
+
+// Creates the due recurring transactions every hour, starting right away so
+// those missed while the server was down are caught up on
+async fn recurring_generator(
+  state: &'static State,
+) {
+  let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+  loop {
+    interval.tick().await;
+    match routes::generate_recurring_transactions(state).await {
+      Ok(n) => { println!("Created {} recurring transactions.", n); },
+      Err(e) => { eprintln!("Error when creating recurring transactions\n  error: {e}"); },
+    }
   }
 }
 
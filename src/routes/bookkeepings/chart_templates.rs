@@ -0,0 +1,239 @@
+use super::*;
+
+use std::collections::HashMap;
+
+// Built-in charts of accounts to start new bookkeepings from
+
+pub struct TemplateAccount {
+  number: Option<i32>,
+  name: &'static str,
+  r#type: &'static str,
+  // Name of an account earlier in the template
+  parent: Option<&'static str>,
+}
+pub struct ChartTemplate {
+  pub id: &'static str,
+  pub name: &'static str,
+  accounts: &'static [TemplateAccount],
+}
+
+const fn account(name: &'static str, r#type: &'static str) -> TemplateAccount {
+  TemplateAccount{ number: None, name, r#type, parent: None }
+}
+const fn numbered(number: i32, name: &'static str, r#type: &'static str) -> TemplateAccount {
+  TemplateAccount{ number: Some(number), name, r#type, parent: None }
+}
+const fn sub_account(
+  name: &'static str,
+  r#type: &'static str,
+  parent: &'static str,
+) -> TemplateAccount {
+  TemplateAccount{ number: None, name, r#type, parent: Some(parent) }
+}
+
+const BAS_SOLE_TRADER: &[TemplateAccount] = &[
+  numbered(1220, "Inventarier och verktyg", "Asset"),
+  numbered(1510, "Kundfordringar", "Asset"),
+  numbered(1630, "Skattekonto", "Asset"),
+  numbered(1910, "Kassa", "Asset"),
+  numbered(1930, "Företagskonto", "Asset"),
+  numbered(2010, "Eget kapital", "Equity"),
+  numbered(2013, "Övriga egna uttag", "Equity"),
+  numbered(2018, "Övriga egna insättningar", "Equity"),
+  numbered(2019, "Årets resultat", "Equity"),
+  numbered(2440, "Leverantörsskulder", "Debt"),
+  numbered(2611, "Utgående moms 25 %", "Debt"),
+  numbered(2621, "Utgående moms 12 %", "Debt"),
+  numbered(2631, "Utgående moms 6 %", "Debt"),
+  numbered(2641, "Debiterad ingående moms", "Debt"),
+  numbered(2650, "Redovisningskonto för moms", "Debt"),
+  numbered(3001, "Försäljning inom Sverige, 25 % moms", "Income"),
+  numbered(3002, "Försäljning inom Sverige, 12 % moms", "Income"),
+  numbered(3003, "Försäljning inom Sverige, 6 % moms", "Income"),
+  numbered(3004, "Försäljning inom Sverige, momsfri", "Income"),
+  numbered(3740, "Öres- och kronutjämning", "Income"),
+  numbered(8310, "Ränteintäkter från omsättningstillgångar", "Income"),
+  numbered(4010, "Inköp material och varor", "Expense"),
+  numbered(5010, "Lokalhyra", "Expense"),
+  numbered(5410, "Förbrukningsinventarier", "Expense"),
+  numbered(5460, "Förbrukningsmaterial", "Expense"),
+  numbered(5611, "Drivmedel för personbilar", "Expense"),
+  numbered(5800, "Resekostnader", "Expense"),
+  numbered(5910, "Annonsering", "Expense"),
+  numbered(6071, "Representation, avdragsgill", "Expense"),
+  numbered(6110, "Kontorsmateriel", "Expense"),
+  numbered(6212, "Mobiltelefon", "Expense"),
+  numbered(6230, "Datakommunikation", "Expense"),
+  numbered(6310, "Företagsförsäkringar", "Expense"),
+  numbered(6530, "Redovisningstjänster", "Expense"),
+  numbered(6540, "IT-tjänster", "Expense"),
+  numbered(6570, "Bankkostnader", "Expense"),
+  numbered(6991, "Övriga externa kostnader, avdragsgilla", "Expense"),
+  numbered(7832, "Avskrivningar på inventarier och verktyg", "Expense"),
+  numbered(8410, "Räntekostnader för skulder till kreditinstitut", "Expense"),
+];
+
+const HOUSEHOLD: &[TemplateAccount] = &[
+  account("Bank account", "Asset"),
+  account("Savings account", "Asset"),
+  account("Cash", "Asset"),
+  account("Net worth", "Equity"),
+  account("Credit card", "Debt"),
+  account("Loans", "Debt"),
+  sub_account("Mortgage", "Debt", "Loans"),
+  sub_account("Student loan", "Debt", "Loans"),
+  account("Salary", "Income"),
+  account("Benefits", "Income"),
+  account("Other income", "Income"),
+  account("Housing", "Expense"),
+  sub_account("Rent", "Expense", "Housing"),
+  sub_account("Electricity", "Expense", "Housing"),
+  sub_account("Home insurance", "Expense", "Housing"),
+  account("Food", "Expense"),
+  sub_account("Groceries", "Expense", "Food"),
+  sub_account("Restaurants", "Expense", "Food"),
+  account("Transport", "Expense"),
+  sub_account("Public transport", "Expense", "Transport"),
+  sub_account("Fuel", "Expense", "Transport"),
+  account("Health", "Expense"),
+  account("Clothing", "Expense"),
+  account("Leisure", "Expense"),
+  sub_account("Subscriptions", "Expense", "Leisure"),
+  sub_account("Hobbies", "Expense", "Leisure"),
+  sub_account("Travel", "Expense", "Leisure"),
+  account("Other expenses", "Expense"),
+];
+
+pub const TEMPLATES: &[ChartTemplate] = &[
+  ChartTemplate{
+    id: "bas-sole-trader",
+    name: "Simplified BAS for sole traders",
+    accounts: BAS_SOLE_TRADER,
+  },
+  ChartTemplate{
+    id: "household",
+    name: "Household budget",
+    accounts: HOUSEHOLD,
+  },
+];
+
+// Create the accounts of the chosen chart in the new bookkeeping, which is
+// either the id of a template or "copy:" and the id of a bookkeeping to copy
+// the accounts of
+pub async fn create_accounts(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  session: &SessionData,
+  bookkeeping_id: i64,
+  chart: &str,
+) -> Result<(), Error> {
+  if let Some(source) = chart.strip_prefix("copy:") {
+    return copy_accounts(transaction, session, bookkeeping_id, source.parse()?).await;
+  }
+  let template = TEMPLATES.iter()
+    .find(|t| t.id == chart)
+    .ok_or(ClientError::ChartTemplateNotFound(chart.to_owned()))?
+  ;
+  let mut ids = HashMap::new();
+  for account in template.accounts {
+    let parent_id: Option<i64> = account.parent.and_then(|p| ids.get(p).copied());
+    let id = sqlx::query!(
+      "
+INSERT INTO Accounts(bookkeeping_id, number, name, type, parent_id)
+  VALUES($1, $2, $3, $4, $5)
+  RETURNING id
+      ",
+      bookkeeping_id,
+      account.number,
+      account.name,
+      account.r#type,
+      parent_id,
+    )
+      .fetch_one(&mut **transaction)
+      .await?
+      .id
+    ;
+    ids.insert(account.name, id);
+  }
+  Ok(())
+}
+// Copy the accounts of another bookkeeping the user has access to, with their
+// numbers, currencies, subtypes and structure
+async fn copy_accounts(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  session: &SessionData,
+  bookkeeping_id: i64,
+  source_id: i64,
+) -> Result<(), Error> {
+  let base_currency = sqlx::query!(
+    "
+SELECT Bookkeepings.base_currency
+  FROM Bookkeepings
+  LEFT JOIN UsersBookkeepingsAccess ON Bookkeepings.id = bookkeeping_id
+WHERE (Bookkeepings.owner_id = $1 OR UsersBookkeepingsAccess.user_id = $1)
+  AND Bookkeepings.id = $2
+LIMIT 1
+    ",
+    session.user_id,
+    source_id,
+  )
+    .fetch_optional(&mut **transaction)
+    .await?
+    .ok_or(ClientError::BookkeepingNotFound(source_id))?
+    .base_currency
+  ;
+  // The currencies of the accounts are relative to the base currency
+  sqlx::query!(
+    "UPDATE Bookkeepings SET base_currency = $2 WHERE id = $1",
+    bookkeeping_id,
+    base_currency,
+  )
+    .execute(&mut **transaction)
+    .await?
+  ;
+  sqlx::query!(
+    "
+INSERT INTO AccountSubtypes(bookkeeping_id, name, type)
+  SELECT $1, name, type FROM AccountSubtypes WHERE bookkeeping_id = $2
+    ",
+    bookkeeping_id,
+    source_id,
+  )
+    .execute(&mut **transaction)
+    .await?
+  ;
+  // Subtype names are also unique within a bookkeeping
+  sqlx::query!(
+    "
+INSERT INTO Accounts(bookkeeping_id, number, name, type, currency, subtype_id)
+  SELECT $1, Accounts.number, Accounts.name, Accounts.type, Accounts.currency, NewSubtype.id
+    FROM Accounts
+    LEFT JOIN AccountSubtypes AS Subtype ON Subtype.id = Accounts.subtype_id
+    LEFT JOIN AccountSubtypes AS NewSubtype
+      ON NewSubtype.bookkeeping_id = $1 AND NewSubtype.name = Subtype.name
+  WHERE Accounts.bookkeeping_id = $2
+    ",
+    bookkeeping_id,
+    source_id,
+  )
+    .execute(&mut **transaction)
+    .await?
+  ;
+  // Account names are unique within a bookkeeping, so parents are found by name
+  sqlx::query!(
+    "
+UPDATE Accounts SET parent_id = NewParent.id
+  FROM Accounts AS Source
+  INNER JOIN Accounts AS SourceParent ON SourceParent.id = Source.parent_id
+  INNER JOIN Accounts AS NewParent
+    ON NewParent.bookkeeping_id = $1 AND NewParent.name = SourceParent.name
+WHERE Accounts.bookkeeping_id = $1
+  AND Source.bookkeeping_id = $2 AND Source.name = Accounts.name
+    ",
+    bookkeeping_id,
+    source_id,
+  )
+    .execute(&mut **transaction)
+    .await?
+  ;
+  Ok(())
+}
@@ -0,0 +1,163 @@
+use super::*;
+
+// The base account types are shared by all bookkeepings, which can classify
+// their accounts further with subtypes of their own
+
+#[derive(Debug)]
+struct BaseType {
+  name: String,
+  normal_balance: String,
+}
+#[derive(Debug)]
+struct Subtype {
+  id: i64,
+  name: String,
+  r#type: String,
+  accounts: i64,
+}
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/account_types/index.html")]
+struct Index {
+  bookkeeping_name: String,
+  base_types: Vec<BaseType>,
+  subtypes: Vec<Subtype>,
+}
+
+async fn index(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let base_types = sqlx::query_as!(BaseType,
+    "SELECT name, normal_balance FROM AccountTypes ORDER BY name",
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let subtypes = sqlx::query_as!(Subtype,
+    "
+SELECT AccountSubtypes.id, AccountSubtypes.name, AccountSubtypes.type,
+    COUNT(Accounts.id) AS \"accounts!\"
+  FROM AccountSubtypes
+  LEFT JOIN Accounts ON Accounts.subtype_id = AccountSubtypes.id
+WHERE AccountSubtypes.bookkeeping_id = $1
+GROUP BY AccountSubtypes.id
+ORDER BY AccountSubtypes.type, AccountSubtypes.name
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  html(Index{
+    bookkeeping_name: bookkeeping.name,
+    base_types,
+    subtypes,
+  }.render()?)
+}
+
+#[derive(Debug, Deserialize)]
+struct NewSubtype {
+  name: String,
+  r#type: String,
+}
+async fn subtypes_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let new_subtype: NewSubtype = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "INSERT INTO AccountSubtypes(bookkeeping_id, name, type) VALUES($1, $2, $3)",
+    bookkeeping.id,
+    new_subtype.name,
+    new_subtype.r#type,
+  )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| -> Error { match e {
+      sqlx::Error::Database(ref dbe) if dbe.is_foreign_key_violation() => {
+        ClientError::InvalidAccountSubtype(format!(
+          "{} is not an account type",
+          new_subtype.r#type,
+        )).into()
+      },
+      sqlx::Error::Database(ref dbe) if dbe.is_unique_violation() => {
+        ClientError::AlreadyExists(format!(
+          "A subtype by name {} already exists in this bookkeeping.",
+          new_subtype.name,
+        )).into()
+      },
+      e => e.into(),
+    }})
+    ?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteSubtype {
+  id: i64,
+}
+async fn delete_subtype_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let form: DeleteSubtype = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "DELETE FROM AccountSubtypes WHERE id = $1 AND bookkeeping_id = $2",
+    form.id,
+    bookkeeping.id,
+  )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| -> Error { match e {
+      // Only accounts refer to subtypes
+      sqlx::Error::Database(ref dbe) if dbe.is_foreign_key_violation() => {
+        ClientError::StillReferenced(
+          "The subtype is still used by accounts, which must be given another first.".to_owned()
+        ).into()
+      },
+      e => e.into(),
+    }})
+    ?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    Some("") => {
+      verify_method_path_end(&path_vec, &req, &Method::GET)?;
+      index(state, bookkeeping).await
+    },
+    Some("subtypes") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      subtypes_post(state, req, session, bookkeeping).await
+    },
+    Some("delete-subtype") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      delete_subtype_post(state, req, session, bookkeeping).await
+    },
+    _ => Err(Error::path_not_found(&req)),
+  }
+}
@@ -0,0 +1,233 @@
+use super::*;
+
+mod reconciliation;
+
+#[derive(Debug)]
+pub struct Account {
+  id: i64,
+  name: String,
+  number: Option<i32>,
+  r#type: String,
+  subtype_id: Option<i64>,
+  iban: Option<String>,
+  currency: Option<String>,
+  parent_id: Option<i64>,
+}
+#[derive(Debug)]
+struct AccountType {
+  name: String,
+}
+// The accounts that can be chosen as parent
+#[derive(Debug)]
+struct ParentChoice {
+  id: i64,
+  name: String,
+  r#type: String,
+}
+#[derive(Debug)]
+struct SubtypeChoice {
+  id: i64,
+  name: String,
+  r#type: String,
+}
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/accounts/id/index.html")]
+struct Index {
+  bookkeeping_name: String,
+  base_currency: String,
+  account: Account,
+  account_types: Vec<AccountType>,
+  parents: Vec<ParentChoice>,
+  subtypes: Vec<SubtypeChoice>,
+}
+impl Index {
+  fn is_parent(&self, id: &i64) -> bool {
+    self.account.parent_id == Some(*id)
+  }
+  fn is_subtype(&self, id: &i64) -> bool {
+    self.account.subtype_id == Some(*id)
+  }
+}
+
+async fn index(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+  account: Account,
+) -> Result<Response, Error> {
+  let account_types = sqlx::query_as!(AccountType,
+    "SELECT name FROM AccountTypes",
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let parents = sqlx::query_as!(ParentChoice,
+    "
+SELECT id, concat_ws(' ', number, name) AS \"name!\", type
+  FROM Accounts
+WHERE bookkeeping_id = $1 AND id != $2
+ORDER BY number, type, name
+    ",
+    bookkeeping.id,
+    account.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let subtypes = sqlx::query_as!(SubtypeChoice,
+    "SELECT id, name, type FROM AccountSubtypes WHERE bookkeeping_id = $1 ORDER BY type, name",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  html(Index{
+    bookkeeping_name: bookkeeping.name,
+    base_currency: bookkeeping.base_currency,
+    account,
+    account_types,
+    parents,
+    subtypes,
+  }.render()?)
+}
+
+async fn update_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  account: Account,
+) -> Result<Response, Error> {
+  // Takes the same fields as when creating the account
+  let update: NewAccount = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let parent_id = update.parent_id()?;
+  let subtype_id = update.subtype_id()?;
+  let number = parse_account_number(&update.number)?;
+  let iban = normalize_iban(update.iban);
+  let currency = currencies::normalize_currency(update.currency, &bookkeeping.base_currency)?;
+  let mut transaction = begin_as(state, &session).await?;
+  if let Some(parent_id) = parent_id {
+    verify_parent(&mut transaction, bookkeeping.id, Some(account.id), parent_id, &update.r#type).await?;
+  }
+  if let Some(subtype_id) = subtype_id {
+    verify_subtype(&mut transaction, bookkeeping.id, subtype_id, &update.r#type).await?;
+  }
+  // The amounts booked are in the account's currency, so it can't change after
+  if currency != account.currency {
+    let booked = sqlx::query!(
+      "SELECT EXISTS(SELECT 1 FROM AccountChanges WHERE account_id = $1) AS \"booked!\"",
+      account.id,
+    )
+      .fetch_one(&mut *transaction)
+      .await?
+      .booked
+    ;
+    if booked {
+      return Err(ClientError::InvalidCurrency(format!(
+        "The account {} has account changes, so its currency can't change",
+        account.name,
+      )).into());
+    }
+  }
+  sqlx::query!(
+    "
+UPDATE Accounts
+  SET name = $2, number = $3, type = $4, subtype_id = $5, iban = $6, currency = $7,
+    parent_id = $8
+WHERE id = $1
+    ",
+    account.id,
+    update.name,
+    number,
+    update.r#type,
+    subtype_id,
+    iban,
+    currency,
+    parent_id,
+  )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| map_account_conflict(e, &update.name, number, iban.as_deref()))
+    ?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+
+async fn delete_post(
+  state: &'static State,
+  session: SessionData,
+  account: Account,
+) -> Result<Response, Error> {
+  let mut transaction = begin_as(state, &session).await?;
+  // The import profile only describes the account's files, so it goes with it
+  sqlx::query!(
+    "DELETE FROM AccountImportProfiles WHERE account_id = $1",
+    account.id,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  sqlx::query!(
+    "DELETE FROM Accounts WHERE id = $1",
+    account.id,
+  )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| -> Error { match e {
+      sqlx::Error::Database(ref dbe) if dbe.is_foreign_key_violation() => {
+        Error::still_referenced(&format!("The account {}", account.name), dbe.table())
+      },
+      e => e.into(),
+    }})
+    ?
+  ;
+  transaction.commit().await?;
+  see_other("../../")
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  account_id: i64,
+) -> Result<Response, Error> {
+  // Get the account, verifying that it belongs to this bookkeeping
+  let account = sqlx::query_as!(Account,
+    "SELECT id, name, number, type, subtype_id, iban, currency, parent_id FROM Accounts WHERE id = $1 AND bookkeeping_id = $2",
+    account_id,
+    bookkeeping.id,
+  )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::path_not_found(&req))?
+  ;
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    Some("") => {
+      verify_method_path_end(&path_vec, &req, &Method::GET)?;
+      index(state, bookkeeping, account).await
+    },
+    Some("update") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      update_post(state, req, session, bookkeeping, account).await
+    },
+    Some("delete") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      delete_post(state, session, account).await
+    },
+    Some("reconciliation") => reconciliation::route(
+      state,
+      req,
+      path_vec,
+      session,
+      bookkeeping,
+      account,
+    ).await,
+    _ => Err(Error::path_not_found(&req)),
+  }
+}
@@ -0,0 +1,174 @@
+use super::*;
+
+#[derive(Debug)]
+struct Checkpoint {
+  day: Date,
+  balance: Decimal,
+  // The balance of the account in the books at the end of the day, in the
+  // account's currency like the statement
+  ledger_balance: Decimal,
+}
+// An imported line that hasn't been migrated into a transaction
+#[derive(Debug)]
+struct BankLine {
+  day: Date,
+  amount: Decimal,
+  other_data: Option<serde_json::Value>,
+}
+// An account change that no imported line has been migrated into
+#[derive(Debug)]
+struct BookLine {
+  day: Date,
+  amount: Decimal,
+  message: String,
+}
+// A checkpoint with the lines since the previous that may explain a difference
+#[derive(Debug)]
+struct CheckpointReport {
+  checkpoint: Checkpoint,
+  difference: Decimal,
+  bank_lines: Vec<BankLine>,
+  book_lines: Vec<BookLine>,
+}
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/accounts/id/reconciliation/index.html")]
+struct Index {
+  bookkeeping_name: String,
+  account_name: String,
+  reports: Vec<CheckpointReport>,
+}
+
+async fn index(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+  account: Account,
+) -> Result<Response, Error> {
+  let checkpoints = sqlx::query_as!(Checkpoint,
+    "
+SELECT StatementCheckpoints.day, StatementCheckpoints.balance,
+    COALESCE((
+      SELECT SUM(COALESCE(AccountChanges.original_amount, AccountChanges.amount))
+        FROM AccountChanges
+        INNER JOIN Transactions ON Transactions.id = AccountChanges.transaction_id
+      WHERE AccountChanges.account_id = StatementCheckpoints.account_id
+        AND AccountChanges.day <= StatementCheckpoints.day
+        AND Transactions.posted
+    ), 0) AS \"ledger_balance!\"
+  FROM StatementCheckpoints
+WHERE StatementCheckpoints.account_id = $1
+ORDER BY StatementCheckpoints.day
+    ",
+    account.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let bank_lines = sqlx::query_as!(BankLine,
+    "
+SELECT day, amount, other_data
+  FROM ImportedAccountChanges
+WHERE account_id = $1 AND account_change_id IS NULL
+ORDER BY day, id
+    ",
+    account.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let book_lines = sqlx::query_as!(BookLine,
+    "
+SELECT AccountChanges.day,
+    COALESCE(AccountChanges.original_amount, AccountChanges.amount) AS \"amount!\",
+    AccountChanges.message
+  FROM AccountChanges
+  INNER JOIN Transactions ON Transactions.id = AccountChanges.transaction_id
+WHERE AccountChanges.account_id = $1 AND Transactions.posted
+  AND NOT EXISTS (
+    SELECT 1 FROM ImportedAccountChanges
+    WHERE ImportedAccountChanges.account_change_id = AccountChanges.id
+  )
+ORDER BY AccountChanges.day, AccountChanges.id
+    ",
+    account.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+
+  // Give each checkpoint the lines since the one before it
+  let mut bank_lines = bank_lines.into_iter().peekable();
+  let mut book_lines = book_lines.into_iter().peekable();
+  let mut reports = Vec::new();
+  for checkpoint in checkpoints {
+    let mut report = CheckpointReport{
+      difference: checkpoint.balance - checkpoint.ledger_balance,
+      bank_lines: Vec::new(),
+      book_lines: Vec::new(),
+      checkpoint,
+    };
+    while let Some(line) = bank_lines.next_if(|l| l.day <= report.checkpoint.day) {
+      report.bank_lines.push(line);
+    }
+    while let Some(line) = book_lines.next_if(|l| l.day <= report.checkpoint.day) {
+      report.book_lines.push(line);
+    }
+    reports.push(report);
+  }
+
+  html(Index{
+    bookkeeping_name: bookkeeping.name,
+    account_name: account.name,
+    reports,
+  }.render()?)
+}
+
+#[derive(Debug, Deserialize)]
+struct NewCheckpoint {
+  day: Date,
+  balance: Decimal,
+}
+async fn index_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  account: Account,
+) -> Result<Response, Error> {
+  let new_checkpoint: NewCheckpoint = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  // A new statement for the same day replaces the earlier one, also if that
+  // was imported, so it stays when the import is undone
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "
+INSERT INTO StatementCheckpoints(account_id, day, balance)
+  VALUES($1, $2, $3)
+  ON CONFLICT (account_id, day) DO UPDATE SET balance = $3, batch_id = NULL
+    ",
+    account.id,
+    new_checkpoint.day,
+    new_checkpoint.balance,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  see_other("reconciliation")
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  account: Account,
+) -> Result<Response, Error> {
+  verify_path_end(&path_vec, &req)?;
+  match req.method() {
+    &Method::GET => index(state, bookkeeping, account).await,
+    &Method::POST => index_post(state, req, session, account).await,
+    _ => Err(Error::method_not_found(&req)),
+  }
+}
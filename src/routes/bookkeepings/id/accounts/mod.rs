@@ -1,42 +1,235 @@
 use super::*;
 
+mod id;
+
 #[derive(Debug, Deserialize)]
 struct NewAccount {
   name: String,
+  // Optional, unique within the bookkeeping
+  #[serde(default)]
+  number: String,
   r#type: String,
+  // Optional, used to import bank statements into the right account
+  iban: Option<String>,
+  // Optional, for accounts in another currency than the base currency
+  currency: Option<String>,
+  // Optional, empty for accounts at the top
+  #[serde(default)]
+  parent: String,
+  // Optional, a subtype of the account's type
+  #[serde(default)]
+  subtype: String,
+}
+impl NewAccount {
+  fn parent_id(&self) -> Result<Option<i64>, Error> {
+    match self.parent.as_str() {
+      "" => Ok(None),
+      id => Ok(Some(id.parse()?)),
+    }
+  }
+  fn subtype_id(&self) -> Result<Option<i64>, Error> {
+    match self.subtype.as_str() {
+      "" => Ok(None),
+      id => Ok(Some(id.parse()?)),
+    }
+  }
+}
+pub fn parse_account_number(value: &str) -> Result<Option<i32>, Error> {
+  match value.trim() {
+    "" => Ok(None),
+    number => number.parse()
+      .ok()
+      .filter(|n: &i32| *n >= 0)
+      .map(Some)
+      .ok_or(ClientError::InvalidAccountNumber(number.to_owned()).into()),
+  }
+}
+// Look up an account by number, as a shorthand for choosing it
+pub async fn account_by_number(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping_id: i64,
+  number: i32,
+) -> Result<i64, Error> {
+  Ok(sqlx::query!(
+    "SELECT id FROM Accounts WHERE bookkeeping_id = $1 AND number = $2",
+    bookkeeping_id,
+    number,
+  )
+    .fetch_optional(&mut **transaction)
+    .await?
+    .ok_or(ClientError::AccountNumberNotFound(number))?
+    .id
+  )
+}
+// Describe which of the account's unique or subtype constraints failed
+fn map_account_conflict(
+  e: sqlx::Error,
+  name: &str,
+  number: Option<i32>,
+  iban: Option<&str>,
+) -> Error {
+  match e {
+    sqlx::Error::Database(ref dbe) if dbe.constraint() == Some("accounts_bookkeeping_id_iban_key") => {
+      ClientError::AlreadyExists(format!(
+        "An account with IBAN {} already exists in this bookkeeping.",
+        iban.unwrap_or(""),
+      )).into()
+    },
+    sqlx::Error::Database(ref dbe) if dbe.constraint() == Some("accounts_bookkeeping_id_number_key") => {
+      ClientError::AlreadyExists(format!(
+        "An account numbered {} already exists in this bookkeeping.",
+        number.map(|n| n.to_string()).unwrap_or_default(),
+      )).into()
+    },
+    sqlx::Error::Database(ref dbe) if dbe.is_unique_violation() => {
+      ClientError::AlreadyExists(format!(
+        "An account by name {} already exists in this bookkeeping.",
+        name,
+      )).into()
+    },
+    // Changing the type also changes it for the sub-accounts
+    sqlx::Error::Database(ref dbe) if dbe.constraint() == Some("accounts_subtype_id_bookkeeping_id_type_fkey") => {
+      ClientError::InvalidAccountSubtype(
+        "A sub-account has a subtype of the old type, which must be changed first".to_owned()
+      ).into()
+    },
+    e => e.into(),
+  }
+}
+// Verify that the parent is an account of the same type in the bookkeeping,
+// and that it isn't the account itself or below it
+async fn verify_parent(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping_id: i64,
+  account_id: Option<i64>,
+  parent_id: i64,
+  r#type: &str,
+) -> Result<(), Error> {
+  // The rows are locked, so the parent's type and ancestors can't change
+  // until the account is saved
+  let parent = sqlx::query!(
+    "SELECT name, type, parent_id FROM Accounts WHERE id = $1 AND bookkeeping_id = $2 FOR UPDATE",
+    parent_id,
+    bookkeeping_id,
+  )
+    .fetch_optional(&mut **transaction)
+    .await?
+    .ok_or(ClientError::AccountNotFound(parent_id))?
+  ;
+  if parent.r#type != r#type {
+    return Err(ClientError::InvalidParentAccount(format!(
+      "The account must have the type {} of its parent account {}",
+      parent.r#type, parent.name,
+    )).into());
+  }
+  if let Some(account_id) = account_id {
+    // Walk up from the parent, which must not lead back to the account
+    // Each ancestor is locked on the way, so a concurrent change can't make a
+    // cycle through the accounts we have checked
+    let mut ancestor = Some(parent_id);
+    let mut visited = std::collections::HashSet::new();
+    while let Some(id) = ancestor {
+      if id == account_id {
+        return Err(ClientError::InvalidParentAccount(format!(
+          "The account {} is below the account, so it can't be its parent",
+          parent.name,
+        )).into());
+      }
+      // Stop at a cycle not involving the account, rather than loop forever
+      if !visited.insert(id) {
+        break;
+      }
+      ancestor = sqlx::query!(
+        "SELECT parent_id FROM Accounts WHERE id = $1 FOR UPDATE",
+        id,
+      )
+        .fetch_one(&mut **transaction)
+        .await?
+        .parent_id
+      ;
+    }
+  }
+  Ok(())
+}
+// Verify that the subtype is of the bookkeeping and the account's type
+async fn verify_subtype(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping_id: i64,
+  subtype_id: i64,
+  r#type: &str,
+) -> Result<(), Error> {
+  let subtype = sqlx::query!(
+    "SELECT name, type FROM AccountSubtypes WHERE id = $1 AND bookkeeping_id = $2",
+    subtype_id,
+    bookkeeping_id,
+  )
+    .fetch_optional(&mut **transaction)
+    .await?
+    .ok_or(ClientError::InvalidAccountSubtype(format!(
+      "No subtype with id {} in this bookkeeping",
+      subtype_id,
+    )))?
+  ;
+  if subtype.r#type != r#type {
+    return Err(ClientError::InvalidAccountSubtype(format!(
+      "The subtype {} is for {} accounts",
+      subtype.name, subtype.r#type,
+    )).into());
+  }
+  Ok(())
+}
+// Store IBANs without the spaces they are usually written with
+fn normalize_iban(iban: Option<String>) -> Option<String> {
+  iban
+    .map(|i| i.split_whitespace().collect::<String>().to_uppercase())
+    .filter(|i| !i.is_empty())
 }
 async fn index_put(
   state: &'static State,
   mut req: Request,
   session: SessionData,
-  bookkeeping_id: i64,
+  bookkeeping: Bookkeeping,
 ) -> Result<Response, Error> {
   // Parse out the new account
   let new_account: NewAccount = parse_body_urlencoded(
     &mut req,
     state.max_content_len,
   ).await?;
+  let parent_id = new_account.parent_id()?;
+  let subtype_id = new_account.subtype_id()?;
+  let number = parse_account_number(&new_account.number)?;
+  let iban = normalize_iban(new_account.iban);
+  let currency = currencies::normalize_currency(new_account.currency, &bookkeeping.base_currency)?;
   // Insert into database
+  let mut transaction = begin_as(state, &session).await?;
+  if let Some(parent_id) = parent_id {
+    verify_parent(&mut transaction, bookkeeping.id, None, parent_id, &new_account.r#type).await?;
+  }
+  if let Some(subtype_id) = subtype_id {
+    verify_subtype(&mut transaction, bookkeeping.id, subtype_id, &new_account.r#type).await?;
+  }
   let created = sqlx::query!(
-    "INSERT INTO Accounts(name, type, bookkeeping_id) VALUES($1, $2, $3) RETURNING id",
+    "
+INSERT INTO Accounts(name, number, type, subtype_id, iban, currency, parent_id, bookkeeping_id)
+  VALUES($1, $2, $3, $4, $5, $6, $7, $8)
+  RETURNING id
+    ",
     new_account.name,
+    number,
     new_account.r#type,
-    bookkeeping_id,
+    subtype_id,
+    iban,
+    currency,
+    parent_id,
+    bookkeeping.id,
   )
-    .fetch_one(&state.db)
+    .fetch_one(&mut *transaction)
     .await
-    .map_err(|e| -> Error { match e {
-      sqlx::Error::Database(ref dbe) if dbe.is_unique_violation() => {
-        ClientError::AlreadyExists(format!(
-          "An account by name {} already exists in this bookkeeping.",
-          new_account.name,
-        )).into()
-      },
-      e => e.into(),
-    }})
+    .map_err(|e| map_account_conflict(e, &new_account.name, number, iban.as_deref()))
     ?
     .id
   ;
+  transaction.commit().await?;
   // Redirect to parent with query parameter of created account's id
   see_other(&format!("../?new_account={created}"))
 }
@@ -52,10 +245,10 @@ pub async fn route(
     Some("") => {
       verify_path_end(&path_vec, &req)?;
       match req.method() {
-        &Method::POST => index_put(state, req, session, bookkeeping.id).await,
+        &Method::POST => index_put(state, req, session, bookkeeping).await,
         _ => Err(Error::method_not_found(&req)),
       }
     },
-    _ => Err(Error::path_not_found(&req)),
+    Some(id) => id::route(state, req, path_vec, session, bookkeeping, id.parse()?).await,
   }
 }
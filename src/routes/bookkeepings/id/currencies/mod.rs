@@ -0,0 +1,524 @@
+use super::*;
+
+// Currency codes are stored as three uppercase letters, and accounts in the
+// base currency have none so there's only one way to write it
+pub fn normalize_currency(
+  currency: Option<String>,
+  base_currency: &str,
+) -> Result<Option<String>, Error> {
+  let currency = currency
+    .map(|c| c.trim().to_uppercase())
+    .filter(|c| !c.is_empty() && c != base_currency)
+  ;
+  match currency {
+    Some(c) if c.len() != 3 || !c.chars().all(|c| c.is_ascii_uppercase()) => {
+      Err(ClientError::InvalidCurrency(format!(
+        "{} is not a three letter currency code",
+        c,
+      )).into())
+    },
+    c => Ok(c),
+  }
+}
+
+// The currency of the account, None for the base currency, and the latest
+// rate for it on or before the given day
+async fn account_rate(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping_id: i64,
+  account_id: i64,
+  day: Date,
+) -> Result<(Option<String>, Option<Decimal>), Error> {
+  let account = sqlx::query!(
+    "
+SELECT Accounts.currency, (
+    SELECT ExchangeRates.rate FROM ExchangeRates
+    WHERE ExchangeRates.bookkeeping_id = Accounts.bookkeeping_id
+      AND ExchangeRates.currency = Accounts.currency
+      AND ExchangeRates.day <= $3
+    ORDER BY ExchangeRates.day DESC
+    LIMIT 1
+  ) AS rate
+  FROM Accounts
+WHERE Accounts.id = $1 AND Accounts.bookkeeping_id = $2
+    ",
+    account_id,
+    bookkeeping_id,
+    day,
+  )
+    .fetch_optional(&mut **transaction)
+    .await?
+    .ok_or(ClientError::AccountNotFound(account_id))?
+  ;
+  Ok((account.currency, account.rate))
+}
+// Split an amount in the account's currency into the amount in the base
+// currency and the original amount to keep for accounts in other currencies.
+// The base amount can be given, for when the bank converted at its own rate.
+pub async fn to_base(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping_id: i64,
+  account_id: i64,
+  day: Date,
+  amount: Decimal,
+  base_amount: Option<Decimal>,
+) -> Result<(Decimal, Option<Decimal>), Error> {
+  match (account_rate(transaction, bookkeeping_id, account_id, day).await?, base_amount) {
+    ((None, _), _) => Ok((amount, None)),
+    ((Some(_), _), Some(base)) => Ok((base, Some(amount))),
+    ((Some(_), Some(rate)), None) => Ok(((amount * rate).round_dp(2), Some(amount))),
+    ((Some(currency), None), None) => Err(Error::exchange_rate_missing(&currency, day)),
+  }
+}
+// The other way around, for the counter side of a change given in the base
+// currency, giving the original amount if the account has another currency
+pub async fn from_base(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping_id: i64,
+  account_id: i64,
+  day: Date,
+  base: Decimal,
+) -> Result<Option<Decimal>, Error> {
+  match account_rate(transaction, bookkeeping_id, account_id, day).await? {
+    (None, _) => Ok(None),
+    (Some(_), Some(rate)) => Ok(Some((base / rate).round_dp(2))),
+    (Some(currency), None) => Err(Error::exchange_rate_missing(&currency, day)),
+  }
+}
+
+#[derive(Debug)]
+struct ExchangeRate {
+  id: i64,
+  currency: String,
+  day: Date,
+  rate: Decimal,
+}
+#[derive(Debug)]
+struct Choice {
+  id: i64,
+  name: String,
+}
+#[derive(Debug)]
+struct Revaluation {
+  account_id: i64,
+  account_name: String,
+  currency: String,
+  original_balance: Decimal,
+  balance: Decimal,
+  rate: Option<Decimal>,
+}
+impl Revaluation {
+  // How much the balance in the base currency is off at the rate, if known
+  fn difference(&self) -> Option<Decimal> {
+    self.rate.map(|rate| (self.original_balance * rate).round_dp(2) - self.balance)
+  }
+}
+#[derive(Debug, Deserialize)]
+struct RevaluationQuery {
+  // Both empty until a revaluation is previewed
+  #[serde(default)]
+  grouping: String,
+  #[serde(default)]
+  date: String,
+}
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/currencies/index.html")]
+struct Index {
+  bookkeeping_name: String,
+  base_currency: String,
+  rates: Vec<ExchangeRate>,
+  groupings: Vec<Choice>,
+  // Income and Expense accounts in the base currency, to book revaluations on
+  result_accounts: Vec<Choice>,
+  grouping: Option<i64>,
+  date: String,
+  revaluations: Vec<Revaluation>,
+}
+impl Index {
+  fn is_grouping(&self, id: &i64) -> bool {
+    self.grouping == Some(*id)
+  }
+}
+
+// The balances of the accounts in other currencies within the grouping, in
+// both currencies, with the rate to revalue them at on the date
+async fn revaluations(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping_id: i64,
+  grouping_id: i64,
+  date: Date,
+) -> Result<Vec<Revaluation>, Error> {
+  let revaluations = sqlx::query_as!(Revaluation,
+    "
+SELECT Accounts.id AS account_id, concat_ws(' ', Accounts.number, Accounts.name) AS \"account_name!\",
+    Accounts.currency AS \"currency!\",
+    COALESCE(SUM(AccountChanges.original_amount), 0) AS \"original_balance!\",
+    COALESCE(SUM(AccountChanges.amount), 0) AS \"balance!\",
+    (
+      SELECT ExchangeRates.rate FROM ExchangeRates
+      WHERE ExchangeRates.bookkeeping_id = Accounts.bookkeeping_id
+        AND ExchangeRates.currency = Accounts.currency
+        AND ExchangeRates.day <= $3
+      ORDER BY ExchangeRates.day DESC
+      LIMIT 1
+    ) AS rate
+  FROM Accounts
+  LEFT JOIN (
+    AccountChanges INNER JOIN Transactions
+      ON Transactions.id = AccountChanges.transaction_id AND Transactions.posted
+        AND Transactions.grouping_id = $2
+  ) ON AccountChanges.account_id = Accounts.id AND AccountChanges.day <= $3
+WHERE Accounts.bookkeeping_id = $1 AND Accounts.currency IS NOT NULL
+GROUP BY Accounts.id
+ORDER BY Accounts.number, Accounts.name
+    ",
+    bookkeeping_id,
+    grouping_id,
+    date,
+  )
+    .fetch_all(&mut **transaction)
+    .await?
+  ;
+  Ok(revaluations)
+}
+
+async fn index(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+  query: RevaluationQuery,
+) -> Result<Response, Error> {
+  let rates = sqlx::query_as!(ExchangeRate,
+    "
+SELECT id, currency AS \"currency!\", day, rate
+  FROM ExchangeRates
+WHERE bookkeeping_id = $1
+ORDER BY currency, day DESC
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let groupings = sqlx::query_as!(Choice,
+    "SELECT id, name FROM Groupings WHERE bookkeeping_id = $1 ORDER BY name",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let result_accounts = sqlx::query_as!(Choice,
+    "
+SELECT id, concat_ws(' ', number, name) AS \"name!\"
+  FROM Accounts
+WHERE bookkeeping_id = $1 AND currency IS NULL AND type IN ('Income', 'Expense')
+ORDER BY number, type, name
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  // Preview the revaluation once both grouping and date are chosen
+  let grouping = match query.grouping.as_str() {
+    "" => None,
+    id => Some(id.parse()?),
+  };
+  let date = parse_form_date("date", &query.date)?;
+  let revaluations = match (grouping, date) {
+    (Some(grouping), Some(date)) => {
+      let mut transaction = state.db.begin().await?;
+      let revaluations = revaluations(&mut transaction, bookkeeping.id, grouping, date).await?;
+      transaction.rollback().await?;
+      revaluations
+    },
+    _ => Vec::new(),
+  };
+  html(Index{
+    bookkeeping_name: bookkeeping.name,
+    base_currency: bookkeeping.base_currency,
+    rates,
+    groupings,
+    result_accounts,
+    grouping,
+    date: query.date,
+    revaluations,
+  }.render()?)
+}
+
+#[derive(Debug, Deserialize)]
+struct BaseCurrencyForm {
+  base_currency: String,
+}
+// The base currency can only change before anything is booked, as amounts
+// aren't converted
+async fn base_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let form: BaseCurrencyForm = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  // Normalized against an empty base, so the code is always given
+  let base_currency = normalize_currency(Some(form.base_currency), "")?
+    .ok_or(ClientError::InvalidCurrency("No base currency given".to_owned()))?
+  ;
+  let mut transaction = begin_as(state, &session).await?;
+  // Lock the bookkeeping, so nothing is booked while changing it
+  sqlx::query!(
+    "SELECT id FROM Bookkeepings WHERE id = $1 FOR UPDATE",
+    bookkeeping.id,
+  )
+    .fetch_one(&mut *transaction)
+    .await?
+  ;
+  let booked = sqlx::query!(
+    "
+SELECT EXISTS(
+  SELECT 1 FROM AccountChanges
+    INNER JOIN Accounts ON Accounts.id = AccountChanges.account_id
+  WHERE Accounts.bookkeeping_id = $1
+) AS \"booked!\"
+    ",
+    bookkeeping.id,
+  )
+    .fetch_one(&mut *transaction)
+    .await?
+    .booked
+  ;
+  if booked {
+    return Err(ClientError::InvalidCurrency(
+      "The base currency can't change once there are account changes".to_owned()
+    ).into());
+  }
+  sqlx::query!(
+    "UPDATE Bookkeepings SET base_currency = $2 WHERE id = $1",
+    bookkeeping.id,
+    base_currency,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  // Accounts in the new base currency are now in the base currency
+  sqlx::query!(
+    "UPDATE Accounts SET currency = NULL WHERE bookkeeping_id = $1 AND currency = $2",
+    bookkeeping.id,
+    base_currency,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+
+#[derive(Debug, Deserialize)]
+struct NewExchangeRate {
+  currency: String,
+  day: Date,
+  rate: Decimal,
+}
+// Add a rate, replacing any given for the same currency and day
+async fn rates_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let new_rate: NewExchangeRate = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let currency = normalize_currency(Some(new_rate.currency), &bookkeeping.base_currency)?
+    .ok_or(ClientError::InvalidCurrency(
+      "Rates are only needed for currencies other than the base currency".to_owned()
+    ))?
+  ;
+  if new_rate.rate <= Decimal::ZERO {
+    return Err(ClientError::InvalidCurrency("The rate must be positive".to_owned()).into());
+  }
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "
+INSERT INTO ExchangeRates(bookkeeping_id, currency, day, rate)
+  VALUES($1, $2, $3, $4)
+  ON CONFLICT (bookkeeping_id, currency, day) DO UPDATE SET rate = EXCLUDED.rate
+    ",
+    bookkeeping.id,
+    currency,
+    new_rate.day,
+    new_rate.rate,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+#[derive(Debug, Deserialize)]
+struct DeleteExchangeRate {
+  id: i64,
+}
+async fn delete_rate_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let form: DeleteExchangeRate = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "DELETE FROM ExchangeRates WHERE id = $1 AND bookkeeping_id = $2",
+    form.id,
+    bookkeeping.id,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+
+#[derive(Debug, Deserialize)]
+struct RevalueForm {
+  grouping: i64,
+  date: Date,
+  // Where the unrealized gains and losses are booked
+  account: i64,
+}
+// Book the difference between the balances of accounts in other currencies
+// at the rate of the date and their balances as booked, as a transaction with
+// the gains and losses against the given account
+async fn revalue_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let form: RevalueForm = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  sqlx::query!(
+    "SELECT id FROM Groupings WHERE id = $1 AND bookkeeping_id = $2",
+    form.grouping,
+    bookkeeping.id,
+  )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(ClientError::GroupingNotFound(form.grouping))?
+  ;
+  sqlx::query!(
+    "
+SELECT id FROM Accounts
+WHERE id = $1 AND bookkeeping_id = $2 AND currency IS NULL
+  AND type IN ('Income', 'Expense')
+    ",
+    form.account,
+    bookkeeping.id,
+  )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(ClientError::AccountNotFound(form.account))?
+  ;
+  let mut transaction = begin_as(state, &session).await?;
+  let revaluations = revaluations(&mut transaction, bookkeeping.id, form.grouping, form.date).await?;
+  let mut differences = Vec::new();
+  for revaluation in revaluations.iter() {
+    match revaluation.difference() {
+      None => return Err(Error::exchange_rate_missing(&revaluation.currency, form.date)),
+      Some(difference) if !difference.is_zero() => {
+        differences.push((revaluation.account_id, difference));
+      },
+      Some(_) => (),
+    }
+  }
+  if differences.is_empty() {
+    return see_other("./");
+  }
+  // Balances by construction, so it is posted right away
+  let transaction_id = sqlx::query!(
+    "
+INSERT INTO Transactions(name, day, grouping_id, posted)
+  VALUES('Currency revaluation', $1, $2, TRUE)
+  RETURNING id
+    ",
+    form.date,
+    form.grouping,
+  )
+    .fetch_one(&mut *transaction)
+    .await?
+    .id
+  ;
+  // Only the base amounts change, the amounts in the currencies are the same
+  for (account_id, difference) in differences.iter() {
+    sqlx::query!(
+      "
+INSERT INTO AccountChanges(transaction_id, account_id, day, amount, original_amount)
+  VALUES($1, $2, $3, $4, 0)
+      ",
+      transaction_id,
+      account_id,
+      form.date,
+      difference,
+    )
+      .execute(&mut *transaction)
+      .await?
+    ;
+  }
+  let total: Decimal = differences.iter().map(|(_, d)| d).sum();
+  sqlx::query!(
+    "
+INSERT INTO AccountChanges(transaction_id, account_id, day, amount)
+  VALUES($1, $2, $3, $4)
+    ",
+    transaction_id,
+    form.account,
+    form.date,
+    -total,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  see_other(&format!("../groupings/{}/?new_transaction={}", form.grouping, transaction_id))
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    Some("") => {
+      verify_method_path_end(&path_vec, &req, &Method::GET)?;
+      let query: RevaluationQuery = parse_query(&req)?;
+      index(state, bookkeeping, query).await
+    },
+    Some("base") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      base_post(state, req, session, bookkeeping).await
+    },
+    Some("rates") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      rates_post(state, req, session, bookkeeping).await
+    },
+    Some("delete-rate") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      delete_rate_post(state, req, session, bookkeeping).await
+    },
+    Some("revalue") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      revalue_post(state, req, session, bookkeeping).await
+    },
+    _ => Err(Error::path_not_found(&req)),
+  }
+}
@@ -0,0 +1,376 @@
+use super::*;
+
+// Closing a year zeroes the Income and Expense accounts of the grouping into a
+// result account, and carries the Asset, Debt and Equity balances over as
+// opening balances of a new grouping for the next year
+
+#[derive(Debug, Deserialize)]
+pub struct CloseForm {
+  // Date range within the grouping to close, empty for no bound
+  #[serde(default)]
+  from: String,
+  #[serde(default)]
+  to: String,
+  // Balance account in the base currency taking the result of the year
+  #[serde(default)]
+  result_account: String,
+  // Name of the grouping to create for the next year
+  #[serde(default)]
+  name: String,
+  // Lock the closed grouping afterwards
+  lock: Option<String>,
+}
+
+#[derive(Debug)]
+struct Account {
+  id: i64,
+  name: String,
+}
+#[derive(Debug)]
+struct Line {
+  account_id: i64,
+  account_name: String,
+  amount: Decimal,
+  // For accounts in another currency
+  original_amount: Option<Decimal>,
+}
+#[derive(Debug)]
+struct Plan {
+  closing_date: Date,
+  opening_date: Date,
+  result_account: Option<i64>,
+  // A chosen result account that can't take the result, left out above
+  invalid_result_account: Option<i64>,
+  // Sum of the Income and Expense balances being closed
+  result: Decimal,
+  closing: Vec<Line>,
+  opening: Vec<Line>,
+  // Drafts in the period, which are neither closed nor carried over
+  drafts: i64,
+}
+impl Plan {
+  // Why the year can't be closed as planned, if it can't
+  fn problem(&self) -> Option<String> {
+    if self.invalid_result_account.is_some() {
+      return Some("The result can only be taken by a balance account in the base currency".to_owned());
+    }
+    if !self.result.is_zero() && self.result_account.is_none() {
+      return Some("Choose an account to take the result of the year".to_owned());
+    }
+    let sum: Decimal = self.opening.iter().map(|l| l.amount).sum();
+    if !sum.is_zero() {
+      return Some(format!(
+        "The opening balances sum to {}, as the date range splits transactions",
+        sum,
+      ));
+    }
+    None
+  }
+}
+
+// Plan the closing from the posted account changes in the grouping and range
+async fn plan(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping: &Bookkeeping,
+  grouping: &Grouping,
+  from: Option<Date>,
+  to: Option<Date>,
+  result_account: Option<i64>,
+) -> Result<Plan, Error> {
+  let balances = sqlx::query_as!(AccountSummary,
+    "
+SELECT Accounts.id, concat_ws(' ', Accounts.number, Accounts.name) AS \"name!\", Accounts.type,
+    COALESCE(SUM(AccountChanges.amount), 0) AS \"balance!\",
+    Accounts.currency,
+    COALESCE(SUM(COALESCE(AccountChanges.original_amount, AccountChanges.amount)), 0) AS \"original_balance!\",
+    Accounts.parent_id, AccountSubtypes.name AS \"subtype?\", AccountTypes.normal_balance
+  FROM Accounts
+  INNER JOIN AccountTypes ON AccountTypes.name = Accounts.type
+  LEFT JOIN AccountSubtypes ON AccountSubtypes.id = Accounts.subtype_id
+  LEFT JOIN (
+    AccountChanges INNER JOIN Transactions
+      ON Transactions.id = AccountChanges.transaction_id AND Transactions.posted
+        AND Transactions.grouping_id = $2
+  ) ON AccountChanges.account_id = Accounts.id
+    AND ($3::DATE IS NULL OR AccountChanges.day >= $3)
+    AND ($4::DATE IS NULL OR AccountChanges.day <= $4)
+WHERE Accounts.bookkeeping_id = $1
+GROUP BY Accounts.id, AccountSubtypes.id, AccountTypes.name
+ORDER BY Accounts.number, Accounts.type, Accounts.name
+    ",
+    bookkeeping.id,
+    grouping.id,
+    from,
+    to,
+  )
+    .fetch_all(&mut **transaction)
+    .await?
+  ;
+  let period = sqlx::query!(
+    "
+SELECT MAX(Transactions.day) AS last_day,
+    COUNT(*) FILTER (WHERE NOT Transactions.posted) AS \"drafts!\"
+  FROM Transactions
+WHERE Transactions.grouping_id = $1
+  AND ($2::DATE IS NULL OR Transactions.day >= $2)
+  AND ($3::DATE IS NULL OR Transactions.day <= $3)
+    ",
+    grouping.id,
+    from,
+    to,
+  )
+    .fetch_one(&mut **transaction)
+    .await?
+  ;
+  // An account that can't take the result is reported as a problem with the
+  // plan, so the form is shown again to choose another
+  let invalid_result_account = result_account.filter(|id| !balances.iter()
+    .any(|a| a.id == *id && a.currency.is_none() && is_balance_type(&a.r#type))
+  );
+  let result_account = result_account.filter(|_| invalid_result_account.is_none());
+  // The year ends where the range or the transactions in it do
+  let closing_date = to
+    .or(period.last_day)
+    .unwrap_or_else(|| time::OffsetDateTime::now_utc().date())
+  ;
+  let opening_date = closing_date.next_day()
+    .ok_or(ClientError::InvalidYearEnd("The year can't end on the last possible date".to_owned()))?
+  ;
+  let mut result = Decimal::ZERO;
+  let mut closing = Vec::new();
+  let mut opening = Vec::new();
+  for account in balances.iter() {
+    let line = |amount, original_amount| Line{
+      account_id: account.id,
+      account_name: account.name.clone(),
+      amount,
+      original_amount: account.currency.as_ref().map(|_| original_amount),
+    };
+    match account.r#type.as_str() {
+      t if is_balance_type(t) => opening.push(line(account.balance, account.original_balance)),
+      _ if !account.balance.is_zero() || !account.original_balance.is_zero() => {
+        result += account.balance;
+        closing.push(line(-account.balance, -account.original_balance));
+      },
+      _ => (),
+    }
+  }
+  // The result ends up on the result account, both when closing and opening
+  if let Some(id) = result_account {
+    if !result.is_zero() {
+      // Verified above to be a balance account, so it has an opening line
+      let account = opening.iter_mut().find(|l| l.account_id == id).unwrap();
+      closing.push(Line{
+        account_id: id,
+        account_name: account.account_name.clone(),
+        amount: result,
+        original_amount: None,
+      });
+      account.amount += result;
+    }
+  }
+  opening.retain(|l| !l.amount.is_zero() || l.original_amount.is_some_and(|a| !a.is_zero()));
+  Ok(Plan{
+    closing_date,
+    opening_date,
+    result_account,
+    invalid_result_account,
+    result,
+    closing,
+    opening,
+    drafts: period.drafts,
+  })
+}
+// The new grouping is named after the year it starts unless named otherwise
+fn grouping_name(form: &CloseForm, plan: &Plan) -> String {
+  match form.name.trim() {
+    "" => plan.opening_date.year().to_string(),
+    name => name.to_owned(),
+  }
+}
+fn parse_result_account(value: &str) -> Result<Option<i64>, Error> {
+  match value {
+    "" => Ok(None),
+    id => Ok(Some(id.parse()?)),
+  }
+}
+
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/groupings/id/close/index.html")]
+struct Index {
+  grouping_name: String,
+  from: String,
+  to: String,
+  name: String,
+  balance_accounts: Vec<Account>,
+  plan: Plan,
+  problem: Option<String>,
+}
+impl Index {
+  fn is_result_account(&self, id: &i64) -> bool {
+    self.plan.result_account == Some(*id)
+  }
+}
+// Show what closing the year would do, without changing anything
+async fn preview(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+  grouping: Grouping,
+  form: CloseForm,
+) -> Result<Response, Error> {
+  let from = parse_form_date("from", &form.from)?;
+  let to = parse_form_date("to", &form.to)?;
+  let result_account = parse_result_account(&form.result_account)?;
+  let mut transaction = state.db.begin().await?;
+  let plan = plan(&mut transaction, &bookkeeping, &grouping, from, to, result_account).await?;
+  // The accounts that can take the result
+  let balance_accounts = sqlx::query_as!(Account,
+    "
+SELECT Accounts.id, concat_ws(' ', Accounts.number, Accounts.name) AS \"name!\"
+  FROM Accounts
+WHERE Accounts.bookkeeping_id = $1 AND Accounts.type IN ('Asset', 'Debt', 'Equity')
+  AND Accounts.currency IS NULL
+ORDER BY Accounts.number, Accounts.type, Accounts.name
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&mut *transaction)
+    .await?
+  ;
+  transaction.rollback().await?;
+  let name = grouping_name(&form, &plan);
+  html(Index{
+    grouping_name: grouping.name,
+    from: form.from,
+    to: form.to,
+    name,
+    balance_accounts,
+    problem: plan.problem(),
+    plan,
+  }.render()?)
+}
+async fn close_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  grouping: Grouping,
+) -> Result<Response, Error> {
+  let form: CloseForm = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let from = parse_form_date("from", &form.from)?;
+  let to = parse_form_date("to", &form.to)?;
+  let result_account = parse_result_account(&form.result_account)?;
+  let mut transaction = begin_as(state, &session).await?;
+  let plan = plan(&mut transaction, &bookkeeping, &grouping, from, to, result_account).await?;
+  if let Some(problem) = plan.problem() {
+    return Err(ClientError::InvalidYearEnd(problem).into());
+  }
+  // Both transactions balance by construction, so they are posted right away
+  if !plan.closing.is_empty() {
+    let closing_id = sqlx::query!(
+      "
+INSERT INTO Transactions(name, day, grouping_id, posted)
+  VALUES('Year-end closing', $1, $2, TRUE)
+  RETURNING id
+      ",
+      plan.closing_date,
+      grouping.id,
+    )
+      .fetch_one(&mut *transaction)
+      .await?
+      .id
+    ;
+    insert_lines(&mut transaction, closing_id, plan.closing_date, &plan.closing).await?;
+  }
+  let name = grouping_name(&form, &plan);
+  let new_grouping = sqlx::query!(
+    "INSERT INTO Groupings(name, bookkeeping_id) VALUES($1, $2) RETURNING id",
+    name,
+    bookkeeping.id,
+  )
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|e| map_name_conflict(e, &name))
+    ?
+    .id
+  ;
+  if !plan.opening.is_empty() {
+    let opening_id = sqlx::query!(
+      "
+INSERT INTO Transactions(name, day, grouping_id, posted, opening)
+  VALUES('Opening balances', $1, $2, TRUE, TRUE)
+  RETURNING id
+      ",
+      plan.opening_date,
+      new_grouping,
+    )
+      .fetch_one(&mut *transaction)
+      .await?
+      .id
+    ;
+    insert_lines(&mut transaction, opening_id, plan.opening_date, &plan.opening).await?;
+  }
+  if form.lock.is_some() {
+    sqlx::query!(
+      "UPDATE Groupings SET locked = TRUE WHERE id = $1",
+      grouping.id,
+    )
+      .execute(&mut *transaction)
+      .await?
+    ;
+  }
+  transaction.commit().await?;
+  see_other(&format!("../../{}/", new_grouping))
+}
+async fn insert_lines(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  transaction_id: i64,
+  day: Date,
+  lines: &[Line],
+) -> Result<(), Error> {
+  for line in lines.iter() {
+    sqlx::query!(
+      "
+INSERT INTO AccountChanges(transaction_id, account_id, day, amount, original_amount)
+  VALUES($1, $2, $3, $4, $5)
+      ",
+      transaction_id,
+      line.account_id,
+      day,
+      line.amount,
+      line.original_amount,
+    )
+      .execute(&mut **transaction)
+      .await?
+    ;
+  }
+  Ok(())
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  grouping: Grouping,
+) -> Result<Response, Error> {
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    Some("") => {
+      verify_path_end(&path_vec, &req)?;
+      match req.method() {
+        &Method::GET => {
+          let form: CloseForm = parse_query(&req)?;
+          preview(state, bookkeeping, grouping, form).await
+        },
+        &Method::POST => close_post(state, req, session, bookkeeping, grouping).await,
+        _ => Err(Error::method_not_found(&req)),
+      }
+    },
+    _ => Err(Error::path_not_found(&req)),
+  }
+}
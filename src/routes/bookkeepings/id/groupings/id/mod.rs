@@ -1,5 +1,6 @@
 use super::*;
 
+mod close;
 mod transactions;
 
 // Only data (no calculations), since this will be fetched very often
@@ -7,6 +8,8 @@ mod transactions;
 pub struct Grouping {
   id: i64,
   name: String,
+  // Nothing in a locked grouping can be changed
+  locked: bool,
 }
 
 #[derive(Debug)]
@@ -15,6 +18,13 @@ pub struct TransactionSummary {
   name: String,
   date: Date,
   sum: Decimal,
+  // Drafts can be edited freely, posted transactions count towards balances
+  posted: bool,
+}
+impl TransactionSummary {
+  fn is_unbalanced_draft(&self) -> bool {
+    !self.posted && !self.sum.is_zero()
+  }
 }
 #[derive(Debug, Deserialize, PartialEq, Eq)]
 struct Created {
@@ -29,9 +39,12 @@ impl Created {
 #[template(path = "bookkeepings/id/groupings/id/index.html")]
 struct Index {
   name: String,
+  locked: bool,
   bookkeeping_name: String,
-  accounts: Vec<AccountSummary>,
+  base_currency: String,
+  accounts: Vec<AccountTreeRow>,
   transactions: Vec<TransactionSummary>,
+  unbalanced_drafts: usize,
   created: Created,
 }
 // Give a summary over the grouping, just like for bookkeepings above
@@ -44,14 +57,23 @@ async fn index(
 ) -> Result<Response, Error> {
   let a = sqlx::query_as!(AccountSummary,
     "
-SELECT Accounts.id, Accounts.name, Accounts.type, COALESCE(SUM(AccountChanges.amount), 0) AS \"balance!\"
-  FROM Transactions
-  INNER JOIN AccountChanges ON AccountChanges.transaction_id = Transactions.id
-  RIGHT JOIN Accounts ON Accounts.id = AccountChanges.account_id
-WHERE Transactions.grouping_id = $1
-GROUP BY Accounts.id, Accounts.name, Accounts.type
-ORDER BY Accounts.type, Accounts.name
+SELECT Accounts.id, concat_ws(' ', Accounts.number, Accounts.name) AS \"name!\", Accounts.type, COALESCE(SUM(AccountChanges.amount), 0) AS \"balance!\",
+    Accounts.currency,
+    COALESCE(SUM(COALESCE(AccountChanges.original_amount, AccountChanges.amount)), 0) AS \"original_balance!\",
+    Accounts.parent_id, AccountSubtypes.name AS \"subtype?\", AccountTypes.normal_balance
+  FROM Accounts
+  INNER JOIN AccountTypes ON AccountTypes.name = Accounts.type
+  LEFT JOIN AccountSubtypes ON AccountSubtypes.id = Accounts.subtype_id
+  LEFT JOIN (
+    AccountChanges INNER JOIN Transactions
+      ON Transactions.id = AccountChanges.transaction_id AND Transactions.posted
+        AND Transactions.grouping_id = $2
+  ) ON AccountChanges.account_id = Accounts.id
+WHERE Accounts.bookkeeping_id = $1
+GROUP BY Accounts.id, AccountSubtypes.id, AccountTypes.name
+ORDER BY Accounts.number, Accounts.type, Accounts.name
     ",
+    bookkeeping.id,
     grouping.id,
   )
     .fetch_all(&state.db)
@@ -60,11 +82,11 @@ ORDER BY Accounts.type, Accounts.name
   let t = sqlx::query_as!(TransactionSummary,
     "
 SELECT Transactions.id, Transactions.name, Transactions.day AS date,
-    COALESCE(SUM(AccountChanges.amount), 0) AS \"sum!\"
+    COALESCE(SUM(AccountChanges.amount), 0) AS \"sum!\", Transactions.posted
   FROM Transactions
   LEFT JOIN AccountChanges ON AccountChanges.transaction_id = Transactions.id
 WHERE Transactions.grouping_id = $1
-GROUP BY Transactions.id, Transactions.name, Transactions.day
+GROUP BY Transactions.id, Transactions.name, Transactions.day, Transactions.posted
 ORDER BY Transactions.day
     ",
     grouping.id,
@@ -72,16 +94,92 @@ ORDER BY Transactions.day
     .fetch_all(&state.db)
     .await?
   ;
-  println!("{t:?}");
+  let unbalanced_drafts = t.iter().filter(|t| t.is_unbalanced_draft()).count();
   html(Index{
     name: grouping.name,
+    locked: grouping.locked,
     bookkeeping_name: bookkeeping.name,
-    accounts: a,
+    base_currency: bookkeeping.base_currency,
+    accounts: account_tree(a),
     transactions: t,
+    unbalanced_drafts,
     created: query,
   }.render()?)
 }
 
+async fn update_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  grouping: Grouping,
+) -> Result<Response, Error> {
+  // Takes the same fields as when creating the grouping
+  let update: NewGrouping = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "UPDATE Groupings SET name = $2 WHERE id = $1",
+    grouping.id,
+    update.name,
+  )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| map_name_conflict(e, &update.name))
+    ?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+async fn delete_post(
+  state: &'static State,
+  session: SessionData,
+  grouping: Grouping,
+) -> Result<Response, Error> {
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "DELETE FROM Groupings WHERE id = $1",
+    grouping.id,
+  )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| -> Error { match e {
+      sqlx::Error::Database(ref dbe) if dbe.is_foreign_key_violation() => {
+        Error::still_referenced(&format!("The grouping {}", grouping.name), dbe.table())
+      },
+      e => e.into(),
+    }})
+    ?
+  ;
+  transaction.commit().await?;
+  see_other("../../")
+}
+
+// Lock or unlock the grouping, where only the owner may unlock
+async fn set_locked(
+  state: &'static State,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  grouping: Grouping,
+  locked: bool,
+) -> Result<Response, Error> {
+  if !locked && session.user_id != bookkeeping.owner_id {
+    return Err(Error::forbidden());
+  }
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "UPDATE Groupings SET locked = $2 WHERE id = $1",
+    grouping.id,
+    locked,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+
 pub async fn route(
   state: &'static State,
   req: Request,
@@ -94,7 +192,7 @@ pub async fn route(
   // bookkeeping (and also since routes are likely to want the name)
   let grouping = sqlx::query_as!(Grouping,
     "
-SELECT Groupings.id, Groupings.name
+SELECT Groupings.id, Groupings.name, Groupings.locked
   FROM Groupings
 WHERE Groupings.bookkeeping_id = $1 AND Groupings.id = $2
     ",
@@ -117,6 +215,30 @@ WHERE Groupings.bookkeeping_id = $1 AND Groupings.id = $2
         _ => Err(Error::method_not_found(&req)),
       }
     },
+    Some("lock") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      set_locked(state, session, bookkeeping, grouping, true).await
+    },
+    Some("unlock") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      set_locked(state, session, bookkeeping, grouping, false).await
+    },
+    Some("update") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      update_post(state, req, session, grouping).await
+    },
+    Some("delete") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      delete_post(state, session, grouping).await
+    },
+    Some("close") => close::route(
+      state,
+      req,
+      path_vec,
+      session,
+      bookkeeping,
+      grouping,
+    ).await,
     Some("transactions") => transactions::route(
       state,
       req,
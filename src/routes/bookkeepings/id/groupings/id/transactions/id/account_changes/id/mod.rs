@@ -0,0 +1,174 @@
+use super::*;
+
+#[derive(Debug)]
+struct AccountChange {
+  id: i64,
+  account_id: i64,
+  message: String,
+  day: Date,
+  amount: Decimal,
+  original_amount: Option<Decimal>,
+  vat_code_id: Option<i64>,
+  vat_role: Option<String>,
+}
+impl AccountChange {
+  // VAT lines are created for the account change VAT is charged on
+  fn is_vat_line(&self) -> bool {
+    matches!(self.vat_role.as_deref(), Some("Output" | "Input"))
+  }
+  fn has_vat_code(&self, id: &i64) -> bool {
+    self.vat_code_id == Some(*id)
+  }
+}
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/groupings/id/transactions/id/account_changes/id/index.html")]
+struct Index {
+  transaction_name: String,
+  account_change: AccountChange,
+  accounts_by_type: std::collections::HashMap<String, Vec<Account>>,
+  vat_codes: Vec<VatChoice>,
+}
+
+async fn index(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+  transaction: TransactionSummary,
+  account_change: AccountChange,
+) -> Result<Response, Error> {
+  // All the accounts (by type), to be able to move the change to another
+  let accounts = sqlx::query_as!(Account,
+    "
+SELECT Accounts.id, concat_ws(' ', Accounts.number, Accounts.name) AS \"name!\", Accounts.type AS t, Accounts.currency
+  FROM Accounts
+WHERE Accounts.bookkeeping_id = $1
+ORDER BY Accounts.number, Accounts.name
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let mut accounts_by_type = std::collections::HashMap::<String,Vec<Account>>::new();
+  for account in accounts {
+    accounts_by_type.entry(account.t.clone()).or_default().push(account);
+  }
+  let vat_codes = sqlx::query_as!(VatChoice,
+    "SELECT id, name FROM VatCodes WHERE bookkeeping_id = $1 ORDER BY name",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  html(Index{
+    transaction_name: transaction.name,
+    account_change,
+    accounts_by_type,
+    vat_codes,
+  }.render()?)
+}
+
+async fn update_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  transaction_id: i64,
+  account_change: AccountChange,
+) -> Result<Response, Error> {
+  // Takes the same fields as when creating the account change
+  let update: NewAccountChange = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let mut transaction = begin_as(state, &session).await?;
+  let account_id = update.account_id(&mut transaction, bookkeeping.id).await?;
+  // Also verifies that the account is in this bookkeeping
+  vat::update_account_change(
+    &mut transaction,
+    bookkeeping.id,
+    account_change.id,
+    &vat::NewChange{
+      transaction_id,
+      account_id,
+      day: update.date,
+      message: &update.message,
+      amount: update.amount,
+      base_amount: update.base_amount()?,
+      vat: vat::parse_vat(&update.vat_code, &update.includes_vat)?,
+    },
+  ).await?;
+  transaction.commit().await?;
+  see_other("../../")
+}
+async fn delete_post(
+  state: &'static State,
+  session: SessionData,
+  account_change: AccountChange,
+) -> Result<Response, Error> {
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "DELETE FROM AccountChanges WHERE id = $1",
+    account_change.id,
+  )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| -> Error { match e {
+      sqlx::Error::Database(ref dbe) if dbe.is_foreign_key_violation() => {
+        Error::still_referenced("The account change", dbe.table())
+      },
+      e => e.into(),
+    }})
+    ?
+  ;
+  transaction.commit().await?;
+  see_other("../../")
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  transaction: TransactionSummary,
+  account_change_id: i64,
+) -> Result<Response, Error> {
+  let account_change = sqlx::query_as!(AccountChange,
+    "
+SELECT id, account_id, message, day, amount, original_amount, vat_code_id, vat_role
+  FROM AccountChanges
+WHERE id = $1 AND transaction_id = $2
+    ",
+    account_change_id,
+    transaction.id,
+  )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::path_not_found(&req))?
+  ;
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    Some("") => {
+      verify_method_path_end(&path_vec, &req, &Method::GET)?;
+      index(state, bookkeeping, transaction, account_change).await
+    },
+    // Posted transactions must be unposted before they can be changed
+    Some("update" | "delete") if transaction.posted => {
+      Err(ClientError::TransactionPosted(transaction.id).into())
+    },
+    // VAT lines follow the account change they were created for, and are
+    // deleted along with it
+    Some("update" | "delete") if account_change.is_vat_line() => {
+      Err(ClientError::GeneratedVatLine(account_change.id).into())
+    },
+    Some("update") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      update_post(state, req, session, bookkeeping, transaction.id, account_change).await
+    },
+    Some("delete") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      delete_post(state, session, account_change).await
+    },
+    _ => Err(Error::path_not_found(&req)),
+  }
+}
@@ -1,11 +1,49 @@
 use super::*;
 
+mod id;
+
 #[derive(Debug, Deserialize)]
 struct NewAccountChange {
   account: i64,
+  // Optionally the number of the account, which then takes precedence
+  #[serde(default)]
+  account_number: String,
   date: Date,
   message: String,
+  // In the account's currency
   amount: Decimal,
+  // Optionally the amount in the base currency, for accounts in another
+  // currency converted at another rate than the bookkeeping's
+  #[serde(default)]
+  base_amount: String,
+  // Optionally a VAT code, to create the VAT lines for the amount with
+  #[serde(default)]
+  vat_code: String,
+  // Whether the amount includes VAT paid to the seller
+  #[serde(default)]
+  includes_vat: String,
+}
+impl NewAccountChange {
+  async fn account_id(
+    &self,
+    transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+    bookkeeping_id: i64,
+  ) -> Result<i64, Error> {
+    match accounts::parse_account_number(&self.account_number)? {
+      Some(number) => accounts::account_by_number(transaction, bookkeeping_id, number).await,
+      None => Ok(self.account),
+    }
+  }
+  fn base_amount(&self) -> Result<Option<Decimal>, Error> {
+    match self.base_amount.as_str() {
+      "" => Ok(None),
+      amount => amount.parse()
+        .map(Some)
+        .map_err(|e: rust_decimal::Error| {
+          ClientError::InvalidUrlEncoding(format!("base_amount: {}", e)).into()
+        }),
+    }
+  }
 }
 async fn index_post(
   state: &'static State,
@@ -20,21 +58,27 @@ async fn index_post(
     &mut req,
     state.max_content_len,
   ).await?;
+  // Posted transactions must be unposted before they can be changed
+  if transaction.posted {
+    return Err(ClientError::TransactionPosted(transaction.id).into());
+  }
   // Insert into database
-  let created = sqlx::query!(
-    "
-INSERT INTO AccountChanges(account_id, day, message, amount, transaction_id) VALUES($1,$2,$3,$4,$5) RETURNING id
-    ",
-    new_account_change.account,
-    new_account_change.date,
-    new_account_change.message,
-    new_account_change.amount,
-    transaction.id,
-  )
-    .fetch_one(&state.db)
-    .await?
-    .id
-  ;
+  let mut db_transaction = begin_as(state, &session).await?;
+  let account_id = new_account_change.account_id(&mut db_transaction, bookkeeping.id).await?;
+  let created = vat::insert_account_change(
+    &mut db_transaction,
+    bookkeeping.id,
+    &vat::NewChange{
+      transaction_id: transaction.id,
+      account_id,
+      day: new_account_change.date,
+      message: &new_account_change.message,
+      amount: new_account_change.amount,
+      base_amount: new_account_change.base_amount()?,
+      vat: vat::parse_vat(&new_account_change.vat_code, &new_account_change.includes_vat)?,
+    },
+  ).await?;
+  db_transaction.commit().await?;
   // Redirect to parent with created as query param
   see_other(&format!("../?new_account_change={created}"))
 }
@@ -60,6 +104,14 @@ pub async fn route(
         transaction,
       ).await
     },
-    _ => Err(Error::path_not_found(&req)),
+    Some(id) => id::route(
+      state,
+      req,
+      path_vec,
+      session,
+      bookkeeping,
+      transaction,
+      id.parse()?,
+    ).await,
   }
 }
@@ -2,11 +2,14 @@ use super::*;
 
 mod account_changes;
 
+use crate::routes::bookkeepings::id::history::AuditEntry;
+
 #[derive(Debug)]
 struct Account{
   id: i64,
   name: String,
   t: String,
+  currency: Option<String>,
 }
 #[derive(Debug)]
 struct AccountChange {
@@ -15,6 +18,23 @@ struct AccountChange {
   message: String,
   date: Date,
   amount: Decimal,
+  // The amount in the account's currency, for accounts in another currency
+  original_amount: Option<Decimal>,
+  currency: Option<String>,
+  vat_code: Option<String>,
+  // Base for the amount VAT is charged on, else Output or Input VAT
+  vat_role: Option<String>,
+}
+impl AccountChange {
+  // VAT lines are edited through the account change they were created for
+  fn is_vat_line(&self) -> bool {
+    matches!(self.vat_role.as_deref(), Some("Output" | "Input"))
+  }
+}
+#[derive(Debug)]
+struct VatChoice {
+  id: i64,
+  name: String,
 }
 #[derive(Debug, Template)]
 #[template(path = "bookkeepings/id/groupings/id/transactions/id/index.html")]
@@ -24,7 +44,9 @@ struct Index {
   bookkeeping_name: String,
   date: Date,
   sum: Decimal,
+  posted: bool,
   accounts_by_type: std::collections::HashMap<String, Vec<Account>>,
+  vat_codes: Vec<VatChoice>,
   account_changes: Vec<AccountChange>,
   created: Created,
 }
@@ -49,12 +71,15 @@ async fn index(
   // Then get all the account changes in the transaction
   let account_changes = sqlx::query_as!(AccountChange,
     "
-SELECT AccountChanges.id, Accounts.name AS account_name, AccountChanges.message,
-    AccountChanges.day AS date, AccountChanges.amount
+SELECT AccountChanges.id, concat_ws(' ', Accounts.number, Accounts.name) AS \"account_name!\", AccountChanges.message,
+    AccountChanges.day AS date, AccountChanges.amount,
+    AccountChanges.original_amount, Accounts.currency,
+    VatCodes.name AS \"vat_code?\", AccountChanges.vat_role
   FROM AccountChanges
   INNER JOIN Accounts ON AccountChanges.account_id = Accounts.id
+  LEFT JOIN VatCodes ON AccountChanges.vat_code_id = VatCodes.id
 WHERE AccountChanges.transaction_id = $1
-ORDER BY AccountChanges.day
+ORDER BY AccountChanges.day, AccountChanges.id
     ",
     transaction.id,
   )
@@ -64,9 +89,10 @@ ORDER BY AccountChanges.day
   // We need all the accounts (by type) for the form creating account changes
   let accounts = sqlx::query_as!(Account,
     "
-SELECT Accounts.id, Accounts.name, Accounts.type AS t
+SELECT Accounts.id, concat_ws(' ', Accounts.number, Accounts.name) AS \"name!\", Accounts.type AS t, Accounts.currency
   FROM Accounts
 WHERE Accounts.bookkeeping_id = $1
+ORDER BY Accounts.number, Accounts.name
     ",
     bookkeeping.id,
   )
@@ -81,6 +107,13 @@ WHERE Accounts.bookkeeping_id = $1
       None => { accounts_by_type.insert(account.t.clone(), vec![account]); },
     }
   }
+  let vat_codes = sqlx::query_as!(VatChoice,
+    "SELECT id, name FROM VatCodes WHERE bookkeeping_id = $1 ORDER BY name",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
 
   html(Index{
     bookkeeping_name: bookkeeping.name,
@@ -88,11 +121,153 @@ WHERE Accounts.bookkeeping_id = $1
     name: transaction.name,
     date: transaction.date,
     sum: transaction.sum,
+    posted: transaction.posted,
     account_changes,
     created,
     accounts_by_type,
+    vat_codes,
+  }.render()?)
+}
+async fn update_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  transaction: TransactionSummary,
+) -> Result<Response, Error> {
+  // Takes the same fields as when creating the transaction
+  let update: NewTransaction = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  if transaction.posted {
+    return Err(ClientError::TransactionPosted(transaction.id).into());
+  }
+  let mut db_transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "UPDATE Transactions SET name = $2, day = $3 WHERE id = $1",
+    transaction.id,
+    update.name,
+    update.date,
+  )
+    .execute(&mut *db_transaction)
+    .await?
+  ;
+  db_transaction.commit().await?;
+  see_other("./")
+}
+async fn delete_post(
+  state: &'static State,
+  session: SessionData,
+  transaction: TransactionSummary,
+) -> Result<Response, Error> {
+  if transaction.posted {
+    return Err(ClientError::TransactionPosted(transaction.id).into());
+  }
+  let mut db_transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "DELETE FROM Transactions WHERE id = $1",
+    transaction.id,
+  )
+    .execute(&mut *db_transaction)
+    .await
+    .map_err(|e| -> Error { match e {
+      sqlx::Error::Database(ref dbe) if dbe.is_foreign_key_violation() => {
+        Error::still_referenced(&format!("The transaction {}", transaction.name), dbe.table())
+      },
+      e => e.into(),
+    }})
+    ?
+  ;
+  db_transaction.commit().await?;
+  see_other("../../")
+}
+
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/groupings/id/transactions/id/history.html")]
+struct History {
+  name: String,
+  entries: Vec<AuditEntry>,
+}
+// The changes to the transaction and its account changes
+async fn history(
+  state: &'static State,
+  transaction: TransactionSummary,
+) -> Result<Response, Error> {
+  let entries = sqlx::query_as!(AuditEntry,
+    "
+SELECT Users.email AS \"user?\",
+    to_char(AuditLog.changed_at, 'YYYY-MM-DD HH24:MI:SS TZ') AS \"changed_at!\",
+    AuditLog.entity, AuditLog.entity_id, AuditLog.operation,
+    audit_fields_as_text(AuditLog.before) AS before,
+    audit_fields_as_text(AuditLog.after) AS after
+  FROM AuditLog
+  LEFT JOIN Users ON AuditLog.user_id = Users.id
+WHERE (AuditLog.entity = 'transactions' AND AuditLog.entity_id = $1)
+  OR (AuditLog.entity = 'accountchanges'
+    AND (AuditLog.before->>'transaction_id')::BIGINT = $1)
+  OR (AuditLog.entity = 'accountchanges'
+    AND (AuditLog.after->>'transaction_id')::BIGINT = $1)
+ORDER BY AuditLog.id DESC
+    ",
+    transaction.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  html(History{
+    name: transaction.name,
+    entries,
   }.render()?)
 }
+
+// Post a draft once it balances, or turn a posted transaction back into a
+// draft so it can be changed
+async fn set_posted(
+  state: &'static State,
+  session: SessionData,
+  transaction: TransactionSummary,
+  posted: bool,
+) -> Result<Response, Error> {
+  let mut db_transaction = begin_as(state, &session).await?;
+  // Lock the transaction, so no account changes are added while checking
+  sqlx::query!(
+    "SELECT id FROM Transactions WHERE id = $1 FOR UPDATE",
+    transaction.id,
+  )
+    .fetch_one(&mut *db_transaction)
+    .await?
+  ;
+  if posted {
+    let changes = sqlx::query!(
+      "
+SELECT COUNT(*) AS \"count!\", COALESCE(SUM(amount), 0) AS \"sum!\"
+  FROM AccountChanges
+WHERE transaction_id = $1
+      ",
+      transaction.id,
+    )
+      .fetch_one(&mut *db_transaction)
+      .await?
+    ;
+    // Empty transactions balance trivially, but there is nothing to post
+    if changes.count == 0 {
+      return Err(ClientError::TransactionEmpty(transaction.id).into());
+    }
+    if !changes.sum.is_zero() {
+      return Err(ClientError::TransactionUnbalanced(transaction.id).into());
+    }
+  }
+  sqlx::query!(
+    "UPDATE Transactions SET posted = $2 WHERE id = $1",
+    transaction.id,
+    posted,
+  )
+    .execute(&mut *db_transaction)
+    .await?
+  ;
+  db_transaction.commit().await?;
+  see_other("./")
+}
 pub async fn route(
   state: &'static State,
   req: Request,
@@ -106,14 +281,15 @@ pub async fn route(
   let transaction = sqlx::query_as!(TransactionSummary,
     "
 SELECT Transactions.id, Transactions.name, Transactions.day AS \"date\",
-    COALESCE(SUM(AccountChanges.amount), 0) AS \"sum!\"
+    COALESCE(SUM(AccountChanges.amount), 0) AS \"sum!\", Transactions.posted
   FROM Transactions
   LEFT JOIN AccountChanges ON AccountChanges.transaction_id = Transactions.id
-WHERE Transactions.id = $1
-GROUP BY Transactions.id, Transactions.name, Transactions.day
+WHERE Transactions.id = $1 AND Transactions.grouping_id = $2
+GROUP BY Transactions.id, Transactions.name, Transactions.day, Transactions.posted
 ORDER BY Transactions.day
     ",
     transaction_id,
+    grouping.id,
   )
     .fetch_optional(&state.db)
     .await?
@@ -143,6 +319,26 @@ ORDER BY Transactions.day
       grouping,
       transaction,
     ).await,
+    Some("history") => {
+      verify_method_path_end(&path_vec, &req, &Method::GET)?;
+      history(state, transaction).await
+    },
+    Some("update") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      update_post(state, req, session, transaction).await
+    },
+    Some("delete") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      delete_post(state, session, transaction).await
+    },
+    Some("post") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      set_posted(state, session, transaction, true).await
+    },
+    Some("unpost") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      set_posted(state, session, transaction, false).await
+    },
     _ => Err(Error::path_not_found(&req)),
   }
 }
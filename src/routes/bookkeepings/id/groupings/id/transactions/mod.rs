@@ -20,6 +20,7 @@ async fn index_post(
     state.max_content_len,
   ).await?;
   // Insert into database
+  let mut transaction = begin_as(state, &session).await?;
   let created = sqlx::query!(
     "
 INSERT INTO Transactions(name, day, grouping_id) VALUES($1,$2,$3) RETURNING id
@@ -28,13 +29,239 @@ INSERT INTO Transactions(name, day, grouping_id) VALUES($1,$2,$3) RETURNING id
     new_transaction.date,
     grouping.id,
   )
-    .fetch_one(&state.db)
+    .fetch_one(&mut *transaction)
     .await?
     .id
   ;
+  transaction.commit().await?;
   // Redirect to parent with created as query param
   see_other(&format!("../?new_transaction={created}"))
 }
+
+#[derive(Debug)]
+struct Account{
+  id: i64,
+  name: String,
+  t: String,
+  currency: Option<String>,
+}
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/groupings/id/transactions/split.html")]
+struct Split {
+  grouping_name: String,
+  bookkeeping_name: String,
+  base_currency: String,
+  accounts_by_type: std::collections::HashMap<String, Vec<Account>>,
+  vat_codes: Vec<VatChoice>,
+  lines: usize,
+}
+#[derive(Debug)]
+struct VatChoice {
+  id: i64,
+  name: String,
+  rate: Decimal,
+  // VAT paid to the seller, the only kind whose lines change the balance
+  domestic: bool,
+}
+#[derive(Debug, Deserialize)]
+struct SplitQuery {
+  // How many empty lines to show, for browsers without scripts
+  lines: Option<usize>,
+}
+// A form for creating a transaction with all its account changes at once
+async fn split(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+  grouping: Grouping,
+  query: SplitQuery,
+) -> Result<Response, Error> {
+  let accounts = sqlx::query_as!(Account,
+    "
+SELECT Accounts.id, concat_ws(' ', Accounts.number, Accounts.name) AS \"name!\", Accounts.type AS t, Accounts.currency
+  FROM Accounts
+WHERE Accounts.bookkeeping_id = $1
+ORDER BY Accounts.number, Accounts.name
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let mut accounts_by_type = std::collections::HashMap::<String,Vec<Account>>::new();
+  for account in accounts {
+    match accounts_by_type.get_mut(&account.t) {
+      Some(x) => x.push(account),
+      None => { accounts_by_type.insert(account.t.clone(), vec![account]); },
+    }
+  }
+  let vat_codes = sqlx::query_as!(VatChoice,
+    "
+SELECT id, name, rate, kind = 'Domestic' AS \"domestic!\"
+  FROM VatCodes
+WHERE bookkeeping_id = $1
+ORDER BY name
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  html(Split{
+    grouping_name: grouping.name,
+    bookkeeping_name: bookkeeping.name,
+    base_currency: bookkeeping.base_currency,
+    accounts_by_type,
+    vat_codes,
+    lines: query.lines.unwrap_or(4).clamp(2, 100),
+  }.render()?)
+}
+
+// One account change of a split transaction, as the fields sent for it
+#[derive(Debug, Default)]
+struct SplitLine {
+  account: String,
+  // Optionally the number of the account, which then takes precedence
+  account_number: String,
+  message: String,
+  // In the account's currency, lines left without an amount are skipped
+  amount: String,
+  // Optionally the amount in the base currency, as for single account changes
+  base_amount: String,
+  vat_code: String,
+  // Only sent if checked
+  includes_vat: String,
+}
+impl SplitLine {
+  // Only accounts of this bookkeeping can be used
+  async fn account_id(
+    &self,
+    transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+    bookkeeping_id: i64,
+  ) -> Result<i64, Error> {
+    if let Some(number) = accounts::parse_account_number(&self.account_number)? {
+      return accounts::account_by_number(transaction, bookkeeping_id, number).await;
+    }
+    let account_id: i64 = self.account.parse()?;
+    sqlx::query!(
+      "SELECT id FROM Accounts WHERE id = $1 AND bookkeeping_id = $2",
+      account_id,
+      bookkeeping_id,
+    )
+      .fetch_optional(&mut **transaction)
+      .await?
+      .ok_or(ClientError::AccountNotFound(account_id))?
+    ;
+    Ok(account_id)
+  }
+  fn amount(&self) -> Result<Decimal, Error> {
+    parse_amount("amount", &self.amount)
+  }
+  fn base_amount(&self) -> Result<Option<Decimal>, Error> {
+    match self.base_amount.as_str() {
+      "" => Ok(None),
+      amount => parse_amount("base_amount", amount).map(Some),
+    }
+  }
+}
+fn parse_amount(field: &str, value: &str) -> Result<Decimal, Error> {
+  value.parse()
+    .map_err(|e: rust_decimal::Error| {
+      ClientError::InvalidUrlEncoding(format!("{}: {}", field, e)).into()
+    })
+}
+#[derive(Debug, Default)]
+struct NewSplitTransaction {
+  name: String,
+  date: String,
+  lines: Vec<SplitLine>,
+}
+impl NewSplitTransaction {
+  // The fields of each line are repeated, which serde_urlencoded can't put in
+  // lists, so they are gathered in order with every account field starting a
+  // new line
+  fn from_pairs(pairs: Vec<(String, String)>) -> Result<Self, Error> {
+    let mut form = Self::default();
+    for (field, value) in pairs {
+      if field == "account" {
+        form.lines.push(SplitLine::default());
+      }
+      match (field.as_str(), form.lines.last_mut()) {
+        ("name", _) => form.name = value,
+        ("date", _) => form.date = value,
+        ("account", Some(line)) => line.account = value,
+        ("account_number", Some(line)) => line.account_number = value,
+        ("message", Some(line)) => line.message = value,
+        ("amount", Some(line)) => line.amount = value,
+        ("base_amount", Some(line)) => line.base_amount = value,
+        ("vat_code", Some(line)) => line.vat_code = value,
+        ("includes_vat", Some(line)) => line.includes_vat = value,
+        (field, _) => return Err(ClientError::InvalidUrlEncoding(format!(
+          "unexpected field {}",
+          field,
+        )).into()),
+      }
+    }
+    Ok(form)
+  }
+}
+// Create the transaction and its account changes in one database transaction,
+// so nothing is created if any line is wrong
+async fn split_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  grouping: Grouping,
+) -> Result<Response, Error> {
+  let pairs: Vec<(String, String)> = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let new_transaction = NewSplitTransaction::from_pairs(pairs)?;
+  let date = parse_form_date("date", &new_transaction.date)?
+    .ok_or(ClientError::InvalidUrlEncoding("date: missing".to_owned()))?
+  ;
+  // Lines without an amount are left out, so some line must have one
+  let lines: Vec<&SplitLine> = new_transaction.lines.iter()
+    .filter(|l| !l.amount.is_empty())
+    .collect()
+  ;
+  if lines.is_empty() {
+    return Err(ClientError::NothingChosen.into());
+  }
+  let mut transaction = begin_as(state, &session).await?;
+  let created = sqlx::query!(
+    "
+INSERT INTO Transactions(name, day, grouping_id) VALUES($1,$2,$3) RETURNING id
+    ",
+    new_transaction.name,
+    date,
+    grouping.id,
+  )
+    .fetch_one(&mut *transaction)
+    .await?
+    .id
+  ;
+  for line in lines {
+    let account_id = line.account_id(&mut transaction, bookkeeping.id).await?;
+    vat::insert_account_change(
+      &mut transaction,
+      bookkeeping.id,
+      &vat::NewChange{
+        transaction_id: created,
+        account_id,
+        day: date,
+        message: &line.message,
+        amount: line.amount()?,
+        base_amount: line.base_amount()?,
+        vat: vat::parse_vat(&line.vat_code, &line.includes_vat)?,
+      },
+    ).await?;
+  }
+  transaction.commit().await?;
+  see_other(&format!("../?new_transaction={created}"))
+}
+
 pub async fn route(
   state: &'static State,
   req: Request,
@@ -55,6 +282,17 @@ pub async fn route(
         grouping,
       ).await
     },
+    Some("split") => {
+      verify_path_end(&path_vec, &req)?;
+      match req.method() {
+        &Method::GET => {
+          let query: SplitQuery = parse_query(&req)?;
+          split(state, bookkeeping, grouping, query).await
+        },
+        &Method::POST => split_post(state, req, session, bookkeeping, grouping).await,
+        _ => Err(Error::method_not_found(&req)),
+      }
+    },
     Some(id) => id::route(state, req, path_vec, session, bookkeeping, grouping, id.parse()?).await,
     _ => Err(Error::path_not_found(&req)),
   }
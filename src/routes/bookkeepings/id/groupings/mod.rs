@@ -6,6 +6,18 @@ mod id;
 struct NewGrouping {
   name: String,
 }
+// Both for renaming and creating, since a grouping is only its name
+fn map_name_conflict(e: sqlx::Error, name: &str) -> Error {
+  match e {
+    sqlx::Error::Database(ref dbe) if dbe.is_unique_violation() => {
+      ClientError::AlreadyExists(format!(
+        "A grouping by name {} already exists in this bookkeeping.",
+        name,
+      )).into()
+    },
+    e => e.into(),
+  }
+}
 async fn index_post(
   state: &'static State,
   mut req: Request,
@@ -18,26 +30,20 @@ async fn index_post(
     state.max_content_len,
   ).await?;
   // Insert into database
+  let mut transaction = begin_as(state, &session).await?;
   let created = sqlx::query!(
     "INSERT INTO Groupings(name, bookkeeping_id) VALUES($1, $2) RETURNING id",
     new_grouping.name,
     bookkeeping_id,
   )
-    .fetch_one(&state.db)
+    .fetch_one(&mut *transaction)
     .await
     // Convert name conflict to readable user error
-    .map_err(|e| -> Error { match e {
-      sqlx::Error::Database(ref dbe) if dbe.is_unique_violation() => {
-        ClientError::AlreadyExists(format!(
-          "A grouping by name {} already exists in this bookkeeping.",
-          new_grouping.name,
-        )).into()
-      },
-      e => e.into(),
-    }})
+    .map_err(|e| map_name_conflict(e, &new_grouping.name))
     ?
     .id
   ;
+  transaction.commit().await?;
   // Redirect to parent with newly created grouping marked
   see_other(&format!("../?new_grouping={created}"))
 }
@@ -0,0 +1,170 @@
+use super::*;
+
+// One row of the audit log, as shown on the history pages
+#[derive(Debug)]
+pub struct AuditEntry {
+  pub user: Option<String>,
+  pub changed_at: String,
+  pub entity: String,
+  pub entity_id: i64,
+  pub operation: String,
+  pub before: Option<serde_json::Value>,
+  pub after: Option<serde_json::Value>,
+}
+// Render a JSON value without the quotes around strings
+fn show(value: Option<&serde_json::Value>) -> String {
+  match value {
+    None | Some(serde_json::Value::Null) => String::new(),
+    Some(serde_json::Value::String(s)) => s.clone(),
+    Some(v) => v.to_string(),
+  }
+}
+impl AuditEntry {
+  pub fn entity_label(&self) -> &str {
+    entity_label(&self.entity)
+  }
+  // The fields that changed, with their values before and after
+  // Values are logged as text, so they are shown as stored
+  pub fn changes(&self) -> Vec<(String, String, String)> {
+    let empty = serde_json::Map::new();
+    let before = self.before.as_ref().and_then(|b| b.as_object()).unwrap_or(&empty);
+    let after = self.after.as_ref().and_then(|a| a.as_object()).unwrap_or(&empty);
+    let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+    fields.sort();
+    fields.dedup();
+    fields.into_iter()
+      .map(|f| (f.clone(), show(before.get(f)), show(after.get(f))))
+      .filter(|(_, before, after)| before != after)
+      .collect()
+  }
+}
+// The audit log names entities by table, which is written without spaces
+pub fn entity_label(entity: &str) -> &str {
+  match entity {
+    "bookkeepings" => "Bookkeeping",
+    "usersbookkeepingsaccess" => "Access",
+    "accounts" => "Account",
+    "accountsubtypes" => "Account subtype",
+    "groupings" => "Grouping",
+    "transactions" => "Transaction",
+    "accountchanges" => "Account change",
+    "accountimportprofiles" => "Import profile",
+    "importedaccountchanges" => "Imported account change",
+    "importrules" => "Import rule",
+    "importbatches" => "Import batch",
+    "statementcheckpoints" => "Statement checkpoint",
+    "exchangerates" => "Exchange rate",
+    "recurringtransactions" => "Recurring transaction",
+    "recurringtransactionlines" => "Recurring transaction line",
+    "vatcodes" => "VAT code",
+    other => other,
+  }
+}
+
+// How many entries to show at most, the log grows without bound
+const MAX_ENTRIES: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+  // Empty when not filtering, as sent by the form
+  #[serde(default)]
+  entity: String,
+  #[serde(default)]
+  user: String,
+}
+#[derive(Debug)]
+struct Entity {
+  name: String,
+}
+impl Entity {
+  fn label(&self) -> &str {
+    entity_label(&self.name)
+  }
+}
+#[derive(Debug)]
+struct User {
+  id: i64,
+  email: String,
+}
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/history/index.html")]
+struct Index {
+  bookkeeping_name: String,
+  entities: Vec<Entity>,
+  users: Vec<User>,
+  entity: String,
+  user: Option<i64>,
+  entries: Vec<AuditEntry>,
+  max_entries: i64,
+}
+impl Index {
+  fn filters_user(&self, id: &i64) -> bool {
+    self.user == Some(*id)
+  }
+}
+
+pub async fn index(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+  query: HistoryQuery,
+) -> Result<Response, Error> {
+  let user: Option<i64> = match query.user.as_str() {
+    "" => None,
+    user => Some(user.parse()?),
+  };
+  let entries = sqlx::query_as!(AuditEntry,
+    "
+SELECT Users.email AS \"user?\",
+    to_char(AuditLog.changed_at, 'YYYY-MM-DD HH24:MI:SS TZ') AS \"changed_at!\",
+    AuditLog.entity, AuditLog.entity_id, AuditLog.operation,
+    audit_fields_as_text(AuditLog.before) AS before,
+    audit_fields_as_text(AuditLog.after) AS after
+  FROM AuditLog
+  LEFT JOIN Users ON AuditLog.user_id = Users.id
+WHERE AuditLog.bookkeeping_id = $1
+  AND ($2 = '' OR AuditLog.entity = $2)
+  AND ($3::BIGINT IS NULL OR AuditLog.user_id = $3)
+ORDER BY AuditLog.id DESC
+LIMIT $4
+    ",
+    bookkeeping.id,
+    query.entity,
+    user,
+    MAX_ENTRIES,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  // What can be filtered on is what is in the log
+  let entities = sqlx::query_as!(Entity,
+    "
+SELECT DISTINCT entity AS name FROM AuditLog
+WHERE bookkeeping_id = $1
+ORDER BY entity
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let users = sqlx::query_as!(User,
+    "
+SELECT Users.id, Users.email FROM Users
+WHERE Users.id IN (SELECT user_id FROM AuditLog WHERE bookkeeping_id = $1)
+ORDER BY Users.email
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  html(Index{
+    bookkeeping_name: bookkeeping.name,
+    entities,
+    users,
+    entity: query.entity,
+    user,
+    entries,
+    max_entries: MAX_ENTRIES,
+  }.render()?)
+}
@@ -0,0 +1,170 @@
+use super::*;
+
+#[derive(Debug)]
+pub struct Batch {
+  id: i64,
+  file_name: String,
+  file_hash: String,
+  uploader: String,
+  uploaded_at: String,
+}
+#[derive(Debug)]
+struct BatchLine {
+  account_name: String,
+  day: Date,
+  amount: Decimal,
+  other_data: Option<serde_json::Value>,
+  // Set when the line has been migrated
+  grouping_id: Option<i64>,
+  transaction_id: Option<i64>,
+}
+// A transaction created from lines in the batch
+#[derive(Debug)]
+struct BatchTransaction {
+  id: i64,
+  grouping_id: i64,
+  name: String,
+  day: Date,
+}
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/imported_account_changes/batches/id/index.html")]
+struct Index {
+  bookkeeping_name: String,
+  batch: Batch,
+  lines: Vec<BatchLine>,
+  transactions: Vec<BatchTransaction>,
+}
+
+async fn index(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+  batch: Batch,
+) -> Result<Response, Error> {
+  let lines = sqlx::query_as!(BatchLine,
+    "
+SELECT concat_ws(' ', Accounts.number, Accounts.name) AS \"account_name!\", ImportedAccountChanges.day,
+    ImportedAccountChanges.amount, ImportedAccountChanges.other_data,
+    Transactions.grouping_id AS \"grouping_id?\",
+    Transactions.id AS \"transaction_id?\"
+  FROM ImportedAccountChanges
+  INNER JOIN Accounts ON ImportedAccountChanges.account_id = Accounts.id
+  LEFT JOIN AccountChanges
+    ON ImportedAccountChanges.account_change_id = AccountChanges.id
+  LEFT JOIN Transactions ON AccountChanges.transaction_id = Transactions.id
+WHERE ImportedAccountChanges.batch_id = $1
+ORDER BY ImportedAccountChanges.day, ImportedAccountChanges.id
+    ",
+    batch.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let transactions = sqlx::query_as!(BatchTransaction,
+    "
+SELECT DISTINCT Transactions.id, Transactions.grouping_id, Transactions.name,
+    Transactions.day
+  FROM ImportedAccountChanges
+  INNER JOIN AccountChanges
+    ON ImportedAccountChanges.account_change_id = AccountChanges.id
+  INNER JOIN Transactions ON AccountChanges.transaction_id = Transactions.id
+WHERE ImportedAccountChanges.batch_id = $1
+ORDER BY Transactions.day, Transactions.id
+    ",
+    batch.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  html(Index{
+    bookkeeping_name: bookkeeping.name,
+    batch,
+    lines,
+    transactions,
+  }.render()?)
+}
+
+// Undo the import, which is only possible while no line is in a transaction
+async fn delete_post(
+  state: &'static State,
+  session: SessionData,
+  batch: Batch,
+) -> Result<Response, Error> {
+  let mut transaction = begin_as(state, &session).await?;
+  // Lock the batch's lines, so none can be migrated while deleting
+  let migrated = sqlx::query!(
+    "
+SELECT id FROM ImportedAccountChanges
+WHERE batch_id = $1 AND account_change_id IS NOT NULL
+FOR UPDATE
+    ",
+    batch.id,
+  )
+    .fetch_optional(&mut *transaction)
+    .await?
+  ;
+  if migrated.is_some() {
+    return Err(ClientError::BatchPartlyMigrated(batch.id).into());
+  }
+  sqlx::query!(
+    "DELETE FROM ImportedAccountChanges WHERE batch_id = $1",
+    batch.id,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  sqlx::query!(
+    "DELETE FROM StatementCheckpoints WHERE batch_id = $1",
+    batch.id,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  sqlx::query!(
+    "DELETE FROM ImportBatches WHERE id = $1",
+    batch.id,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  see_other("../../")
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  batch_id: i64,
+) -> Result<Response, Error> {
+  // Get the batch, verifying that it belongs to this bookkeeping
+  let batch = sqlx::query_as!(Batch,
+    "
+SELECT ImportBatches.id, ImportBatches.file_name, ImportBatches.file_hash,
+    Users.email AS uploader,
+    to_char(ImportBatches.uploaded_at, 'YYYY-MM-DD HH24:MI TZ') AS \"uploaded_at!\"
+  FROM ImportBatches
+  INNER JOIN Users ON ImportBatches.uploader_id = Users.id
+WHERE ImportBatches.id = $1 AND ImportBatches.bookkeeping_id = $2
+    ",
+    batch_id,
+    bookkeeping.id,
+  )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::path_not_found(&req))?
+  ;
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    Some("") => {
+      verify_method_path_end(&path_vec, &req, &Method::GET)?;
+      index(state, bookkeeping, batch).await
+    },
+    Some("delete") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      delete_post(state, session, batch).await
+    },
+    _ => Err(Error::path_not_found(&req)),
+  }
+}
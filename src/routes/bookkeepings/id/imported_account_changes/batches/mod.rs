@@ -0,0 +1,18 @@
+use super::*;
+
+mod id;
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    // Batches are listed on the import page, so there is no index here
+    Some("") => Err(Error::path_not_found(&req)),
+    Some(id) => id::route(state, req, path_vec, session, bookkeeping, id.parse()?).await,
+  }
+}
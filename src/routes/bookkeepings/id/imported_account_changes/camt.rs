@@ -0,0 +1,373 @@
+use super::*;
+
+use quick_xml::events::Event;
+
+// Parsing of ISO 20022 camt.053 (end of day statement) and camt.054 (debit and
+// credit notification) files. A file can contain several statements, each for
+// the account given by IBAN.
+
+// One statement/notification in a camt file
+#[derive(Debug)]
+pub struct Statement {
+  pub iban: Option<String>,
+  pub lines: Vec<ImportLine>,
+  // The closing booked balance (CLBD), only given in camt.053
+  pub closing_balance: Option<(Date, Decimal)>,
+}
+
+// Check if the given file looks like a camt.053/camt.054 file
+pub fn is_camt(data: &[u8]) -> bool {
+  String::from_utf8_lossy(&data[..data.len().min(2048)])
+    .contains("urn:iso:std:iso:20022:tech:xsd:camt.05")
+}
+
+// Normalise an IBAN the way it is stored on Accounts
+fn normalize_iban(iban: &str) -> String {
+  iban.chars()
+    .filter(|c| !c.is_whitespace())
+    .collect::<String>()
+    .to_uppercase()
+}
+
+// Dates are given either as Dt (a date) or DtTm (a date and time)
+fn parse_date(raw: &str) -> Result<Date, String> {
+  Date::parse(
+    raw.get(..10).unwrap_or(raw),
+    &time::format_description::well_known::Iso8601::DATE,
+  )
+    .map_err(|e| format!("Invalid date {raw}: {e}"))
+}
+// Amounts are always positive, the sign is given by CdtDbtInd
+fn parse_amount(raw: &str, credit_debit: Option<&str>) -> Result<Decimal, String> {
+  let amount: Decimal = raw.trim().parse()
+    .map_err(|e: rust_decimal::Error| format!("Invalid amount {raw}: {e}"))?
+  ;
+  match credit_debit {
+    Some("CRDT") => Ok(amount),
+    Some("DBIT") => Ok(-amount),
+    other => Err(format!("Invalid CdtDbtInd {}", other.unwrap_or("(missing)"))),
+  }
+}
+
+// The data we collect from a Ntry before converting it into an ImportLine
+#[derive(Debug, Default)]
+struct Entry {
+  amount: Option<String>,
+  credit_debit: Option<String>,
+  status: Option<String>,
+  booking_date: Option<String>,
+  value_date: Option<String>,
+  account_servicer_reference: Option<String>,
+  end_to_end_ids: Vec<String>,
+  remittance_info: Vec<String>,
+  additional_info: Option<String>,
+  debtor: Option<String>,
+  creditor: Option<String>,
+}
+impl Entry {
+  fn into_line(self, line: usize) -> Result<ImportLine, Error> {
+    let invalid = |e: String| Error::invalid_import_file(line, &e);
+    let day = parse_date(
+      self.booking_date.as_ref()
+        .or(self.value_date.as_ref())
+        .ok_or(invalid("Entry without booking date".to_owned()))?
+    ).map_err(invalid)?;
+    let amount = parse_amount(
+      self.amount.as_ref().ok_or(invalid("Entry without amount".to_owned()))?,
+      self.credit_debit.as_deref(),
+    ).map_err(invalid)?;
+
+    let mut other_data = serde_json::Map::new();
+    let remittance_info = if self.remittance_info.is_empty() {
+      self.additional_info
+    } else {
+      Some(self.remittance_info.join(" "))
+    };
+    // The other party is the creditor for payments and the debtor for deposits
+    let counterparty = if amount.is_sign_negative() {
+      self.creditor
+    } else {
+      self.debtor
+    };
+    let end_to_end_id = if self.end_to_end_ids.is_empty() {
+      None
+    } else {
+      Some(self.end_to_end_ids.join(", "))
+    };
+    for (key, value) in [
+      ("remittance_info", remittance_info),
+      ("end_to_end_id", end_to_end_id),
+      ("counterparty", counterparty),
+      ("account_servicer_reference", self.account_servicer_reference),
+    ] {
+      if let Some(value) = value {
+        other_data.insert(key.to_owned(), value.into());
+      }
+    }
+    Ok(ImportLine{
+      day,
+      amount,
+      other_data: serde_json::Value::Object(other_data),
+    })
+  }
+}
+
+// Parse a camt.053/camt.054 file into its statements
+pub fn parse(
+  data: &[u8],
+) -> Result<Vec<Statement>, Error> {
+  let mut reader = quick_xml::Reader::from_reader(data);
+  reader.trim_text(true);
+  let mut buf = Vec::new();
+
+  // Local names of the elements we are within
+  let mut path: Vec<String> = Vec::new();
+  let mut statements = Vec::new();
+  let mut statement: Option<Statement> = None;
+  let mut entry: Option<Entry> = None;
+  // Type, amount, sign and date of the Bal element we are within
+  let mut balance: [Option<String>; 4] = Default::default();
+  loop {
+    let event = reader.read_event_into(&mut buf)
+      .map_err(|e| Error::invalid_import_file(
+        // No line numbers from the XML reader, so we give the entry instead
+        statement.as_ref().map(|s| s.lines.len() + 1).unwrap_or(0),
+        &e.to_string(),
+      ))?
+    ;
+    let (start, end, text) = match event {
+      Event::Start(ref e) => (Some(e.local_name()), None, None),
+      Event::Empty(ref e) => (Some(e.local_name()), Some(()), None),
+      Event::End(_) => (None, Some(()), None),
+      Event::Text(ref t) => (None, None, Some(
+        t.unescape()
+          .map_err(|e| Error::invalid_import_file(0, &e.to_string()))?
+          .into_owned()
+      )),
+      Event::CData(ref t) => (None, None, Some(
+        String::from_utf8_lossy(t).into_owned()
+      )),
+      Event::Eof => break,
+      _ => (None, None, None),
+    };
+
+    if let Some(name) = start {
+      let name = String::from_utf8_lossy(name.as_ref()).into_owned();
+      match (path.len(), name.as_str()) {
+        // Document/BkToCstmrStmt/Stmt or Document/BkToCstmrDbtCdtNtfctn/Ntfctn
+        (2, "Stmt" | "Ntfctn") => {
+          statement = Some(Statement{
+            iban: None,
+            lines: Vec::new(),
+            closing_balance: None,
+          });
+        },
+        (3, "Ntry") => entry = Some(Entry::default()),
+        (3, "Bal") => balance = Default::default(),
+        _ => (),
+      }
+      path.push(name);
+    }
+
+    if let (Some(text), Some(s)) = (text, statement.as_mut()) {
+      let relative: Vec<&str> = path.iter().skip(3).map(|p| p.as_str()).collect();
+      match (relative.as_slice(), entry.as_mut()) {
+        (["Acct", "Id", "IBAN"], _) => s.iban = Some(normalize_iban(&text)),
+        (["Bal", "Tp", "CdOrPrtry", "Cd"], _) => balance[0] = Some(text),
+        (["Bal", "Amt"], _) => balance[1] = Some(text),
+        (["Bal", "CdtDbtInd"], _) => balance[2] = Some(text),
+        (["Bal", "Dt", "Dt" | "DtTm"], _) => balance[3] = Some(text),
+        (["Ntry", rest @ ..], Some(e)) => match rest {
+          ["Amt"] => e.amount = Some(text),
+          ["CdtDbtInd"] => e.credit_debit = Some(text),
+          // Status is a code from version 8, and directly given before that
+          ["Sts"] | ["Sts", "Cd"] => e.status = Some(text),
+          ["BookgDt", "Dt" | "DtTm"] => e.booking_date = Some(text),
+          ["ValDt", "Dt" | "DtTm"] => e.value_date = Some(text),
+          ["AcctSvcrRef"] => e.account_servicer_reference = Some(text),
+          ["AddtlNtryInf"] => e.additional_info = Some(text),
+          // NOTPROVIDED is the standard placeholder for a missing id
+          ["NtryDtls", "TxDtls", "Refs", "EndToEndId"] if text != "NOTPROVIDED" => {
+            e.end_to_end_ids.push(text)
+          },
+          ["NtryDtls", "TxDtls", "RmtInf", "Ustrd"] => e.remittance_info.push(text),
+          ["NtryDtls", "TxDtls", "RmtInf", "Strd", "CdtrRefInf", "Ref"] => {
+            e.remittance_info.push(text)
+          },
+          // The name is given directly in the party before version 8
+          ["NtryDtls", "TxDtls", "RltdPties", "Dbtr", "Nm"]
+            | ["NtryDtls", "TxDtls", "RltdPties", "Dbtr", "Pty", "Nm"]
+            => { e.debtor.get_or_insert(text); },
+          ["NtryDtls", "TxDtls", "RltdPties", "Cdtr", "Nm"]
+            | ["NtryDtls", "TxDtls", "RltdPties", "Cdtr", "Pty", "Nm"]
+            => { e.creditor.get_or_insert(text); },
+          _ => (),
+        },
+        _ => (),
+      }
+    }
+
+    if end.is_some() {
+      let name = path.pop().unwrap_or_default();
+      match (path.len(), name.as_str()) {
+        (2, "Stmt" | "Ntfctn") => {
+          if let Some(s) = statement.take() {
+            statements.push(s);
+          }
+        },
+        (3, "Ntry") => {
+          if let (Some(e), Some(s)) = (entry.take(), statement.as_mut()) {
+            // Only booked entries have happened, pending ones may still change
+            if e.status.as_deref().unwrap_or("BOOK") == "BOOK" {
+              s.lines.push(e.into_line(s.lines.len() + 1)?);
+            }
+          }
+        },
+        (3, "Bal") => {
+          if let (Some(s), [Some(t), Some(amount), Some(sign), Some(date)]) =
+            (statement.as_mut(), &balance)
+          {
+            // Only the closing booked balance says what the account holds
+            if t == "CLBD" {
+              let invalid = |e: String| -> Error {
+                ClientError::InvalidImportFile(format!("Closing balance: {e}")).into()
+              };
+              s.closing_balance = Some((
+                parse_date(date).map_err(invalid)?,
+                parse_amount(amount, Some(sign)).map_err(invalid)?,
+              ));
+            }
+          }
+        },
+        _ => (),
+      }
+    }
+    buf.clear();
+  }
+  Ok(statements)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn day(year: i32, month: time::Month, day: u8) -> Date {
+    Date::from_calendar_date(year, month, day).unwrap()
+  }
+  // A camt.053 document with the given entries in one statement
+  fn statement(entries: &str) -> String {
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <BkToCstmrStmt>
+    <Stmt>
+      <Acct><Id><IBAN>se45 5000 0000 0583 9825 7466</IBAN></Id></Acct>
+      <Bal>
+        <Tp><CdOrPrtry><Cd>OPBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="SEK">5.00</Amt><CdtDbtInd>CRDT</CdtDbtInd>
+        <Dt><Dt>2026-02-28</Dt></Dt>
+      </Bal>
+      <Bal>
+        <Tp><CdOrPrtry><Cd>CLBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="SEK">120.50</Amt><CdtDbtInd>DBIT</CdtDbtInd>
+        <Dt><DtTm>2026-03-31T23:59:59</DtTm></Dt>
+      </Bal>
+      {entries}
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#)
+  }
+  const ENTRIES: &str = r#"
+      <Ntry>
+        <Amt Ccy="SEK">100.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts>BOOK</Sts>
+        <BookgDt><Dt>2026-03-02</Dt></BookgDt>
+        <AcctSvcrRef>REF1</AcctSvcrRef>
+        <NtryDtls><TxDtls>
+          <Refs><EndToEndId>NOTPROVIDED</EndToEndId></Refs>
+          <RltdPties><Dbtr><Nm>Åsa &amp; Co</Nm></Dbtr></RltdPties>
+          <RmtInf><Ustrd>Invoice 1</Ustrd><Ustrd>Invoice 2</Ustrd></RmtInf>
+        </TxDtls></NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="SEK">25.5</Amt><CdtDbtInd>DBIT</CdtDbtInd><Sts><Cd>BOOK</Cd></Sts>
+        <ValDt><DtTm>2026-03-03T10:00:00</DtTm></ValDt>
+        <AddtlNtryInf>Card purchase</AddtlNtryInf>
+        <NtryDtls><TxDtls>
+          <Refs><EndToEndId>E2E</EndToEndId></Refs>
+          <RltdPties><Cdtr><Pty><Nm>Shop</Nm></Pty></Cdtr></RltdPties>
+        </TxDtls></NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="SEK">9.00</Amt><CdtDbtInd>DBIT</CdtDbtInd><Sts>PDNG</Sts>
+        <BookgDt><Dt>2026-03-04</Dt></BookgDt>
+      </Ntry>"#;
+
+  #[test]
+  fn detects_camt() {
+    assert!(is_camt(statement("").as_bytes()));
+    assert!(!is_camt(b"<OFX></OFX>"));
+  }
+  #[test]
+  fn signs_and_booked_entries() {
+    let statements = parse(statement(ENTRIES).as_bytes()).unwrap();
+    assert_eq!(statements.len(), 1);
+    let s = &statements[0];
+    assert_eq!(s.iban.as_deref(), Some("SE4550000000058398257466"));
+    // Only the closing balance is kept, with its sign and the date of DtTm
+    assert_eq!(
+      s.closing_balance,
+      Some((day(2026, time::Month::March, 31), Decimal::new(-12050, 2))),
+    );
+    // The pending entry is left out
+    assert_eq!(s.lines.len(), 2);
+    assert_eq!(s.lines[0].day, day(2026, time::Month::March, 2));
+    assert_eq!(s.lines[0].amount, Decimal::new(10000, 2));
+    assert_eq!(s.lines[0].other_data, serde_json::json!({
+      "remittance_info": "Invoice 1 Invoice 2",
+      "counterparty": "Åsa & Co",
+      "account_servicer_reference": "REF1",
+    }));
+    // Without booking date the value date is used
+    assert_eq!(s.lines[1].day, day(2026, time::Month::March, 3));
+    assert_eq!(s.lines[1].amount, Decimal::new(-255, 1));
+    assert_eq!(s.lines[1].other_data, serde_json::json!({
+      "remittance_info": "Card purchase",
+      "end_to_end_id": "E2E",
+      "counterparty": "Shop",
+    }));
+  }
+  #[test]
+  fn notifications() {
+    let data = r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.054.001.08">
+<BkToCstmrDbtCdtNtfctn>
+  <Ntfctn><Acct><Id><IBAN>DE89370400440532013000</IBAN></Id></Acct>
+    <Ntry><Amt>1</Amt><CdtDbtInd>DBIT</CdtDbtInd><BookgDt><Dt>2026-01-01</Dt></BookgDt></Ntry>
+  </Ntfctn>
+  <Ntfctn><Acct><Id><IBAN>SE4550000000058398257466</IBAN></Id></Acct></Ntfctn>
+</BkToCstmrDbtCdtNtfctn>
+</Document>"#;
+    let statements = parse(data.as_bytes()).unwrap();
+    assert_eq!(statements.len(), 2);
+    assert_eq!(statements[0].iban.as_deref(), Some("DE89370400440532013000"));
+    assert_eq!(statements[0].lines[0].amount, Decimal::from(-1));
+    assert_eq!(statements[0].closing_balance, None);
+    assert!(statements[1].lines.is_empty());
+  }
+  #[test]
+  fn malformed_entries_are_refused() {
+    for entry in [
+      // No sign
+      "<Ntry><Amt>1</Amt><BookgDt><Dt>2026-01-01</Dt></BookgDt></Ntry>",
+      // No amount
+      "<Ntry><CdtDbtInd>CRDT</CdtDbtInd><BookgDt><Dt>2026-01-01</Dt></BookgDt></Ntry>",
+      // No date
+      "<Ntry><Amt>1</Amt><CdtDbtInd>CRDT</CdtDbtInd></Ntry>",
+      // Invalid amount and date
+      "<Ntry><Amt>1,5</Amt><CdtDbtInd>CRDT</CdtDbtInd><BookgDt><Dt>2026-01-01</Dt></BookgDt></Ntry>",
+      "<Ntry><Amt>1</Amt><CdtDbtInd>CRDT</CdtDbtInd><BookgDt><Dt>2026-02-30</Dt></BookgDt></Ntry>",
+      // Broken XML
+      "<Ntry><Amt>1</Amt></CdtDbtInd></Ntry>",
+    ] {
+      assert!(parse(statement(entry).as_bytes()).is_err(), "{entry}");
+    }
+  }
+}
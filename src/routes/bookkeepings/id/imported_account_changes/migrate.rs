@@ -0,0 +1,219 @@
+use super::*;
+
+// The form migrating imported account changes into a transaction
+#[derive(Debug)]
+struct MigrateForm {
+  // The imported account changes to migrate
+  lines: Vec<i64>,
+  grouping: i64,
+  // The account on the other side of the imported account changes
+  account: i64,
+  name: String,
+}
+impl TryFrom<Vec<(String, String)>> for MigrateForm {
+  type Error = Error;
+  // The chosen lines are given as repeated fields, so it is parsed from pairs
+  fn try_from(pairs: Vec<(String, String)>) -> Result<Self, Error> {
+    let field = |name: &str| pairs.iter()
+      .find(|(key, _)| key == name)
+      .map(|(_, value)| value.as_str())
+      .ok_or(ClientError::InvalidUrlEncoding(format!("Missing field {name}")))
+    ;
+    Ok(Self{
+      lines: pairs.iter()
+        .filter(|(key, _)| key == "line")
+        .map(|(_, value)| value.parse())
+        .collect::<Result<_, _>>()?,
+      grouping: field("grouping")?.parse()?,
+      account: field("account")?.parse()?,
+      name: field("name")?.to_owned(),
+    })
+  }
+}
+#[derive(Debug)]
+pub struct Line {
+  pub id: i64,
+  account_id: i64,
+  day: Date,
+  pub amount: Decimal,
+  pub other_data: Option<serde_json::Value>,
+  account_change_id: Option<i64>,
+}
+// The text values of other_data, to keep the gist of it on the account change
+fn message(other_data: &Option<serde_json::Value>) -> String {
+  let text = match other_data {
+    Some(serde_json::Value::Object(map)) => map.values()
+      .filter_map(|v| v.as_str())
+      .map(|v| v.trim())
+      .filter(|v| !v.is_empty())
+      .collect::<Vec<_>>()
+      .join(" "),
+    _ => String::new(),
+  };
+  text.chars().take(256).collect()
+}
+
+// Create a transaction from the chosen imported account changes, with an
+// account change for each of them and a counter change on the given account
+pub async fn migrate_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let pairs: Vec<(String, String)> = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let form: MigrateForm = pairs.try_into()?;
+  if form.lines.is_empty() {
+    return Err(ClientError::NothingChosen.into());
+  }
+  sqlx::query!(
+    "SELECT id FROM Groupings WHERE id = $1 AND bookkeeping_id = $2",
+    form.grouping,
+    bookkeeping.id,
+  )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(ClientError::GroupingNotFound(form.grouping))?
+  ;
+  verify_account(state, &bookkeeping, form.account).await?;
+
+  let mut transaction = begin_as(state, &session).await?;
+  let lines = lock_lines(&mut transaction, &bookkeeping, &form.lines).await?;
+  let transaction_id = create_transaction(
+    &mut transaction,
+    &bookkeeping,
+    &lines,
+    form.grouping,
+    form.account,
+    &form.name,
+  ).await?;
+  transaction.commit().await?;
+
+  // Show the created transaction in its grouping
+  see_other(&format!("../groupings/{}/?new_transaction={}", form.grouping, transaction_id))
+}
+
+// Get and lock the given imported account changes, so they can't be migrated
+// twice concurrently, verifying that they exist and aren't migrated already
+pub async fn lock_lines(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping: &Bookkeeping,
+  ids: &[i64],
+) -> Result<Vec<Line>, Error> {
+  let lines = sqlx::query_as!(Line,
+    "
+SELECT ImportedAccountChanges.id, ImportedAccountChanges.account_id,
+    ImportedAccountChanges.day, ImportedAccountChanges.amount,
+    ImportedAccountChanges.other_data, ImportedAccountChanges.account_change_id
+  FROM ImportedAccountChanges
+  INNER JOIN Accounts ON ImportedAccountChanges.account_id = Accounts.id
+WHERE Accounts.bookkeeping_id = $1 AND ImportedAccountChanges.id = ANY($2)
+ORDER BY ImportedAccountChanges.day, ImportedAccountChanges.id
+FOR UPDATE OF ImportedAccountChanges
+    ",
+    bookkeeping.id,
+    ids,
+  )
+    .fetch_all(&mut **transaction)
+    .await?
+  ;
+  for id in ids.iter() {
+    match lines.iter().find(|l| l.id == *id) {
+      None => return Err(ClientError::ImportedAccountChangeNotFound(*id).into()),
+      Some(Line{ account_change_id: Some(_), .. }) => {
+        return Err(ClientError::AlreadyMigrated(*id).into());
+      },
+      Some(_) => (),
+    }
+  }
+  Ok(lines)
+}
+
+// Create a transaction with an account change for each of the given lines and
+// a counter change on the given account, marking the lines as migrated
+// The lines are in the currency of their account, which is converted if needed
+pub async fn create_transaction(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping: &Bookkeeping,
+  lines: &[Line],
+  grouping_id: i64,
+  account_id: i64,
+  name: &str,
+) -> Result<i64, Error> {
+  // The transaction happened when the first of the lines did, and it balances
+  // by construction so it is posted right away
+  let transaction_id = sqlx::query!(
+    "
+INSERT INTO Transactions(name, day, grouping_id, posted)
+  VALUES($1, $2, $3, TRUE)
+  RETURNING id
+    ",
+    name,
+    lines[0].day,
+    grouping_id,
+  )
+    .fetch_one(&mut **transaction)
+    .await?
+    .id
+  ;
+  for line in lines.iter() {
+    let (amount, original_amount) = currencies::to_base(
+      transaction,
+      bookkeeping.id,
+      line.account_id,
+      line.day,
+      line.amount,
+      None,
+    ).await?;
+    let counter_amount = currencies::from_base(
+      transaction,
+      bookkeeping.id,
+      account_id,
+      line.day,
+      -amount,
+    ).await?;
+    let account_change_id = sqlx::query!(
+      "
+INSERT INTO AccountChanges(transaction_id, account_id, message, day, amount, original_amount)
+  VALUES($1, $2, $3, $4, $5, $6)
+  RETURNING id
+      ",
+      transaction_id,
+      line.account_id,
+      message(&line.other_data),
+      line.day,
+      amount,
+      original_amount,
+    )
+      .fetch_one(&mut **transaction)
+      .await?
+      .id
+    ;
+    sqlx::query!(
+      "
+INSERT INTO AccountChanges(transaction_id, account_id, day, amount, original_amount)
+  VALUES($1, $2, $3, $4, $5)
+      ",
+      transaction_id,
+      account_id,
+      line.day,
+      -amount,
+      counter_amount,
+    )
+      .execute(&mut **transaction)
+      .await?
+    ;
+    sqlx::query!(
+      "UPDATE ImportedAccountChanges SET account_change_id = $2 WHERE id = $1",
+      line.id,
+      account_change_id,
+    )
+      .execute(&mut **transaction)
+      .await?
+    ;
+  }
+  Ok(transaction_id)
+}
@@ -1,5 +1,16 @@
 use super::*;
 
+use std::collections::HashMap;
+use serde::Serialize;
+
+mod profiles;
+mod ofx;
+mod camt;
+mod review;
+mod migrate;
+mod rules;
+mod batches;
+
 // Duplicate declaration to groupings/id/transactions/id/mod.rs
 // Kept since the usage may differ in the future
 #[derive(Debug)]
@@ -15,28 +26,86 @@ struct ImportedAccountChange {
   date: Date,
   amount: Decimal,
   other_data: sqlx::types::JsonValue,
+  // Where it has been migrated to, if it has
+  grouping_id: Option<i64>,
+  transaction_id: Option<i64>,
+}
+#[derive(Debug)]
+struct ImportBatch {
+  id: i64,
+  file_name: String,
+  file_hash: String,
+  uploader: String,
+  uploaded_at: String,
+  row_count: i32,
+  // How many of the rows have been migrated into transactions
+  migrated: i64,
+}
+impl ImportBatch {
+  // The start of the hash is enough to tell files apart at a glance
+  fn short_hash(&self) -> &str {
+    &self.file_hash[..self.file_hash.len().min(12)]
+  }
+}
+#[derive(Debug)]
+struct Grouping {
+  id: i64,
+  name: String,
+}
+#[derive(Debug, Deserialize)]
+struct Created {
+  new_from: Option<i64>,
+  new_to: Option<i64>,
+  // Lines not imported since they had already been imported before
+  #[serde(default)]
+  skipped: u64,
+  // Balance reported by the imported statement, if any
+  ledger_date: Option<Date>,
+  ledger_balance: Option<Decimal>,
+}
+impl Created {
+  fn contains(&self, id: &i64) -> bool {
+    match (self.new_from, self.new_to) {
+      (Some(from), Some(to)) => from <= *id && *id <= to,
+      _ => false,
+    }
+  }
 }
 #[derive(Debug, Template)]
 #[template(path = "bookkeepings/id/imported_account_changes/index.html")]
 struct Index {
   bookkeeping_name: String,
   imported_account_changes: Vec<ImportedAccountChange>,
+  batches: Vec<ImportBatch>,
   accounts_by_type: std::collections::HashMap<String, Vec<Account>>,
+  groupings: Vec<Grouping>,
+  // The rule matching each line not yet migrated, by line id
+  suggestions: HashMap<i64, rules::Rule>,
+  created: Created,
+}
+impl Index {
+  fn suggestion(&self, id: &i64) -> Option<&rules::Rule> {
+    self.suggestions.get(id)
+  }
 }
 async fn index(
   state: &'static State,
-  req: Request,
-  session: SessionData,
   bookkeeping: Bookkeeping,
+  created: Created,
 ) -> Result<Response, Error> {
   // Get all the imported account changes valid for this bookkeeping
   let imported_account_changes = sqlx::query_as!(ImportedAccountChange,
     "
-SELECT ImportedAccountChanges.id, Accounts.name AS account_name,
+SELECT ImportedAccountChanges.id, concat_ws(' ', Accounts.number, Accounts.name) AS \"account_name!\",
     ImportedAccountChanges.day as date, ImportedAccountChanges.amount,
-    ImportedAccountCHanges.other_data
+    ImportedAccountCHanges.other_data,
+    Transactions.grouping_id AS \"grouping_id?\",
+    Transactions.id AS \"transaction_id?\"
   FROM ImportedAccountChanges
   INNER JOIN Accounts ON ImportedAccountChanges.account_id = Accounts.id
+  LEFT JOIN AccountChanges
+    ON ImportedAccountChanges.account_change_id = AccountChanges.id
+  LEFT JOIN Transactions ON AccountChanges.transaction_id = Transactions.id
 WHERE Accounts.bookkeeping_id = $1
 ORDER BY ImportedAccountChanges.day
     ",
@@ -48,15 +117,53 @@ ORDER BY ImportedAccountChanges.day
   // We need all the accounts (by type) for the form creating account changes
   let accounts = sqlx::query_as!(Account,
     "
-SELECT Accounts.id, Accounts.name, Accounts.type AS t
+SELECT Accounts.id, concat_ws(' ', Accounts.number, Accounts.name) AS \"name!\", Accounts.type AS t
   FROM Accounts
 WHERE Accounts.bookkeeping_id = $1
+ORDER BY Accounts.number, Accounts.name
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  // The uploads the imported account changes came from
+  let batches = sqlx::query_as!(ImportBatch,
+    "
+SELECT ImportBatches.id, ImportBatches.file_name, ImportBatches.file_hash,
+    Users.email AS uploader,
+    to_char(ImportBatches.uploaded_at, 'YYYY-MM-DD HH24:MI TZ') AS \"uploaded_at!\",
+    ImportBatches.row_count,
+    (
+      SELECT COUNT(*) FROM ImportedAccountChanges
+      WHERE ImportedAccountChanges.batch_id = ImportBatches.id
+        AND ImportedAccountChanges.account_change_id IS NOT NULL
+    ) AS \"migrated!\"
+  FROM ImportBatches
+  INNER JOIN Users ON ImportBatches.uploader_id = Users.id
+WHERE ImportBatches.bookkeeping_id = $1
+ORDER BY ImportBatches.uploaded_at DESC
     ",
     bookkeeping.id,
   )
     .fetch_all(&state.db)
     .await?
   ;
+  // And the groupings to create transactions in when migrating
+  let groupings = sqlx::query_as!(Grouping,
+    "SELECT id, name FROM Groupings WHERE bookkeeping_id = $1 ORDER BY name",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  // Propose how to migrate the lines that haven't been
+  let matcher = rules::Matcher::load(state, bookkeeping.id).await?;
+  let suggestions = imported_account_changes.iter()
+    .filter(|l| l.transaction_id.is_none())
+    .filter_map(|l| matcher.find(l.amount, Some(&l.other_data)).map(|r| (l.id, r.clone())))
+    .collect()
+  ;
   // Then we sort them by account type
   let mut accounts_by_type = std::collections::HashMap::<String,Vec<Account>>::new();
   for account in accounts {
@@ -69,9 +176,431 @@ WHERE Accounts.bookkeeping_id = $1
   html(Index{
     bookkeeping_name: bookkeeping.name,
     imported_account_changes,
+    batches,
     accounts_by_type,
+    groupings,
+    suggestions,
+    created,
   }.render()?)
 }
+
+// A parsed line from an uploaded CSV file
+#[derive(Debug, Serialize, Deserialize)]
+struct ImportLine {
+  day: Date,
+  amount: Decimal,
+  // Always a JSON object
+  other_data: serde_json::Value,
+}
+// Parse a CSV file with a header row, where the columns named "day" (or
+// "date") and "amount" are required and all others are kept as other_data
+// Used for accounts without an import profile
+fn parse_csv(
+  data: &[u8],
+) -> Result<Vec<ImportLine>, Error> {
+  let mut reader = csv::Reader::from_reader(data);
+  let headers = reader.headers()
+    .map_err(|e| Error::invalid_import_file(1, &e.to_string()))?
+    .clone()
+  ;
+  let find_column = |names: &[&str]| headers.iter()
+    .position(|h| names.iter().any(|n| h.trim().eq_ignore_ascii_case(n)))
+  ;
+  let day_column = find_column(&["day", "date"])
+    .ok_or(Error::invalid_import_file(1, "No column named day or date"))?
+  ;
+  let amount_column = find_column(&["amount"])
+    .ok_or(Error::invalid_import_file(1, "No column named amount"))?
+  ;
+
+  let mut lines = Vec::new();
+  for (i, record) in reader.records().enumerate() {
+    // Header is line 1, so the first record is on line 2
+    let line = i + 2;
+    let record = record
+      .map_err(|e| Error::invalid_import_file(line, &e.to_string()))?
+    ;
+    let day = Date::parse(
+      record.get(day_column).unwrap_or("").trim(),
+      &time::format_description::well_known::Iso8601::DATE,
+    )
+      .map_err(|e| Error::invalid_import_file(line, &e.to_string()))?
+    ;
+    let amount: Decimal = record.get(amount_column).unwrap_or("").trim()
+      .parse()
+      .map_err(|e: rust_decimal::Error| Error::invalid_import_file(line, &e.to_string()))?
+    ;
+    let other_data = headers.iter()
+      .zip(record.iter())
+      .enumerate()
+      .filter(|(column, _)| *column != day_column && *column != amount_column)
+      .map(|(_, (h, v))| (h.to_owned(), serde_json::Value::String(v.to_owned())))
+      .collect()
+    ;
+    let other_data = serde_json::Value::Object(other_data);
+    lines.push(ImportLine{ day, amount, other_data });
+  }
+  Ok(lines)
+}
+#[derive(Debug, Deserialize)]
+struct ImportForm {
+  // Left empty to pick the account by the IBAN in a camt statement
+  account: String,
+}
+// Lines parsed from an uploaded file, for one account
+#[derive(Debug, Serialize, Deserialize)]
+struct AccountImport {
+  account_id: i64,
+  lines: Vec<ImportLine>,
+  // The balance the statement reports, if any
+  balance: Option<(Date, Decimal)>,
+}
+// The uploaded file lines are imported from, to create an import batch for
+#[derive(Debug)]
+struct UploadedFile {
+  name: String,
+  // SHA-256 of the content, hex encoded
+  hash: String,
+}
+impl UploadedFile {
+  fn new(name: Option<String>, data: &[u8]) -> Self {
+    use sha2::Digest;
+    let hash = sha2::Sha256::digest(data).iter()
+      .map(|b| format!("{b:02x}"))
+      .collect()
+    ;
+    Self{
+      name: name.unwrap_or_default().chars().take(256).collect(),
+      hash,
+    }
+  }
+}
+// Verify that the account belongs to this bookkeeping
+async fn verify_account(
+  state: &'static State,
+  bookkeeping: &Bookkeeping,
+  account_id: i64,
+) -> Result<(), Error> {
+  sqlx::query!(
+    "SELECT id FROM Accounts WHERE id = $1 AND bookkeeping_id = $2",
+    account_id,
+    bookkeeping.id,
+  )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(ClientError::AccountNotFound(account_id))?
+  ;
+  Ok(())
+}
+// Verify that a statement's IBAN isn't known to be of another account than
+// the chosen one, which would import it into the wrong account
+async fn verify_iban(
+  state: &'static State,
+  bookkeeping: &Bookkeeping,
+  account_id: i64,
+  iban: String,
+) -> Result<(), Error> {
+  let mismatched = sqlx::query!(
+    "
+SELECT id FROM Accounts
+WHERE bookkeeping_id = $1
+  AND ((id = $2 AND iban != $3) OR (id != $2 AND iban = $3))
+    ",
+    bookkeeping.id,
+    account_id,
+    iban,
+  )
+    .fetch_optional(&state.db)
+    .await?
+  ;
+  match mismatched {
+    Some(_) => Err(ClientError::IbanMismatch(iban).into()),
+    None => Ok(()),
+  }
+}
+async fn index_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  // Parse out the submitted account and file
+  let (form, mut files): (ImportForm, _) = parse_body_multipart(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let account_id: Option<i64> = match form.account.as_str() {
+    "" => None,
+    id => Some(id.parse()?),
+  };
+  // Verified before anything of the account is used, such as its profile.
+  // Accounts found by IBAN are looked up within the bookkeeping.
+  if let Some(account_id) = account_id {
+    verify_account(state, &bookkeeping, account_id).await?;
+  }
+  let file_name = match files.next_file().await? {
+    Some(file) if file.name == "changes_file" => file.filename,
+    _ => return Err(Error::multipart_field_missing("changes_file")),
+  };
+  let data = files.read_to_end().await?;
+  let file = UploadedFile::new(file_name, &data);
+
+  // Statement formats are recognised by content, anything else is CSV
+  let mut imports = Vec::new();
+  if camt::is_camt(&data) {
+    let statements = camt::parse(&data)?;
+    let single_statement = statements.len() == 1;
+    for statement in statements {
+      // A chosen account can only be used if there is no doubt which
+      // statement it is for
+      let account_id = match (account_id, statement.iban) {
+        (Some(id), Some(iban)) if single_statement => {
+          verify_iban(state, &bookkeeping, id, iban).await?;
+          id
+        },
+        (Some(id), None) if single_statement => id,
+        (_, Some(iban)) => sqlx::query!(
+          "SELECT id FROM Accounts WHERE bookkeeping_id = $1 AND iban = $2",
+          bookkeeping.id,
+          iban,
+        )
+          .fetch_optional(&state.db)
+          .await?
+          .ok_or(ClientError::UnknownIban(iban))?
+          .id,
+        (_, None) => return Err(Error::multipart_field_missing("account")),
+      };
+      imports.push(AccountImport{
+        account_id,
+        lines: statement.lines,
+        balance: statement.closing_balance,
+      });
+    }
+  } else {
+    let account_id = account_id.ok_or(Error::multipart_field_missing("account"))?;
+    if ofx::is_ofx(&data) {
+      let statement = ofx::parse(&data)?;
+      imports.push(AccountImport{
+        account_id,
+        lines: statement.lines,
+        balance: statement.ledger_balance,
+      });
+    } else {
+      // CSV files are read as configured for the account, if configured
+      let lines = match profiles::get_profile(state, account_id).await? {
+        Some(profile) => profile.parse_csv(&data)?,
+        None => parse_csv(&data)?,
+      };
+      imports.push(AccountImport{
+        account_id,
+        lines,
+        balance: None,
+      });
+    }
+  }
+  // If any lines look like they have been imported before, let the user
+  // choose which to import
+  let mut duplicates = Vec::new();
+  for import in imports.iter() {
+    duplicates.push(find_duplicates(state, import).await?);
+  }
+  if duplicates.iter().flatten().any(|d| *d) {
+    return review::review(state, &session, bookkeeping, file, imports, duplicates).await;
+  }
+  insert_imports(state, &session, &bookkeeping, file, imports, duplicates, 0).await
+}
+// Check which of the lines already exist for the account, as imported lines
+// with the same fingerprint or as account changes with the same day and amount
+// Identical lines are counted, so the second of two identical lines in a file
+// is only a duplicate if two such lines already exist.
+async fn find_duplicates(
+  state: &'static State,
+  import: &AccountImport,
+) -> Result<Vec<bool>, Error> {
+  let fields = profiles::get_fingerprint_fields(state, import.account_id).await?;
+  let days: Vec<Date> = import.lines.iter().map(|l| l.day).collect();
+  let amounts: Vec<Decimal> = import.lines.iter().map(|l| l.amount).collect();
+  let other_datas: Vec<serde_json::Value> = import.lines.iter()
+    .map(|l| l.other_data.clone())
+    .collect()
+  ;
+  // Account changes have no other_data, so they are matched on the rest
+  let fingerprints = sqlx::query!(
+    "
+SELECT import_fingerprint(day, amount, other_data, $4) AS \"fingerprint!\",
+    import_fingerprint(day, amount, '{}', '{}') AS \"day_amount!\"
+  FROM UNNEST($1::DATE[], $2::NUMERIC[], $3::JSONB[])
+    WITH ORDINALITY AS Lines(day, amount, other_data, n)
+ORDER BY n
+    ",
+    &days,
+    &amounts,
+    &other_datas,
+    &fields,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let mut imported: HashMap<String, i64> = sqlx::query!(
+    "
+SELECT fingerprint, COUNT(*) AS \"count!\"
+  FROM ImportedAccountChanges
+WHERE account_id = $1 AND fingerprint = ANY($2)
+GROUP BY fingerprint
+    ",
+    import.account_id,
+    &fingerprints.iter().map(|f| f.fingerprint.clone()).collect::<Vec<_>>(),
+  )
+    .fetch_all(&state.db)
+    .await?
+    .into_iter()
+    .map(|r| (r.fingerprint, r.count))
+    .collect()
+  ;
+  let mut changes: HashMap<String, i64> = sqlx::query!(
+    "
+SELECT import_fingerprint(day, amount, '{}', '{}') AS \"day_amount!\",
+    COUNT(*) AS \"count!\"
+  FROM AccountChanges
+WHERE account_id = $1 AND import_fingerprint(day, amount, '{}', '{}') = ANY($2)
+GROUP BY 1
+    ",
+    import.account_id,
+    &fingerprints.iter().map(|f| f.day_amount.clone()).collect::<Vec<_>>(),
+  )
+    .fetch_all(&state.db)
+    .await?
+    .into_iter()
+    .map(|r| (r.day_amount, r.count))
+    .collect()
+  ;
+  // Each existing row can only account for one incoming line
+  fn take(counts: &mut HashMap<String, i64>, key: &str) -> bool {
+    match counts.get_mut(key) {
+      Some(count) if *count > 0 => { *count -= 1; true },
+      _ => false,
+    }
+  }
+  Ok(fingerprints.iter()
+    .map(|f| {
+      let is_imported = take(&mut imported, &f.fingerprint);
+      let is_changed = take(&mut changes, &f.day_amount);
+      is_imported || is_changed
+    })
+    .collect()
+  )
+}
+// Insert the lines as a batch and redirect to the listing, reporting skipped
+// lines. Forced lines were chosen on review although they seem imported.
+async fn insert_imports(
+  state: &'static State,
+  session: &SessionData,
+  bookkeeping: &Bookkeeping,
+  file: UploadedFile,
+  imports: Vec<AccountImport>,
+  forced: Vec<Vec<bool>>,
+  mut skipped: u64,
+) -> Result<Response, Error> {
+  // Insert all the lines in one transaction, so a failure imports nothing
+  // Lines with the OFX FITID of an imported line have already been imported,
+  // so those are skipped unless forced
+  let mut transaction = begin_as(state, session).await?;
+  let batch_id = sqlx::query!(
+    "
+INSERT INTO ImportBatches(bookkeeping_id, file_name, file_hash, uploader_id)
+  VALUES($1, $2, $3, $4)
+  RETURNING id
+    ",
+    bookkeeping.id,
+    file.name,
+    file.hash,
+    session.user_id,
+  )
+    .fetch_one(&mut *transaction)
+    .await?
+    .id
+  ;
+  let mut created = Vec::new();
+  let mut balances = Vec::new();
+  for (import, forced) in imports.into_iter().zip(forced) {
+    let fields = profiles::get_fingerprint_fields(state, import.account_id).await?;
+    for (line, forced) in import.lines.into_iter().zip(forced) {
+      match sqlx::query!(
+        "
+INSERT INTO ImportedAccountChanges(account_id, day, amount, other_data,
+    fingerprint, batch_id, forced)
+  VALUES($1, $2, $3, $4, import_fingerprint($2, $3, $4, $5), $6, $7)
+  ON CONFLICT (account_id, (other_data->>'fitid'))
+    WHERE other_data ? 'fitid' AND NOT forced
+    DO NOTHING
+  RETURNING id
+        ",
+        import.account_id,
+        line.day,
+        line.amount,
+        line.other_data,
+        &fields,
+        batch_id,
+        forced,
+      )
+        .fetch_optional(&mut *transaction)
+        .await?
+      {
+        Some(row) => created.push(row.id),
+        None => skipped += 1,
+      }
+    }
+    // Keep the balance the statement reports, to reconcile the account with
+    // A checkpoint already on that day, entered by hand or by another batch,
+    // is left as is, so undoing this batch can't remove it
+    if let Some((day, balance)) = import.balance {
+      sqlx::query!(
+        "
+INSERT INTO StatementCheckpoints(account_id, day, balance, batch_id)
+  VALUES($1, $2, $3, $4)
+  ON CONFLICT (account_id, day) DO NOTHING
+        ",
+        import.account_id,
+        day,
+        balance,
+        batch_id,
+      )
+        .execute(&mut *transaction)
+        .await?
+      ;
+    }
+    balances.extend(import.balance);
+  }
+  sqlx::query!(
+    "UPDATE ImportBatches SET row_count = $2 WHERE id = $1",
+    batch_id,
+    created.len() as i32,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  // The file is imported, so it no longer waits for review
+  sqlx::query!(
+    "DELETE FROM PendingImports WHERE bookkeeping_id = $1 AND file_hash = $2",
+    bookkeeping.id,
+    file.hash,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+
+  // Redirect back to the listing with the imported range marked
+  let mut query = format!("skipped={skipped}");
+  if let (Some(from), Some(to)) = (created.first(), created.last()) {
+    query.push_str(&format!("&new_from={from}&new_to={to}"));
+  }
+  // Only show the statement balance if it is clear which account it is for
+  if let [(date, balance)] = balances.as_slice() {
+    query.push_str(&format!("&ledger_date={date}&ledger_balance={balance}"));
+  }
+  see_other(&format!("./?{query}"))
+}
 pub async fn route(
   state: &'static State,
   req: Request,
@@ -82,14 +611,31 @@ pub async fn route(
   match path_vec.pop().as_deref() {
     None => permanent_redirect(&format!("{}/", req.uri().path())),
     Some("") => {
-      verify_method_path_end(&path_vec, &req, &Method::GET)?;
-      index(
-        state,
-        req,
-        session,
-        bookkeeping,
-      ).await
+      verify_path_end(&path_vec, &req)?;
+      match req.method() {
+        &Method::GET => {
+          let created: Created = parse_query(&req)?;
+          index(state, bookkeeping, created).await
+        },
+        &Method::POST => index_post(state, req, session, bookkeeping).await,
+        _ => Err(Error::method_not_found(&req)),
+      }
+    },
+    Some("review") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      review::review_post(state, req, session, bookkeeping).await
+    },
+    Some("migrate") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      migrate::migrate_post(state, req, session, bookkeeping).await
+    },
+    Some("apply-rules") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      rules::apply_post(state, req, session, bookkeeping).await
     },
+    Some("batches") => batches::route(state, req, path_vec, session, bookkeeping).await,
+    Some("rules") => rules::route(state, req, path_vec, session, bookkeeping).await,
+    Some("profiles") => profiles::route(state, req, path_vec, session, bookkeeping).await,
     _ => Err(Error::path_not_found(&req)),
   }
 }
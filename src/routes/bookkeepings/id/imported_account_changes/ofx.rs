@@ -0,0 +1,309 @@
+use super::*;
+
+// Parsing of OFX/QFX bank statements, both the SGML based 1.x versions (where
+// elements holding values aren't closed) and the XML based 2.x versions.
+
+// A parsed OFX statement
+#[derive(Debug)]
+pub struct Statement {
+  pub lines: Vec<ImportLine>,
+  // The balance the bank reports for the account, and when it applied
+  pub ledger_balance: Option<(Date, Decimal)>,
+}
+
+// Check if the given file looks like an OFX file rather than CSV
+pub fn is_ofx(data: &[u8]) -> bool {
+  let start = String::from_utf8_lossy(&data[..data.len().min(1024)]).to_uppercase();
+  let start = start.trim_start_matches('\u{feff}').trim_start();
+  start.starts_with("OFXHEADER") || (start.starts_with("<?XML") && start.contains("OFX"))
+    || start.starts_with("<OFX>")
+}
+
+// The parts of the markup we care about
+#[derive(Debug)]
+enum Token {
+  Open(String),
+  Close(String),
+  Text(String),
+}
+fn decode_entities(text: &str) -> String {
+  text
+    .replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&quot;", "\"")
+    .replace("&apos;", "'")
+    .replace("&nbsp;", " ")
+    .replace("&amp;", "&")
+}
+// Split the markup into tags and text, skipping headers, processing
+// instructions and comments
+fn tokenize(text: &str) -> Vec<Token> {
+  let mut tokens = Vec::new();
+  // Everything before the first tag is the SGML header, which we don't need
+  let mut rest = match text.find('<') {
+    Some(start) => &text[start..],
+    None => return tokens,
+  };
+  while let Some(start) = rest.find('<') {
+    let value = rest[..start].trim();
+    if !value.is_empty() {
+      tokens.push(Token::Text(decode_entities(value)));
+    }
+    rest = &rest[start + 1 ..];
+    let end = match rest.find('>') {
+      Some(end) => end,
+      None => break,
+    };
+    let tag = &rest[..end];
+    rest = &rest[end + 1 ..];
+    if tag.starts_with('?') || tag.starts_with('!') {
+      continue;
+    }
+    match tag.strip_prefix('/') {
+      Some(name) => tokens.push(Token::Close(name.trim().to_uppercase())),
+      None => {
+        // Take only the name, in case an XML version adds attributes
+        let name = tag.trim_end_matches('/').split_whitespace().next().unwrap_or("");
+        tokens.push(Token::Open(name.to_uppercase()));
+        if tag.ends_with('/') {
+          tokens.push(Token::Close(name.to_uppercase()));
+        }
+      },
+    }
+  }
+  tokens
+}
+// OFX dates are YYYYMMDD, optionally followed by time and timezone
+fn parse_date(raw: &str) -> Result<Date, String> {
+  let digits = raw.get(..8)
+    .filter(|d| d.chars().all(|c| c.is_ascii_digit()))
+    .ok_or(format!("Invalid date {raw}"))?
+  ;
+  let number = |range: std::ops::Range<usize>| digits[range].parse::<u16>()
+    .map_err(|_| format!("Invalid date {raw}"))
+  ;
+  let month = time::Month::try_from(number(4..6)? as u8)
+    .map_err(|e| e.to_string())?
+  ;
+  Date::from_calendar_date(number(0..4)? as i32, month, number(6..8)? as u8)
+    .map_err(|e| e.to_string())
+}
+fn parse_amount(raw: &str) -> Result<Decimal, String> {
+  let invalid = || format!("Invalid amount {raw}");
+  // Some banks use decimal comma despite the specification. A comma is taken
+  // as one when it comes last and is followed by two digits, or is the only
+  // separator and isn't followed by three (which would group thousands).
+  let separators = || raw.chars().filter(|c| *c == '.' || *c == ',');
+  let decimal_comma = match raw.rfind(['.', ',']) {
+    Some(i) if raw[i..].starts_with(',') => {
+      let decimals = raw[i + 1 ..].trim().len();
+      decimals == 2 || (separators().count() == 1 && decimals != 3)
+    },
+    _ => false,
+  };
+  let (decimal_separator, grouping_separator) = if decimal_comma { (',', '.') } else { ('.', ',') };
+  // Thousands can only be grouped before the decimals
+  if let Some(decimal) = raw.find(decimal_separator) {
+    if raw[decimal..].contains(grouping_separator) {
+      return Err(invalid());
+    }
+  }
+  let cleaned: String = raw.chars()
+    .filter(|c| !c.is_whitespace() && *c != grouping_separator)
+    .map(|c| if c == ',' { '.' } else { c })
+    .collect()
+  ;
+  cleaned.parse()
+    .map_err(|e: rust_decimal::Error| format!("{}: {e}", invalid()))
+}
+
+// Parse an OFX file into lines to import
+// Stores FITID, payee, memo and type in other_data.
+pub fn parse(
+  data: &[u8],
+) -> Result<Statement, Error> {
+  // SGML OFX files are usually in a legacy charset, but that is rarely needed
+  // to make sense of the data, so decode lossily if not UTF-8
+  let text = match std::str::from_utf8(data) {
+    Ok(text) => std::borrow::Cow::Borrowed(text),
+    Err(_) => std::borrow::Cow::Owned(data.iter().map(|b| *b as char).collect()),
+  };
+  let tokens = tokenize(&text);
+
+  let mut lines = Vec::new();
+  let mut ledger_balance = None;
+  // Values of leaf elements in the aggregate we are currently within
+  let mut fields = std::collections::HashMap::<String, String>::new();
+  let mut previous_open: Option<&str> = None;
+  for token in tokens.iter() {
+    match token {
+      Token::Open(name) => {
+        if name == "STMTTRN" || name == "LEDGERBAL" {
+          fields.clear();
+        }
+        previous_open = Some(name);
+      },
+      // A text after an opening tag is the value of that element
+      Token::Text(value) => {
+        if let Some(name) = previous_open.take() {
+          // The payee can be given as NAME or as a PAYEE aggregate with NAME
+          fields.entry(name.to_owned()).or_insert(value.to_owned());
+        }
+      },
+      Token::Close(name) => {
+        previous_open = None;
+        match name.as_str() {
+          "STMTTRN" => {
+            // Use the number of the transaction as line, since the tags are
+            // not necessarily on separate lines
+            let line = lines.len() + 1;
+            let field = |name: &str| fields.get(name)
+              .ok_or(Error::invalid_import_file(line, &format!("Transaction without {name}")))
+            ;
+            let day = parse_date(field("DTPOSTED")?)
+              .map_err(|e| Error::invalid_import_file(line, &e))?
+            ;
+            let amount = parse_amount(field("TRNAMT")?)
+              .map_err(|e| Error::invalid_import_file(line, &e))?
+            ;
+            let mut other_data = serde_json::Map::new();
+            other_data.insert("fitid".to_owned(), field("FITID")?.as_str().into());
+            for (key, tag) in [("payee", "NAME"), ("memo", "MEMO"), ("type", "TRNTYPE")] {
+              if let Some(value) = fields.get(tag) {
+                other_data.insert(key.to_owned(), value.as_str().into());
+              }
+            }
+            lines.push(ImportLine{
+              day,
+              amount,
+              other_data: serde_json::Value::Object(other_data),
+            });
+          },
+          "LEDGERBAL" => {
+            if let (Some(amount), Some(date)) = (fields.get("BALAMT"), fields.get("DTASOF")) {
+              let invalid = |e: String| -> Error {
+                ClientError::InvalidImportFile(format!("Ledger balance: {e}")).into()
+              };
+              ledger_balance = Some((
+                parse_date(date).map_err(invalid)?,
+                parse_amount(amount).map_err(invalid)?,
+              ));
+            }
+          },
+          _ => (),
+        }
+      },
+    }
+  }
+  Ok(Statement{
+    lines,
+    ledger_balance,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn day(year: i32, month: time::Month, day: u8) -> Date {
+    Date::from_calendar_date(year, month, day).unwrap()
+  }
+
+  // SGML 1.x, where elements holding values aren't closed
+  const SGML: &[u8] = b"OFXHEADER:100\r
+DATA:OFXSGML\r
+CHARSET:1252\r
+\r
+<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><BANKTRANLIST>
+<STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20260302120000[-5:EST]<TRNAMT>-1,234.50
+<FITID>A1<NAME>Caf\xe9 &amp; Bar<MEMO>Lunch
+</STMTTRN>
+<STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20260303<TRNAMT>1.234,50<FITID>A2
+<PAYEE><NAME>Employer</PAYEE>
+</STMTTRN>
+</BANKTRANLIST>
+<LEDGERBAL><BALAMT>100<DTASOF>20260331</LEDGERBAL>
+</STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>";
+
+  #[test]
+  fn amounts_with_either_decimal_separator() {
+    for (raw, expected) in [
+      ("-12.50", Decimal::new(-1250, 2)),
+      ("1,234.56", Decimal::new(123456, 2)),
+      ("12,345,678", Decimal::from(12345678)),
+      // A single comma before three digits groups thousands
+      ("1,234", Decimal::from(1234)),
+      ("1.234,56", Decimal::new(123456, 2)),
+      ("1 234,56", Decimal::new(123456, 2)),
+      ("-0,5", Decimal::new(-5, 1)),
+      ("100,50", Decimal::new(10050, 2)),
+      ("1.234", Decimal::new(1234, 3)),
+    ] {
+      assert_eq!(parse_amount(raw), Ok(expected), "{raw}");
+    }
+    for raw in ["1.234,5", "1,234,56", "12.34.56", ""] {
+      assert!(parse_amount(raw).is_err(), "{raw}");
+    }
+  }
+  #[test]
+  fn detects_ofx() {
+    assert!(is_ofx(SGML));
+    assert!(is_ofx("\u{feff}<?xml version=\"1.0\"?><?OFX OFXHEADER=\"200\"?><OFX/>".as_bytes()));
+    assert!(!is_ofx(b"day,amount\n2026-01-01,1"));
+  }
+  #[test]
+  fn sgml_with_legacy_charset() {
+    let statement = parse(SGML).unwrap();
+    assert_eq!(statement.lines.len(), 2);
+    let line = &statement.lines[0];
+    assert_eq!(line.day, day(2026, time::Month::March, 2));
+    assert_eq!(line.amount, Decimal::new(-123450, 2));
+    assert_eq!(line.other_data, serde_json::json!({
+      "fitid": "A1",
+      "payee": "Café & Bar",
+      "memo": "Lunch",
+      "type": "DEBIT",
+    }));
+    // Decimal comma with point grouping thousands
+    assert_eq!(statement.lines[1].amount, Decimal::new(123450, 2));
+    assert_eq!(statement.lines[1].other_data["payee"], "Employer");
+    assert_eq!(
+      statement.ledger_balance,
+      Some((day(2026, time::Month::March, 31), Decimal::from(100))),
+    );
+  }
+  #[test]
+  fn xml_with_closed_elements() {
+    let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+<?OFX OFXHEADER="200" VERSION="220"?>
+<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><BANKTRANLIST>
+  <STMTTRN>
+    <TRNTYPE>POS</TRNTYPE><DTPOSTED>20260101</DTPOSTED>
+    <TRNAMT>+12.00</TRNAMT><FITID>X&lt;1&gt;</FITID><MEMO/>
+  </STMTTRN>
+</BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>"#;
+    let statement = parse(data.as_bytes()).unwrap();
+    assert_eq!(statement.lines.len(), 1);
+    assert_eq!(statement.lines[0].amount, Decimal::from(12));
+    assert_eq!(statement.lines[0].other_data, serde_json::json!({
+      "fitid": "X<1>",
+      "type": "POS",
+    }));
+    assert_eq!(statement.ledger_balance, None);
+  }
+  #[test]
+  fn malformed_transactions_are_refused() {
+    for transaction in [
+      "<STMTTRN><DTPOSTED>20260101<TRNAMT>1</STMTTRN>",
+      "<STMTTRN><DTPOSTED>20260101<FITID>A</STMTTRN>",
+      "<STMTTRN><TRNAMT>1<FITID>A</STMTTRN>",
+      "<STMTTRN><DTPOSTED>20261301<TRNAMT>1<FITID>A</STMTTRN>",
+      "<STMTTRN><DTPOSTED>20260\u{e5}01<TRNAMT>1<FITID>A</STMTTRN>",
+      "<STMTTRN><DTPOSTED>20260101<TRNAMT>12.34.56<FITID>A</STMTTRN>",
+      "<STMTTRN><DTPOSTED>20260101<TRNAMT>kr<FITID>A</STMTTRN>",
+    ] {
+      let data = format!("<OFX>{transaction}</OFX>");
+      assert!(parse(data.as_bytes()).is_err(), "{transaction}");
+    }
+  }
+}
@@ -0,0 +1,212 @@
+use super::*;
+
+#[derive(Debug)]
+struct Account {
+  id: i64,
+  name: String,
+}
+// Delimiters offered in the form, since a tab is hard to type in a text field
+const DELIMITERS: [(&str, &str); 4] = [
+  (",", "Comma"),
+  (";", "Semicolon"),
+  ("\t", "Tab"),
+  ("|", "Pipe"),
+];
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/imported_account_changes/profiles/id/index.html")]
+struct Index {
+  account_name: String,
+  profile: ImportProfile,
+  delimiters: [(&'static str, &'static str); 4],
+  // Lines parsed from an uploaded file with the profile, if previewing
+  preview: Option<Vec<ImportLine>>,
+}
+impl ImportProfile {
+  fn delimiter_is(&self, delimiter: &str) -> bool {
+    self.delimiter == delimiter
+  }
+  fn other_data_columns_string(&self) -> String {
+    self.other_data_columns.iter()
+      .map(|c| c.to_string())
+      .collect::<Vec<_>>()
+      .join(",")
+  }
+  fn fingerprint_fields_string(&self) -> String {
+    self.fingerprint_fields.join(",")
+  }
+}
+// How many lines of an uploaded file to show when previewing
+const PREVIEW_LINES: usize = 10;
+
+#[derive(Debug, Deserialize)]
+struct ProfileForm {
+  delimiter: String,
+  decimal_separator: String,
+  date_format: String,
+  skip_rows: i32,
+  date_column: i32,
+  amount_column: i32,
+  // Comma separated column numbers
+  other_data_columns: String,
+  // Comma separated other_data field names
+  fingerprint_fields: String,
+}
+impl TryFrom<ProfileForm> for ImportProfile {
+  type Error = Error;
+  fn try_from(form: ProfileForm) -> Result<Self, Error> {
+    let other_data_columns = form.other_data_columns.split(',')
+      .map(|c| c.trim())
+      .filter(|c| !c.is_empty())
+      .map(|c| c.parse::<i32>())
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(|e| Error::invalid_import_profile(&format!("Invalid other data column: {e}")))?
+    ;
+    let fingerprint_fields = form.fingerprint_fields.split(',')
+      .map(|f| f.trim())
+      .filter(|f| !f.is_empty())
+      .map(|f| f.to_owned())
+      .collect()
+    ;
+    let profile = ImportProfile{
+      delimiter: form.delimiter,
+      decimal_separator: form.decimal_separator,
+      date_format: form.date_format,
+      skip_rows: form.skip_rows,
+      date_column: form.date_column,
+      amount_column: form.amount_column,
+      other_data_columns,
+      fingerprint_fields,
+    };
+    profile.validate()?;
+    Ok(profile)
+  }
+}
+
+async fn index(
+  state: &'static State,
+  account: Account,
+) -> Result<Response, Error> {
+  let profile = get_profile(state, account.id).await?.unwrap_or_default();
+  html(Index{
+    account_name: account.name,
+    profile,
+    delimiters: DELIMITERS,
+    preview: None,
+  }.render()?)
+}
+async fn index_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  account: Account,
+) -> Result<Response, Error> {
+  // The form is multipart since it can also upload a file for preview, which
+  // we ignore when saving
+  let (form, _files): (ProfileForm, _) = parse_body_multipart(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let profile: ImportProfile = form.try_into()?;
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "
+INSERT INTO AccountImportProfiles(account_id, delimiter, decimal_separator,
+    date_format, skip_rows, date_column, amount_column, other_data_columns,
+    fingerprint_fields)
+  VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9)
+  ON CONFLICT (account_id) DO UPDATE SET
+    delimiter = $2, decimal_separator = $3, date_format = $4, skip_rows = $5,
+    date_column = $6, amount_column = $7, other_data_columns = $8,
+    fingerprint_fields = $9
+    ",
+    account.id,
+    profile.delimiter,
+    profile.decimal_separator,
+    profile.date_format,
+    profile.skip_rows,
+    profile.date_column,
+    profile.amount_column,
+    &profile.other_data_columns,
+    &profile.fingerprint_fields,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  // Fingerprint the already imported lines the same way as new ones will be
+  sqlx::query!(
+    "
+UPDATE ImportedAccountChanges
+  SET fingerprint = import_fingerprint(day, amount, other_data, $2)
+WHERE account_id = $1
+    ",
+    account.id,
+    &profile.fingerprint_fields,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  // Back to the import page, where the profile will now be used
+  see_other("../../")
+}
+async fn preview_post(
+  state: &'static State,
+  mut req: Request,
+  account: Account,
+) -> Result<Response, Error> {
+  // Parse the profile from the form without saving it, then try it out
+  let (form, mut files): (ProfileForm, _) = parse_body_multipart(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let profile: ImportProfile = form.try_into()?;
+  match files.next_file().await? {
+    Some(file) if file.name == "changes_csv" => (),
+    _ => return Err(Error::multipart_field_missing("changes_csv")),
+  }
+  let mut lines = profile.parse_csv(&files.read_to_end().await?)?;
+  lines.truncate(PREVIEW_LINES);
+
+  html(Index{
+    account_name: account.name,
+    profile,
+    delimiters: DELIMITERS,
+    preview: Some(lines),
+  }.render()?)
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  account_id: i64,
+) -> Result<Response, Error> {
+  // Profiles are per account, so verify that the account is in the bookkeeping
+  let account = sqlx::query_as!(Account,
+    "SELECT id, name FROM Accounts WHERE id = $1 AND bookkeeping_id = $2",
+    account_id,
+    bookkeeping.id,
+  )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::path_not_found(&req))?
+  ;
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    Some("") => {
+      verify_path_end(&path_vec, &req)?;
+      match req.method() {
+        &Method::GET => index(state, account).await,
+        &Method::POST => index_post(state, req, session, account).await,
+        _ => Err(Error::method_not_found(&req)),
+      }
+    },
+    Some("preview") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      preview_post(state, req, account).await
+    },
+    _ => Err(Error::path_not_found(&req)),
+  }
+}
@@ -0,0 +1,202 @@
+use super::*;
+
+mod id;
+
+// How to read the CSV files exported by the bank of an account
+#[derive(Debug)]
+pub struct ImportProfile {
+  delimiter: String,
+  decimal_separator: String,
+  date_format: String,
+  skip_rows: i32,
+  date_column: i32,
+  amount_column: i32,
+  other_data_columns: Vec<i32>,
+  // other_data fields that tell otherwise identical lines apart
+  fingerprint_fields: Vec<String>,
+}
+impl Default for ImportProfile {
+  // Same as the defaults in the database, reading the format parse_csv expects
+  // when the date and amount are the first two columns
+  fn default() -> Self {
+    Self{
+      delimiter: ",".to_owned(),
+      decimal_separator: ".".to_owned(),
+      date_format: "[year]-[month]-[day]".to_owned(),
+      skip_rows: 1,
+      date_column: 1,
+      amount_column: 2,
+      other_data_columns: Vec::new(),
+      fingerprint_fields: Vec::new(),
+    }
+  }
+}
+impl ImportProfile {
+  // Verify that the profile can be used, so we don't save a broken one
+  fn validate(&self) -> Result<(), Error> {
+    if self.delimiter.len() != 1 {
+      return Err(Error::invalid_import_profile("Delimiter must be one ASCII character"));
+    }
+    if self.decimal_separator != "." && self.decimal_separator != "," {
+      return Err(Error::invalid_import_profile("Decimal separator must be . or ,"));
+    }
+    time::format_description::parse(&self.date_format)
+      .map_err(|e| Error::invalid_import_profile(&format!("Invalid date format: {e}")))?
+    ;
+    let columns = [self.date_column, self.amount_column].into_iter()
+      .chain(self.other_data_columns.iter().copied())
+    ;
+    for column in columns {
+      if column < 1 {
+        return Err(Error::invalid_import_profile("Columns are numbered from 1"));
+      }
+    }
+    if self.skip_rows < 0 {
+      return Err(Error::invalid_import_profile("Can't skip a negative number of rows"));
+    }
+    Ok(())
+  }
+  // Parse an amount, ignoring thousands separators, apostrophes and whitespace
+  fn parse_amount(&self, raw: &str) -> Result<Decimal, String> {
+    let thousands_separator = if self.decimal_separator == "," { '.' } else { ',' };
+    let cleaned: String = raw.chars()
+      .filter(|c| !c.is_whitespace() && *c != thousands_separator && *c != '\'')
+      .map(|c| if c == ',' { '.' } else { c })
+      .collect()
+    ;
+    cleaned.parse().map_err(|e: rust_decimal::Error| e.to_string())
+  }
+  // Parse a CSV file as described by this profile
+  pub fn parse_csv(
+    &self,
+    data: &[u8],
+  ) -> Result<Vec<ImportLine>, Error> {
+    self.validate()?;
+    let date_format = time::format_description::parse(&self.date_format)
+      .map_err(|e| Error::invalid_import_profile(&e.to_string()))?
+    ;
+    let mut reader = csv::ReaderBuilder::new()
+      .has_headers(false)
+      .flexible(true)
+      .delimiter(self.delimiter.as_bytes()[0])
+      .from_reader(data)
+    ;
+
+    // The last skipped row, if any, names the columns kept as other data
+    let mut header: Option<csv::StringRecord> = None;
+    let mut lines = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+      let record = record
+        .map_err(|e| Error::invalid_import_file(i + 1, &e.to_string()))?
+      ;
+      if i < self.skip_rows as usize {
+        header = Some(record);
+        continue;
+      }
+      // Quoted fields may contain newlines, so ask the reader for the line
+      let line = record.position().map(|p| p.line() as usize).unwrap_or(i + 1);
+      let column = |c: i32| record.get(c as usize - 1)
+        .ok_or(Error::invalid_import_file(line, &format!("No column {c}")))
+      ;
+
+      let day = Date::parse(column(self.date_column)?.trim(), &date_format)
+        .map_err(|e| Error::invalid_import_file(line, &e.to_string()))?
+      ;
+      let amount = self.parse_amount(column(self.amount_column)?)
+        .map_err(|e| Error::invalid_import_file(line, &e))?
+      ;
+      let other_data = self.other_data_columns.iter()
+        .map(|c| {
+          let name = header.as_ref()
+            .and_then(|h| h.get(*c as usize - 1))
+            .filter(|h| !h.trim().is_empty())
+            .map(|h| h.trim().to_owned())
+            .unwrap_or(format!("column {c}"))
+          ;
+          let value = record.get(*c as usize - 1).unwrap_or("").to_owned();
+          (name, serde_json::Value::String(value))
+        })
+        .collect()
+      ;
+      let other_data = serde_json::Value::Object(other_data);
+      lines.push(ImportLine{ day, amount, other_data });
+    }
+    Ok(lines)
+  }
+}
+
+// Get the other_data fields included in the fingerprints of the given account
+pub async fn get_fingerprint_fields(
+  state: &'static State,
+  account_id: i64,
+) -> Result<Vec<String>, Error> {
+  Ok(get_profile(state, account_id).await?
+    .map(|p| p.fingerprint_fields)
+    .unwrap_or_default()
+  )
+}
+// Get the import profile of the given account, if it has one
+pub async fn get_profile(
+  state: &'static State,
+  account_id: i64,
+) -> Result<Option<ImportProfile>, Error> {
+  Ok(sqlx::query_as!(ImportProfile,
+    "
+SELECT delimiter, decimal_separator, date_format, skip_rows, date_column,
+    amount_column, other_data_columns, fingerprint_fields
+  FROM AccountImportProfiles
+WHERE account_id = $1
+    ",
+    account_id,
+  )
+    .fetch_optional(&state.db)
+    .await?
+  )
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    // Profiles are listed on the import page, so there is no index here
+    Some("") => Err(Error::path_not_found(&req)),
+    Some(id) => id::route(state, req, path_vec, session, bookkeeping, id.parse()?).await,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn amount(decimal_separator: &str, raw: &str) -> Result<Decimal, String> {
+    ImportProfile{
+      decimal_separator: decimal_separator.to_owned(),
+      ..Default::default()
+    }.parse_amount(raw)
+  }
+
+  #[test]
+  fn decimal_point_amounts_ignore_grouping() {
+    assert_eq!(amount(".", "1,234.56"), Ok(Decimal::new(123456, 2)));
+    assert_eq!(amount(".", "-1 234 567.5"), Ok(Decimal::new(-12345675, 1)));
+    assert_eq!(amount(".", "1'234.56"), Ok(Decimal::new(123456, 2)));
+  }
+  #[test]
+  fn decimal_comma_amounts_ignore_grouping() {
+    assert_eq!(amount(",", "1.234,56"), Ok(Decimal::new(123456, 2)));
+    assert_eq!(amount(",", "1 234,56"), Ok(Decimal::new(123456, 2)));
+    // Non-breaking spaces, as spreadsheets group thousands with
+    assert_eq!(amount(",", "-1\u{a0}234,56"), Ok(Decimal::new(-123456, 2)));
+  }
+  #[test]
+  fn malformed_amounts_are_refused() {
+    assert!(amount(".", "").is_err());
+    assert!(amount(".", "12.34.56").is_err());
+    assert!(amount(",", "12kr").is_err());
+  }
+}
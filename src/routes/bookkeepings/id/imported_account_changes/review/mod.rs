@@ -0,0 +1,142 @@
+use super::*;
+
+// A line to import, as shown for review
+#[derive(Debug)]
+struct ReviewLine {
+  // Position among the lines of all the imports, to refer to it in the form
+  index: usize,
+  account_name: String,
+  day: Date,
+  amount: Decimal,
+  other_data: serde_json::Value,
+  duplicate: bool,
+}
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/imported_account_changes/review/index.html")]
+struct Index {
+  bookkeeping_name: String,
+  lines: Vec<ReviewLine>,
+  // Identifies the pending import, sent back with the lines chosen to import
+  file_hash: String,
+}
+
+// Show the lines of an import where some seem to have been imported before,
+// keeping them as a pending import until the user has chosen
+pub async fn review(
+  state: &'static State,
+  session: &SessionData,
+  bookkeeping: Bookkeeping,
+  file: UploadedFile,
+  imports: Vec<AccountImport>,
+  duplicates: Vec<Vec<bool>>,
+) -> Result<Response, Error> {
+  sqlx::query!(
+    "
+INSERT INTO PendingImports(bookkeeping_id, file_hash, file_name, uploader_id, imports, duplicates)
+  VALUES($1, $2, $3, $4, $5, $6)
+  ON CONFLICT (bookkeeping_id, file_hash) DO UPDATE
+    SET file_name = $3, uploader_id = $4, uploaded_at = NOW(), imports = $5, duplicates = $6
+    ",
+    bookkeeping.id,
+    file.hash,
+    file.name,
+    session.user_id,
+    serde_json::to_value(&imports)?,
+    serde_json::to_value(&duplicates)?,
+  )
+    .execute(&state.db)
+    .await?
+  ;
+  let account_names: HashMap<i64, String> = sqlx::query!(
+    "SELECT id, concat_ws(' ', number, name) AS \"name!\" FROM Accounts WHERE bookkeeping_id = $1",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+    .into_iter()
+    .map(|a| (a.id, a.name))
+    .collect()
+  ;
+  let mut lines = Vec::new();
+  for (import, duplicates) in imports.into_iter().zip(duplicates) {
+    for (line, duplicate) in import.lines.into_iter().zip(duplicates) {
+      lines.push(ReviewLine{
+        index: lines.len(),
+        account_name: account_names.get(&import.account_id).cloned().unwrap_or_default(),
+        day: line.day,
+        amount: line.amount,
+        other_data: line.other_data,
+        duplicate,
+      });
+    }
+  }
+
+  html(Index{
+    bookkeeping_name: bookkeeping.name,
+    lines,
+    file_hash: file.hash,
+  }.render()?)
+}
+
+// Import the lines chosen on the review page
+pub async fn review_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  // The chosen lines are given as repeated import fields, so parse as pairs
+  let form: Vec<(String, String)> = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let file_hash = form.iter()
+    .find(|(key, _)| key == "file_hash")
+    .map(|(_, value)| value.as_str())
+    .ok_or(ClientError::InvalidUrlEncoding("Missing field file_hash".to_owned()))?
+  ;
+  let pending = sqlx::query!(
+    "
+SELECT file_name, imports, duplicates
+  FROM PendingImports
+WHERE bookkeeping_id = $1 AND file_hash = $2
+  -- Those never chosen for are forgotten after a day, see database_cleaner
+  AND uploaded_at >= NOW() - INTERVAL '1 day'
+    ",
+    bookkeeping.id,
+    file_hash,
+  )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(ClientError::PendingImportNotFound(file_hash.to_owned()))?
+  ;
+  let file = UploadedFile{ name: pending.file_name, hash: file_hash.to_owned() };
+  let mut imports: Vec<AccountImport> = serde_json::from_value(pending.imports)?;
+  let duplicates: Vec<Vec<bool>> = serde_json::from_value(pending.duplicates)?;
+  let chosen = form.iter()
+    .filter(|(key, _)| key == "import")
+    .map(|(_, value)| value.parse::<usize>())
+    .collect::<Result<std::collections::HashSet<_>, _>>()?
+  ;
+
+  // Chosen lines that seem imported are forced in
+  let mut index = 0;
+  let mut skipped = 0;
+  let mut forced = Vec::new();
+  for (import, duplicates) in imports.iter_mut().zip(duplicates) {
+    let mut lines = Vec::new();
+    let mut import_forced = Vec::new();
+    for (line, duplicate) in std::mem::take(&mut import.lines).into_iter().zip(duplicates) {
+      if chosen.contains(&index) {
+        lines.push(line);
+        import_forced.push(duplicate);
+      } else {
+        skipped += 1;
+      }
+      index += 1;
+    }
+    import.lines = lines;
+    forced.push(import_forced);
+  }
+  insert_imports(state, &session, &bookkeeping, file, imports, forced, skipped).await
+}
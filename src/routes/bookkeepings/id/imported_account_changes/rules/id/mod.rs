@@ -0,0 +1,111 @@
+use super::*;
+
+// Swap places with the closest rule before (or after) this one, if any
+async fn move_post(
+  state: &'static State,
+  session: SessionData,
+  rule_id: i64,
+  bookkeeping: Bookkeeping,
+  up: bool,
+) -> Result<Response, Error> {
+  let mut transaction = begin_as(state, &session).await?;
+  let rule = sqlx::query!(
+    "SELECT position FROM ImportRules WHERE id = $1 AND bookkeeping_id = $2 FOR UPDATE",
+    rule_id,
+    bookkeeping.id,
+  )
+    .fetch_one(&mut *transaction)
+    .await?
+  ;
+  let other = sqlx::query!(
+    "
+SELECT id, position FROM ImportRules
+WHERE bookkeeping_id = $1
+  AND CASE WHEN $3 THEN position < $2 ELSE position > $2 END
+ORDER BY CASE WHEN $3 THEN -position ELSE position END
+LIMIT 1
+FOR UPDATE
+    ",
+    bookkeeping.id,
+    rule.position,
+    up,
+  )
+    .fetch_optional(&mut *transaction)
+    .await?
+  ;
+  if let Some(other) = other {
+    // The uniqueness of positions is checked on commit, so they can be swapped
+    sqlx::query!(
+      "UPDATE ImportRules SET position = $2 WHERE id = $1",
+      rule_id,
+      other.position,
+    )
+      .execute(&mut *transaction)
+      .await?
+    ;
+    sqlx::query!(
+      "UPDATE ImportRules SET position = $2 WHERE id = $1",
+      other.id,
+      rule.position,
+    )
+      .execute(&mut *transaction)
+      .await?
+    ;
+  }
+  transaction.commit().await?;
+  see_other("../")
+}
+async fn delete_post(
+  state: &'static State,
+  session: SessionData,
+  rule_id: i64,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "DELETE FROM ImportRules WHERE id = $1 AND bookkeeping_id = $2",
+    rule_id,
+    bookkeeping.id,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  see_other("../")
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  rule_id: i64,
+) -> Result<Response, Error> {
+  // Verify that the rule is in the bookkeeping
+  sqlx::query!(
+    "SELECT id FROM ImportRules WHERE id = $1 AND bookkeeping_id = $2",
+    rule_id,
+    bookkeeping.id,
+  )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::path_not_found(&req))?
+  ;
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    Some("move-up") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      move_post(state, session, rule_id, bookkeeping, true).await
+    },
+    Some("move-down") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      move_post(state, session, rule_id, bookkeeping, false).await
+    },
+    Some("delete") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      delete_post(state, session, rule_id, bookkeeping).await
+    },
+    _ => Err(Error::path_not_found(&req)),
+  }
+}
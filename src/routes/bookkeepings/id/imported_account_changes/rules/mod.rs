@@ -0,0 +1,321 @@
+use super::*;
+
+use migrate::{lock_lines, create_transaction};
+
+mod id;
+
+// A rule proposing how to migrate matching imported account changes
+#[derive(Debug, Clone)]
+pub struct Rule {
+  id: i64,
+  pub position: i32,
+  field: String,
+  pattern: String,
+  is_regex: bool,
+  min_amount: Option<Decimal>,
+  max_amount: Option<Decimal>,
+  sign: Option<i16>,
+  account_id: i64,
+  pub account_name: String,
+  pub transaction_name: String,
+  grouping_id: i64,
+  pub grouping_name: String,
+}
+impl Rule {
+  fn sign_string(&self) -> &'static str {
+    match self.sign {
+      Some(1) => "Deposits",
+      Some(-1) => "Withdrawals",
+      _ => "Both",
+    }
+  }
+}
+
+// The rules of a bookkeeping, ready to match lines against
+pub struct Matcher {
+  rules: Vec<(Rule, Option<regex::Regex>)>,
+}
+impl Matcher {
+  pub async fn load(
+    state: &'static State,
+    bookkeeping_id: i64,
+  ) -> Result<Self, Error> {
+    let rules = sqlx::query_as!(Rule,
+      "
+SELECT ImportRules.id, ImportRules.position, ImportRules.field,
+    ImportRules.pattern, ImportRules.is_regex, ImportRules.min_amount,
+    ImportRules.max_amount, ImportRules.sign, ImportRules.account_id,
+    concat_ws(' ', Accounts.number, Accounts.name) AS \"account_name!\", ImportRules.transaction_name,
+    ImportRules.grouping_id, Groupings.name AS grouping_name
+  FROM ImportRules
+  INNER JOIN Accounts ON ImportRules.account_id = Accounts.id
+  INNER JOIN Groupings ON ImportRules.grouping_id = Groupings.id
+WHERE ImportRules.bookkeeping_id = $1
+ORDER BY ImportRules.position
+      ",
+      bookkeeping_id,
+    )
+      .fetch_all(&state.db)
+      .await?
+    ;
+    // Patterns are verified when saved, so failing here only disables the rule
+    let rules = rules.into_iter()
+      .map(|rule| {
+        let regex = if rule.is_regex { regex::Regex::new(&rule.pattern).ok() } else { None };
+        (rule, regex)
+      })
+      .collect()
+    ;
+    Ok(Self{ rules })
+  }
+  // The first rule matching the given line, if any
+  pub fn find(
+    &self,
+    amount: Decimal,
+    other_data: Option<&serde_json::Value>,
+  ) -> Option<&Rule> {
+    // Other data values as text, only the chosen field if the rule has one
+    let values = |field: &str| -> Vec<String> {
+      let Some(serde_json::Value::Object(map)) = other_data else { return Vec::new() };
+      map.iter()
+        .filter(|(key, _)| field.is_empty() || *key == field)
+        .map(|(_, value)| match value {
+          serde_json::Value::String(s) => s.clone(),
+          value => value.to_string(),
+        })
+        .collect()
+    };
+    self.rules.iter()
+      .find(|(rule, regex)| {
+        let size = amount.abs();
+        if rule.min_amount.is_some_and(|min| size < min)
+          || rule.max_amount.is_some_and(|max| size > max)
+        {
+          return false;
+        }
+        match rule.sign {
+          Some(1) if amount.is_sign_negative() => return false,
+          Some(-1) if !amount.is_sign_negative() => return false,
+          _ => (),
+        }
+        let pattern = rule.pattern.to_lowercase();
+        values(&rule.field).iter().any(|value| match (rule.is_regex, regex) {
+          (true, Some(regex)) => regex.is_match(value),
+          (true, None) => false,
+          (false, _) => value.to_lowercase().contains(&pattern),
+        })
+      })
+      .map(|(rule, _)| rule)
+  }
+}
+
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/imported_account_changes/rules/index.html")]
+struct Index {
+  bookkeeping_name: String,
+  rules: Vec<Rule>,
+  accounts_by_type: HashMap<String, Vec<Account>>,
+  groupings: Vec<Grouping>,
+}
+async fn index(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let rules = Matcher::load(state, bookkeeping.id).await?.rules.into_iter()
+    .map(|(rule, _)| rule)
+    .collect()
+  ;
+  let accounts = sqlx::query_as!(Account,
+    "
+SELECT Accounts.id, concat_ws(' ', Accounts.number, Accounts.name) AS \"name!\", Accounts.type AS t
+  FROM Accounts
+WHERE Accounts.bookkeeping_id = $1
+ORDER BY Accounts.number, Accounts.name
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let mut accounts_by_type = HashMap::<String, Vec<Account>>::new();
+  for account in accounts {
+    accounts_by_type.entry(account.t.clone()).or_default().push(account);
+  }
+  let groupings = sqlx::query_as!(Grouping,
+    "SELECT id, name FROM Groupings WHERE bookkeeping_id = $1 ORDER BY name",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+
+  html(Index{
+    bookkeeping_name: bookkeeping.name,
+    rules,
+    accounts_by_type,
+    groupings,
+  }.render()?)
+}
+
+#[derive(Debug, Deserialize)]
+struct NewRule {
+  #[serde(default)]
+  field: String,
+  pattern: String,
+  // A checkbox, so only given if checked
+  is_regex: Option<String>,
+  // Empty for no bound
+  #[serde(default)]
+  min_amount: String,
+  #[serde(default)]
+  max_amount: String,
+  // Empty for both, 1 for deposits and -1 for withdrawals
+  #[serde(default)]
+  sign: String,
+  account: i64,
+  transaction_name: String,
+  grouping: i64,
+}
+async fn index_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let new_rule: NewRule = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+
+  // Validate, so every saved rule can be used
+  if new_rule.pattern.is_empty() {
+    return Err(Error::invalid_import_rule("The pattern can't be empty"));
+  }
+  let is_regex = new_rule.is_regex.is_some();
+  if is_regex {
+    regex::Regex::new(&new_rule.pattern)
+      .map_err(|e| Error::invalid_import_rule(&e.to_string()))?
+    ;
+  }
+  let bound = |raw: &str| -> Result<Option<Decimal>, Error> {
+    match raw.trim() {
+      "" => Ok(None),
+      raw => raw.parse::<Decimal>()
+        .map(|b| Some(b.abs()))
+        .map_err(|e| Error::invalid_import_rule(&format!("Invalid amount {raw}: {e}"))),
+    }
+  };
+  let min_amount = bound(&new_rule.min_amount)?;
+  let max_amount = bound(&new_rule.max_amount)?;
+  if let (Some(min), Some(max)) = (min_amount, max_amount) {
+    if min > max {
+      return Err(Error::invalid_import_rule("The minimum amount is above the maximum"));
+    }
+  }
+  let sign: Option<i16> = match new_rule.sign.as_str() {
+    "" => None,
+    "1" => Some(1),
+    "-1" => Some(-1),
+    _ => return Err(Error::invalid_import_rule("The sign must be 1, -1 or empty")),
+  };
+  verify_account(state, &bookkeeping, new_rule.account).await?;
+  sqlx::query!(
+    "SELECT id FROM Groupings WHERE id = $1 AND bookkeeping_id = $2",
+    new_rule.grouping,
+    bookkeeping.id,
+  )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(ClientError::GroupingNotFound(new_rule.grouping))?
+  ;
+
+  // New rules are tried last
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "
+INSERT INTO ImportRules(bookkeeping_id, position, field, pattern, is_regex,
+    min_amount, max_amount, sign, account_id, transaction_name, grouping_id)
+  SELECT $1, COALESCE(MAX(position), 0) + 1, $2, $3, $4, $5, $6, $7, $8, $9, $10
+    FROM ImportRules
+  WHERE bookkeeping_id = $1
+    ",
+    bookkeeping.id,
+    new_rule.field.trim(),
+    new_rule.pattern,
+    is_regex,
+    min_amount,
+    max_amount,
+    sign,
+    new_rule.account,
+    new_rule.transaction_name,
+    new_rule.grouping,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+
+// Migrate each of the chosen lines into a transaction of its own, as proposed
+// by the first rule matching it. Lines no rule matches are left as they are.
+pub async fn apply_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  // The chosen lines are given as repeated fields, so parse as pairs
+  let pairs: Vec<(String, String)> = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let ids = pairs.iter()
+    .filter(|(key, _)| key == "line")
+    .map(|(_, value)| value.parse())
+    .collect::<Result<Vec<i64>, _>>()?
+  ;
+  if ids.is_empty() {
+    return Err(ClientError::NothingChosen.into());
+  }
+  let matcher = Matcher::load(state, bookkeeping.id).await?;
+
+  let mut transaction = begin_as(state, &session).await?;
+  let lines = lock_lines(&mut transaction, &bookkeeping, &ids).await?;
+  for line in lines {
+    if let Some(rule) = matcher.find(line.amount, line.other_data.as_ref()) {
+      create_transaction(
+        &mut transaction,
+        &bookkeeping,
+        std::slice::from_ref(&line),
+        rule.grouping_id,
+        rule.account_id,
+        &rule.transaction_name,
+      ).await?;
+    }
+  }
+  transaction.commit().await?;
+
+  see_other("./")
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    Some("") => {
+      verify_path_end(&path_vec, &req)?;
+      match req.method() {
+        &Method::GET => index(state, bookkeeping).await,
+        &Method::POST => index_post(state, req, session, bookkeeping).await,
+        _ => Err(Error::method_not_found(&req)),
+      }
+    },
+    Some(id) => id::route(state, req, path_vec, session, bookkeeping, id.parse()?).await,
+  }
+}
@@ -1,31 +1,159 @@
 use super::*;
 
+mod account_types;
 mod accounts;
+mod currencies;
 mod groupings;
+mod history;
 mod imported_account_changes;
+mod recurring;
+mod sie_export;
+mod vat;
+
+pub use recurring::generate_due as generate_recurring_transactions;
 
 #[derive(Debug)]
 pub struct Bookkeeping {
   id: i64,
   name: String,
   owner: String,
+  owner_id: i64,
+  // Transactions dated on or before this can't be changed
+  locked_until: Option<Date>,
+  // Amounts are kept in this currency, whatever the account's currency
+  base_currency: String,
 }
 #[derive(Debug)]
 struct AccountSummary {
   id: i64,
   name: String,
   r#type: String,
+  // In the base currency
   balance: Decimal,
+  // None for accounts in the base currency
+  currency: Option<String>,
+  // In the account's currency
+  original_balance: Decimal,
+  parent_id: Option<i64>,
+  subtype: Option<String>,
+  // Debit or Credit, the side of the account's type
+  normal_balance: String,
+}
+// The types of the balance sheet, whose balances carry over between years
+fn is_balance_type(r#type: &str) -> bool {
+  matches!(r#type, "Asset" | "Debt" | "Equity")
+}
+// An account in a tree of accounts, with the balance of its sub-accounts
+// included, in the order to render the tree in
+#[derive(Debug)]
+struct AccountTreeRow {
+  id: i64,
+  name: String,
+  r#type: String,
+  subtype: Option<String>,
+  // In the base currency, including all sub-accounts, positive when on the
+  // normal side of the type
+  balance: Decimal,
+  // Only the account's own, as sub-accounts may be in other currencies
+  currency: Option<String>,
+  original_balance: Decimal,
+  has_children: bool,
+  // How many subtrees end after this row
+  closes: usize,
+}
+// Order the accounts as a tree, each followed by its sub-accounts, with the
+// balances shown from the normal side of their type
+fn account_tree(accounts: Vec<AccountSummary>) -> Vec<AccountTreeRow> {
+  let ids: std::collections::HashSet<i64> = accounts.iter().map(|a| a.id).collect();
+  let mut children = std::collections::HashMap::<i64, Vec<usize>>::new();
+  let mut roots = Vec::new();
+  for (i, account) in accounts.iter().enumerate() {
+    match account.parent_id {
+      Some(parent) if ids.contains(&parent) => {
+        children.entry(parent).or_default().push(i);
+      },
+      _ => roots.push(i),
+    }
+  }
+  // Adds the account and its subtree to the rows, giving its rolled up balance
+  fn add(
+    i: usize,
+    accounts: &[AccountSummary],
+    children: &std::collections::HashMap<i64, Vec<usize>>,
+    added: &mut [bool],
+    rows: &mut Vec<AccountTreeRow>,
+  ) -> Decimal {
+    added[i] = true;
+    let account = &accounts[i];
+    // Accounts already added are skipped, which can only be those in a cycle
+    let subtree: Vec<usize> = children.get(&account.id)
+      .into_iter()
+      .flatten()
+      .copied()
+      .filter(|child| !added[*child])
+      .collect()
+    ;
+    let row = rows.len();
+    // Sub-accounts have the same type, so the same side as their parent
+    let sign = match account.normal_balance.as_str() {
+      "Credit" => Decimal::NEGATIVE_ONE,
+      _ => Decimal::ONE,
+    };
+    rows.push(AccountTreeRow{
+      id: account.id,
+      name: account.name.clone(),
+      r#type: account.r#type.clone(),
+      subtype: account.subtype.clone(),
+      balance: account.balance,
+      currency: account.currency.clone(),
+      original_balance: sign * account.original_balance,
+      has_children: !subtree.is_empty(),
+      closes: 0,
+    });
+    let mut balance = account.balance;
+    for child in subtree.iter() {
+      balance += add(*child, accounts, children, added, rows);
+    }
+    rows[row].balance = sign * balance;
+    if !subtree.is_empty() {
+      // Subtrees end with their last descendant
+      if let Some(last) = rows.last_mut() {
+        last.closes += 1;
+      }
+    }
+    balance
+  }
+  let mut added = vec![false; accounts.len()];
+  let mut rows = Vec::new();
+  for root in roots {
+    add(root, &accounts, &children, &mut added, &mut rows);
+  }
+  // Accounts in a cycle have no root above them, so they are shown from
+  // wherever the cycle is entered rather than left out
+  for i in 0..accounts.len() {
+    if !added[i] {
+      add(i, &accounts, &children, &mut added, &mut rows);
+    }
+  }
+  rows
 }
 #[derive(Debug)]
 struct AccountType {
   name: String,
 }
 #[derive(Debug)]
+struct SubtypeChoice {
+  id: i64,
+  name: String,
+  r#type: String,
+}
+#[derive(Debug)]
 struct GroupingSummary {
   id: i64,
   name: String,
   movement: Decimal,
+  // Drafts whose account changes don't sum to zero, which need attention
+  unbalanced_drafts: i64,
 }
 #[derive(Debug, Deserialize, PartialEq, Eq)]
 struct Created {
@@ -45,9 +173,12 @@ impl Created {
 struct Index {
   name: String,
   owner: String,
-  accounts: Vec<AccountSummary>,
+  locked_until: Option<Date>,
+  base_currency: String,
+  accounts: Vec<AccountTreeRow>,
   groupings: Vec<GroupingSummary>,
   account_types: Vec<AccountType>,
+  subtypes: Vec<SubtypeChoice>,
   created: Created,
 }
 
@@ -60,12 +191,20 @@ async fn index(
 ) -> Result<Response, Error> {
   let a = sqlx::query_as!(AccountSummary,
     "
-SELECT Accounts.id, Accounts.name, Accounts.type, COALESCE(SUM(AccountChanges.amount), 0) AS \"balance!\"
+SELECT Accounts.id, concat_ws(' ', Accounts.number, Accounts.name) AS \"name!\", Accounts.type, COALESCE(SUM(AccountChanges.amount), 0) AS \"balance!\",
+    Accounts.currency,
+    COALESCE(SUM(COALESCE(AccountChanges.original_amount, AccountChanges.amount)), 0) AS \"original_balance!\",
+    Accounts.parent_id, AccountSubtypes.name AS \"subtype?\", AccountTypes.normal_balance
   FROM Accounts
-  LEFT JOIN AccountChanges ON AccountChanges.account_id = Accounts.id
+  INNER JOIN AccountTypes ON AccountTypes.name = Accounts.type
+  LEFT JOIN AccountSubtypes ON AccountSubtypes.id = Accounts.subtype_id
+  LEFT JOIN (
+    AccountChanges INNER JOIN Transactions
+      ON Transactions.id = AccountChanges.transaction_id AND Transactions.posted
+  ) ON AccountChanges.account_id = Accounts.id
 WHERE Accounts.bookkeeping_id = $1
-GROUP BY Accounts.id, Accounts.name, Accounts.type
-ORDER BY Accounts.type, Accounts.name
+GROUP BY Accounts.id, AccountSubtypes.id, AccountTypes.name
+ORDER BY Accounts.number, Accounts.type, Accounts.name
     ",
     bookkeeping.id,
   )
@@ -75,9 +214,17 @@ ORDER BY Accounts.type, Accounts.name
   let g = sqlx::query_as!(GroupingSummary,
     "
 SELECT Groupings.id, Groupings.name, COALESCE(SUM(CASE
-    WHEN AccountChanges.amount > 0 THEN AccountChanges.amount
+    WHEN AccountChanges.amount > 0 AND Transactions.posted THEN AccountChanges.amount
     ELSE 0
-  END),0) AS \"movement!\"
+  END),0) AS \"movement!\",
+  (
+    SELECT COUNT(*) FROM Transactions
+    WHERE Transactions.grouping_id = Groupings.id AND NOT Transactions.posted
+      AND COALESCE((
+        SELECT SUM(AccountChanges.amount) FROM AccountChanges
+        WHERE AccountChanges.transaction_id = Transactions.id
+      ), 0) != 0
+  ) AS \"unbalanced_drafts!\"
   FROM Groupings
   LEFT JOIN Transactions ON Transactions.grouping_id = Groupings.id
   LEFT JOIN AccountChanges ON AccountChanges.transaction_id = Transactions.id
@@ -95,16 +242,73 @@ GROUP BY Groupings.id
     .fetch_all(&state.db)
     .await?
   ;
+  let subtypes = sqlx::query_as!(SubtypeChoice,
+    "SELECT id, name, type FROM AccountSubtypes WHERE bookkeeping_id = $1 ORDER BY type, name",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
 
   html(Index{
     name: bookkeeping.name,
     owner: bookkeeping.owner,
-    accounts: a,
+    locked_until: bookkeeping.locked_until,
+    base_currency: bookkeeping.base_currency,
+    accounts: account_tree(a),
     groupings: g,
     account_types: t,
+    subtypes,
     created: query,
   }.render()?)
 }
+#[derive(Debug, Deserialize)]
+struct LockForm {
+  // Empty to remove the lock
+  locked_until: String,
+}
+// Set the lock date, which only the owner may move backwards since that
+// unlocks reported periods. The change is kept in the audit log.
+async fn lock_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let form: LockForm = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let locked_until = parse_form_date("locked_until", &form.locked_until)?;
+  let mut transaction = begin_as(state, &session).await?;
+  // Compare with the current lock date, locked so it can't change meanwhile
+  let current = sqlx::query!(
+    "SELECT locked_until FROM Bookkeepings WHERE id = $1 FOR UPDATE",
+    bookkeeping.id,
+  )
+    .fetch_one(&mut *transaction)
+    .await?
+    .locked_until
+  ;
+  let unlocks = match (current, locked_until) {
+    (Some(current), Some(new)) => new < current,
+    (Some(_), None) => true,
+    (None, _) => false,
+  };
+  if unlocks && session.user_id != bookkeeping.owner_id {
+    return Err(Error::forbidden());
+  }
+  sqlx::query!(
+    "UPDATE Bookkeepings SET locked_until = $2 WHERE id = $1",
+    bookkeeping.id,
+    locked_until,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
 pub async fn route(
   state: &'static State,
   req: Request,
@@ -116,7 +320,8 @@ pub async fn route(
   // bookkeeping and since most routes want to at least print the name
   let bookkeeping = sqlx::query_as!(Bookkeeping,
     "
-SELECT Bookkeepings.id, Bookkeepings.name, Users.email AS owner
+SELECT Bookkeepings.id, Bookkeepings.name, Users.email AS owner,
+    Bookkeepings.owner_id, Bookkeepings.locked_until, Bookkeepings.base_currency
   FROM Bookkeepings
   LEFT JOIN UsersBookkeepingsAccess ON Bookkeepings.id = bookkeeping_id
   JOIN Users ON Users.id = Bookkeepings.owner_id
@@ -137,9 +342,74 @@ WHERE (Bookkeepings.owner_id = $1 OR UsersBookkeepingsAccess.user_id = $1)
       let created: Created = parse_query(&req)?;
       index(state, req, session, bookkeeping, created).await
     },
+    Some("account-types") => account_types::route(state, req, path_vec, session, bookkeeping).await,
     Some("accounts") => accounts::route(state, req, path_vec, session, bookkeeping).await,
+    Some("currencies") => currencies::route(state, req, path_vec, session, bookkeeping).await,
     Some("groupings") => groupings::route(state, req, path_vec, session, bookkeeping).await,
     Some("imported_account_changes") => imported_account_changes::route(state, req, path_vec, session, bookkeeping).await,
+    Some("recurring") => recurring::route(state, req, path_vec, session, bookkeeping).await,
+    Some("vat") => vat::route(state, req, path_vec, session, bookkeeping).await,
+    Some("lock") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      lock_post(state, req, session, bookkeeping).await
+    },
+    Some("history") => {
+      verify_method_path_end(&path_vec, &req, &Method::GET)?;
+      let query: history::HistoryQuery = parse_query(&req)?;
+      history::index(state, bookkeeping, query).await
+    },
+    Some("sie-export") => {
+      verify_method_path_end(&path_vec, &req, &Method::GET)?;
+      let query: sie_export::SieExportQuery = parse_query(&req)?;
+      sie_export::sie_export(state, bookkeeping, query).await
+    },
     _ => Err(Error::path_not_found(&req)),
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn account(id: i64, parent_id: Option<i64>, balance: i64) -> AccountSummary {
+    AccountSummary{
+      id,
+      name: id.to_string(),
+      r#type: "Asset".to_owned(),
+      balance: Decimal::from(balance),
+      currency: None,
+      original_balance: Decimal::from(balance),
+      parent_id,
+      subtype: None,
+      normal_balance: "Debit".to_owned(),
+    }
+  }
+  fn order(rows: &[AccountTreeRow]) -> Vec<(i64, i64, usize)> {
+    rows.iter()
+      .map(|r| (r.id, r.balance.try_into().unwrap(), r.closes))
+      .collect()
+  }
+
+  #[test]
+  fn sub_accounts_follow_their_parent() {
+    let rows = account_tree(vec![
+      account(3, Some(1), 5),
+      account(1, None, 1),
+      account(2, None, 2),
+      account(4, Some(3), 10),
+      account(5, Some(99), 7),
+    ]);
+    // An account whose parent is missing is shown as a root
+    assert_eq!(order(&rows), [(1, 16, 0), (3, 15, 0), (4, 10, 2), (2, 2, 0), (5, 7, 0)]);
+    assert!(rows[0].has_children && rows[1].has_children && !rows[2].has_children);
+  }
+  #[test]
+  fn accounts_in_a_cycle_are_kept() {
+    let rows = account_tree(vec![
+      account(1, None, 1),
+      account(2, Some(3), 2),
+      account(3, Some(2), 3),
+    ]);
+    assert_eq!(order(&rows), [(1, 1, 0), (2, 5, 0), (3, 3, 1)]);
+  }
+}
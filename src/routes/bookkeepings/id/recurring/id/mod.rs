@@ -0,0 +1,284 @@
+use super::*;
+
+#[derive(Debug)]
+struct Account{
+  id: i64,
+  name: String,
+  t: String,
+  currency: Option<String>,
+}
+#[derive(Debug)]
+struct RecurringLine {
+  id: i64,
+  account_name: String,
+  message: String,
+  amount: Decimal,
+  currency: Option<String>,
+}
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/recurring/id/index.html")]
+struct Index {
+  bookkeeping_name: String,
+  recurring: Recurring,
+  next: Option<Date>,
+  sum: Decimal,
+  lines: Vec<RecurringLine>,
+  accounts_by_type: std::collections::HashMap<String, Vec<Account>>,
+  groupings: Vec<Choice>,
+}
+impl Index {
+  fn is_grouping(&self, id: &i64) -> bool {
+    self.recurring.grouping_id == Some(*id)
+  }
+}
+
+async fn index(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+  recurring: Recurring,
+) -> Result<Response, Error> {
+  let lines = sqlx::query_as!(RecurringLine,
+    "
+SELECT RecurringTransactionLines.id, concat_ws(' ', Accounts.number, Accounts.name) AS \"account_name!\",
+    RecurringTransactionLines.message, RecurringTransactionLines.amount, Accounts.currency
+  FROM RecurringTransactionLines
+  INNER JOIN Accounts ON RecurringTransactionLines.account_id = Accounts.id
+WHERE RecurringTransactionLines.recurring_transaction_id = $1
+ORDER BY RecurringTransactionLines.id
+    ",
+    recurring.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let accounts = sqlx::query_as!(Account,
+    "
+SELECT Accounts.id, concat_ws(' ', Accounts.number, Accounts.name) AS \"name!\", Accounts.type AS t, Accounts.currency
+  FROM Accounts
+WHERE Accounts.bookkeeping_id = $1
+ORDER BY Accounts.number, Accounts.name
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let mut accounts_by_type = std::collections::HashMap::<String,Vec<Account>>::new();
+  for account in accounts {
+    match accounts_by_type.get_mut(&account.t) {
+      Some(x) => x.push(account),
+      None => { accounts_by_type.insert(account.t.clone(), vec![account]); },
+    }
+  }
+  let groupings = sqlx::query_as!(Choice,
+    "SELECT id, name FROM Groupings WHERE bookkeeping_id = $1 ORDER BY name",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  // Lines in other currencies are converted on each occurrence, so this only
+  // tells if the transactions can be posted when all are in the base currency
+  let sum = lines.iter().map(|l| l.amount).sum();
+  let next = recurring.occurrences().next();
+  html(Index{
+    bookkeeping_name: bookkeeping.name,
+    next,
+    recurring,
+    sum,
+    lines,
+    accounts_by_type,
+    groupings,
+  }.render()?)
+}
+
+#[derive(Debug, Deserialize)]
+struct NewLine {
+  account: i64,
+  // Optionally the number of the account, which then takes precedence
+  #[serde(default)]
+  account_number: String,
+  message: String,
+  // In the account's currency
+  amount: Decimal,
+}
+async fn lines_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  recurring: Recurring,
+) -> Result<Response, Error> {
+  let new_line: NewLine = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let mut transaction = begin_as(state, &session).await?;
+  let account_id = match accounts::parse_account_number(&new_line.account_number)? {
+    Some(number) => accounts::account_by_number(&mut transaction, bookkeeping.id, number).await?,
+    None => sqlx::query!(
+      "SELECT id FROM Accounts WHERE id = $1 AND bookkeeping_id = $2",
+      new_line.account,
+      bookkeeping.id,
+    )
+      .fetch_optional(&mut *transaction)
+      .await?
+      .ok_or(ClientError::AccountNotFound(new_line.account))?
+      .id
+    ,
+  };
+  sqlx::query!(
+    "
+INSERT INTO RecurringTransactionLines(recurring_transaction_id, bookkeeping_id, account_id, message, amount)
+  VALUES($1, $2, $3, $4, $5)
+    ",
+    recurring.id,
+    bookkeeping.id,
+    account_id,
+    new_line.message,
+    new_line.amount,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteLine {
+  id: i64,
+}
+async fn delete_line_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  recurring: Recurring,
+) -> Result<Response, Error> {
+  let form: DeleteLine = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "DELETE FROM RecurringTransactionLines WHERE id = $1 AND recurring_transaction_id = $2",
+    form.id,
+    recurring.id,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+
+// Changes only apply to occurrences not yet created
+async fn update_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  recurring: Recurring,
+) -> Result<Response, Error> {
+  let update: NewRecurring = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let schedule = update.schedule(state, &bookkeeping).await?;
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "
+UPDATE RecurringTransactions SET name = $2, schedule = $3, day_of_month = $4,
+    interval_weeks = $5, start_date = $6, end_date = $7, grouping_id = $8
+WHERE id = $1
+    ",
+    recurring.id,
+    update.name,
+    schedule.schedule,
+    schedule.day_of_month,
+    schedule.interval_weeks,
+    update.start_date,
+    schedule.end_date,
+    schedule.grouping_id,
+  )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| map_recurring_conflict(e, &update.name))
+    ?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+
+// The transactions already created are kept
+async fn delete_post(
+  state: &'static State,
+  session: SessionData,
+  recurring: Recurring,
+) -> Result<Response, Error> {
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "DELETE FROM RecurringTransactionLines WHERE recurring_transaction_id = $1",
+    recurring.id,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  sqlx::query!(
+    "DELETE FROM RecurringTransactions WHERE id = $1",
+    recurring.id,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  see_other("../")
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+  recurring_id: i64,
+) -> Result<Response, Error> {
+  let recurring = sqlx::query_as!(Recurring,
+    "
+SELECT id, bookkeeping_id, name, schedule, day_of_month, interval_weeks,
+    start_date, end_date, grouping_id, generated_until
+  FROM RecurringTransactions
+WHERE id = $1 AND bookkeeping_id = $2
+    ",
+    recurring_id,
+    bookkeeping.id,
+  )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or(Error::path_not_found(&req))?
+  ;
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    Some("") => {
+      verify_method_path_end(&path_vec, &req, &Method::GET)?;
+      index(state, bookkeeping, recurring).await
+    },
+    Some("lines") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      lines_post(state, req, session, bookkeeping, recurring).await
+    },
+    Some("delete-line") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      delete_line_post(state, req, session, recurring).await
+    },
+    Some("update") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      update_post(state, req, session, bookkeeping, recurring).await
+    },
+    Some("delete") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      delete_post(state, session, recurring).await
+    },
+    _ => Err(Error::path_not_found(&req)),
+  }
+}
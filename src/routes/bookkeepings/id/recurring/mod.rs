@@ -0,0 +1,580 @@
+use super::*;
+
+use time::{Duration, Month};
+
+mod id;
+
+// A transaction recurring on a schedule, created as a draft on each of its
+// days by the background task calling generate_due
+#[derive(Debug)]
+struct Recurring {
+  id: i64,
+  bookkeeping_id: i64,
+  name: String,
+  schedule: String,
+  day_of_month: Option<i16>,
+  interval_weeks: Option<i16>,
+  start_date: Date,
+  end_date: Option<Date>,
+  grouping_id: Option<i64>,
+  generated_until: Option<Date>,
+}
+impl Recurring {
+  fn schedule_string(&self) -> String {
+    match (self.day_of_month, self.interval_weeks) {
+      (Some(day), _) => format!("Monthly on day {}", day),
+      (_, Some(1)) => "Every week".to_owned(),
+      (_, Some(weeks)) => format!("Every {} weeks", weeks),
+      _ => self.schedule.clone(),
+    }
+  }
+  // The first occurrence on or after the given day
+  fn occurrence_from(&self, from: Date) -> Option<Date> {
+    match (self.day_of_month, self.interval_weeks) {
+      (Some(day), _) => {
+        let this_month = month_day(from.year(), from.month(), day)?;
+        if this_month >= from { Some(this_month) } else { self.occurrence_after(this_month) }
+      },
+      (_, Some(weeks)) => {
+        if from <= self.start_date {
+          return Some(self.start_date);
+        }
+        let step = 7 * i64::from(weeks);
+        let steps = ((from - self.start_date).whole_days() + step - 1) / step;
+        self.start_date.checked_add(Duration::days(steps * step))
+      },
+      _ => None,
+    }
+  }
+  fn occurrence_after(&self, day: Date) -> Option<Date> {
+    match (self.day_of_month, self.interval_weeks) {
+      (Some(day_of_month), _) => {
+        let year = if day.month() == Month::December { day.year() + 1 } else { day.year() };
+        month_day(year, day.month().next(), day_of_month)
+      },
+      (_, Some(weeks)) => day.checked_add(Duration::weeks(weeks.into())),
+      _ => None,
+    }
+  }
+  // The occurrences not yet generated, in order
+  fn occurrences(&self) -> impl Iterator<Item = Date> + '_ {
+    let from = self.generated_until
+      .and_then(|d| d.next_day())
+      .map_or(self.start_date, |d| d.max(self.start_date))
+    ;
+    std::iter::successors(self.occurrence_from(from), |d| self.occurrence_after(*d))
+      .take_while(|d| self.end_date.is_none_or(|end| *d <= end))
+  }
+}
+// The day of the month, or the last day of shorter months
+fn month_day(year: i32, month: Month, day: i16) -> Option<Date> {
+  let last = month.length(year);
+  let day = u8::try_from(day).ok()?.min(last);
+  Date::from_calendar_date(year, month, day).ok()
+}
+
+#[derive(Debug)]
+struct Line {
+  account_id: i64,
+  message: String,
+  amount: Decimal,
+}
+// Create the draft transaction of one occurrence, unless its day is locked
+async fn create_occurrence(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  recurring: &Recurring,
+  lines: &[Line],
+  day: Date,
+) -> Result<bool, Error> {
+  let grouping = match recurring.grouping_id {
+    Some(id) => sqlx::query!(
+      "SELECT id, locked FROM Groupings WHERE id = $1",
+      id,
+    )
+      .fetch_one(&mut **transaction)
+      .await
+      .map(|g| (g.id, g.locked))?
+    ,
+    // Named like the groupings of years created when closing a year
+    None => {
+      let name = day.year().to_string();
+      let existing = sqlx::query!(
+        "SELECT id, locked FROM Groupings WHERE bookkeeping_id = $1 AND name = $2",
+        recurring.bookkeeping_id,
+        name,
+      )
+        .fetch_optional(&mut **transaction)
+        .await?
+      ;
+      match existing {
+        Some(g) => (g.id, g.locked),
+        None => sqlx::query!(
+          "INSERT INTO Groupings(bookkeeping_id, name) VALUES($1, $2) RETURNING id",
+          recurring.bookkeeping_id,
+          name,
+        )
+          .fetch_one(&mut **transaction)
+          .await
+          .map(|g| (g.id, false))?
+        ,
+      }
+    },
+  };
+  // Occurrences in locked periods are skipped, rather than blocking the rest
+  let locked_until = sqlx::query!(
+    "SELECT locked_until FROM Bookkeepings WHERE id = $1",
+    recurring.bookkeeping_id,
+  )
+    .fetch_one(&mut **transaction)
+    .await?
+    .locked_until
+  ;
+  if grouping.1 || locked_until.is_some_and(|l| day <= l) {
+    return Ok(false);
+  }
+  let transaction_id = sqlx::query!(
+    "INSERT INTO Transactions(name, day, grouping_id) VALUES($1, $2, $3) RETURNING id",
+    recurring.name,
+    day,
+    grouping.0,
+  )
+    .fetch_one(&mut **transaction)
+    .await?
+    .id
+  ;
+  for line in lines {
+    let (amount, original_amount) = currencies::to_base(
+      transaction,
+      recurring.bookkeeping_id,
+      line.account_id,
+      day,
+      line.amount,
+      None,
+    ).await?;
+    sqlx::query!(
+      "
+INSERT INTO AccountChanges(transaction_id, account_id, day, message, amount, original_amount)
+  VALUES($1, $2, $3, $4, $5, $6)
+      ",
+      transaction_id,
+      line.account_id,
+      day,
+      line.message,
+      amount,
+      original_amount,
+    )
+      .execute(&mut **transaction)
+      .await?
+    ;
+  }
+  Ok(true)
+}
+// Create the due occurrences of one recurring transaction up to today, and
+// record how far it got in the same database transaction
+async fn generate(
+  state: &'static State,
+  recurring_id: i64,
+  today: Date,
+) -> Result<usize, Error> {
+  let mut transaction = state.db.begin().await?;
+  // Skipped if another server is generating it right now
+  let recurring = sqlx::query_as!(Recurring,
+    "
+SELECT id, bookkeeping_id, name, schedule, day_of_month, interval_weeks,
+    start_date, end_date, grouping_id, generated_until
+  FROM RecurringTransactions
+WHERE id = $1
+FOR UPDATE SKIP LOCKED
+    ",
+    recurring_id,
+  )
+    .fetch_optional(&mut *transaction)
+    .await?
+  ;
+  let recurring = match recurring {
+    Some(r) => r,
+    None => return Ok(0),
+  };
+  let lines = sqlx::query_as!(Line,
+    "
+SELECT account_id, message, amount
+  FROM RecurringTransactionLines
+WHERE recurring_transaction_id = $1
+ORDER BY id
+    ",
+    recurring.id,
+  )
+    .fetch_all(&mut *transaction)
+    .await?
+  ;
+  // Without lines there is nothing to create yet, so the occurrences are kept
+  // for when lines are added
+  if lines.is_empty() {
+    return Ok(0);
+  }
+  let mut created = 0;
+  for day in recurring.occurrences().take_while(|d| *d <= today) {
+    if create_occurrence(&mut transaction, &recurring, &lines, day).await? {
+      created += 1;
+    }
+  }
+  sqlx::query!(
+    "UPDATE RecurringTransactions SET generated_until = $2 WHERE id = $1",
+    recurring.id,
+    today,
+  )
+    .execute(&mut *transaction)
+    .await?
+  ;
+  transaction.commit().await?;
+  Ok(created)
+}
+// Create the draft transactions of all recurring transactions that are due,
+// including those missed while the server was down
+pub async fn generate_due(
+  state: &'static State,
+) -> Result<usize, Error> {
+  let today = time::OffsetDateTime::now_utc().date();
+  let due = sqlx::query!(
+    "
+SELECT id FROM RecurringTransactions
+WHERE generated_until IS NULL OR generated_until < $1
+ORDER BY id
+    ",
+    today,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let mut created = 0;
+  for recurring in due {
+    // One failing, for example on a missing exchange rate, is retried later
+    // without holding the others up
+    match generate(state, recurring.id, today).await {
+      Ok(n) => created += n,
+      Err(e) => eprintln!(
+        "Error when generating recurring transaction {}\n  error: {:?}",
+        recurring.id, e,
+      ),
+    }
+  }
+  Ok(created)
+}
+
+#[derive(Debug, Deserialize)]
+struct NewRecurring {
+  name: String,
+  // Monthly or Weekly, using the field of the same kind below
+  schedule: String,
+  #[serde(default)]
+  day_of_month: String,
+  #[serde(default)]
+  interval_weeks: String,
+  start_date: Date,
+  #[serde(default)]
+  end_date: String,
+  // Empty for the grouping named after the year of each occurrence
+  #[serde(default)]
+  grouping: String,
+}
+struct Schedule {
+  schedule: String,
+  day_of_month: Option<i16>,
+  interval_weeks: Option<i16>,
+  end_date: Option<Date>,
+  grouping_id: Option<i64>,
+}
+impl NewRecurring {
+  // Validate the schedule so it always gives occurrences
+  async fn schedule(
+    &self,
+    state: &'static State,
+    bookkeeping: &Bookkeeping,
+  ) -> Result<Schedule, Error> {
+    let number = |field: &str, value: &str, range: std::ops::RangeInclusive<i16>| {
+      value.trim().parse::<i16>()
+        .ok()
+        .filter(|n| range.contains(n))
+        .ok_or(ClientError::InvalidSchedule(format!(
+          "The {} must be a number from {} to {}",
+          field, range.start(), range.end(),
+        )))
+    };
+    let (day_of_month, interval_weeks) = match self.schedule.as_str() {
+      "Monthly" => (Some(number("day of the month", &self.day_of_month, 1..=31)?), None),
+      "Weekly" => (None, Some(number("number of weeks", &self.interval_weeks, 1..=520)?)),
+      other => return Err(ClientError::InvalidSchedule(format!(
+        "{} is neither Monthly nor Weekly",
+        other,
+      )).into()),
+    };
+    let end_date = parse_form_date("end_date", &self.end_date)?;
+    if end_date.is_some_and(|end| end < self.start_date) {
+      return Err(ClientError::InvalidSchedule(
+        "The end date is before the start date".to_owned()
+      ).into());
+    }
+    let grouping_id = match self.grouping.as_str() {
+      "" => None,
+      id => {
+        let id: i64 = id.parse()?;
+        sqlx::query!(
+          "SELECT id FROM Groupings WHERE id = $1 AND bookkeeping_id = $2",
+          id,
+          bookkeeping.id,
+        )
+          .fetch_optional(&state.db)
+          .await?
+          .ok_or(ClientError::GroupingNotFound(id))?
+        ;
+        Some(id)
+      },
+    };
+    Ok(Schedule{
+      schedule: self.schedule.clone(),
+      day_of_month,
+      interval_weeks,
+      end_date,
+      grouping_id,
+    })
+  }
+}
+fn map_recurring_conflict(e: sqlx::Error, name: &str) -> Error {
+  match e {
+    sqlx::Error::Database(ref dbe) if dbe.is_unique_violation() => {
+      ClientError::AlreadyExists(format!(
+        "A recurring transaction by name {} already exists in this bookkeeping.",
+        name,
+      )).into()
+    },
+    e => e.into(),
+  }
+}
+
+#[derive(Debug)]
+struct RecurringSummary {
+  recurring: Recurring,
+  grouping_name: Option<String>,
+  next: Option<Date>,
+}
+#[derive(Debug)]
+struct Choice {
+  id: i64,
+  name: String,
+}
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/recurring/index.html")]
+struct Index {
+  bookkeeping_name: String,
+  recurring: Vec<RecurringSummary>,
+  groupings: Vec<Choice>,
+}
+
+async fn index(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let recurring = sqlx::query_as!(Recurring,
+    "
+SELECT id, bookkeeping_id, name, schedule, day_of_month, interval_weeks,
+    start_date, end_date, grouping_id, generated_until
+  FROM RecurringTransactions
+WHERE bookkeeping_id = $1
+ORDER BY name
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let groupings = sqlx::query_as!(Choice,
+    "SELECT id, name FROM Groupings WHERE bookkeeping_id = $1 ORDER BY name",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let recurring = recurring.into_iter()
+    .map(|r| {
+      let next = r.occurrences().next();
+      RecurringSummary{
+        grouping_name: r.grouping_id
+          .and_then(|id| groupings.iter().find(|g| g.id == id))
+          .map(|g| g.name.clone()),
+        next,
+        recurring: r,
+      }
+    })
+    .collect()
+  ;
+  html(Index{
+    bookkeeping_name: bookkeeping.name,
+    recurring,
+    groupings,
+  }.render()?)
+}
+async fn index_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let new_recurring: NewRecurring = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let schedule = new_recurring.schedule(state, &bookkeeping).await?;
+  let mut transaction = begin_as(state, &session).await?;
+  let created = sqlx::query!(
+    "
+INSERT INTO RecurringTransactions(bookkeeping_id, name, schedule, day_of_month,
+    interval_weeks, start_date, end_date, grouping_id)
+  VALUES($1, $2, $3, $4, $5, $6, $7, $8)
+  RETURNING id
+    ",
+    bookkeeping.id,
+    new_recurring.name,
+    schedule.schedule,
+    schedule.day_of_month,
+    schedule.interval_weeks,
+    new_recurring.start_date,
+    schedule.end_date,
+    schedule.grouping_id,
+  )
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|e| map_recurring_conflict(e, &new_recurring.name))
+    ?
+    .id
+  ;
+  transaction.commit().await?;
+  // The account changes are added on its own page
+  see_other(&format!("{}/", created))
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    Some("") => {
+      verify_path_end(&path_vec, &req)?;
+      match req.method() {
+        &Method::GET => index(state, bookkeeping).await,
+        &Method::POST => index_post(state, req, session, bookkeeping).await,
+        _ => Err(Error::method_not_found(&req)),
+      }
+    },
+    Some(id) => id::route(state, req, path_vec, session, bookkeeping, id.parse()?).await,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn day(year: i32, month: Month, day: u8) -> Date {
+    Date::from_calendar_date(year, month, day).unwrap()
+  }
+  fn monthly(day_of_month: i16, start_date: Date) -> Recurring {
+    Recurring{
+      id: 1,
+      bookkeeping_id: 1,
+      name: "Rent".to_owned(),
+      schedule: "Monthly".to_owned(),
+      day_of_month: Some(day_of_month),
+      interval_weeks: None,
+      start_date,
+      end_date: None,
+      grouping_id: None,
+      generated_until: None,
+    }
+  }
+  fn weekly(interval_weeks: i16, start_date: Date) -> Recurring {
+    Recurring{
+      schedule: "Weekly".to_owned(),
+      day_of_month: None,
+      interval_weeks: Some(interval_weeks),
+      ..monthly(1, start_date)
+    }
+  }
+
+  #[test]
+  fn month_day_is_clamped_to_the_month_end() {
+    assert_eq!(month_day(2026, Month::January, 31), Some(day(2026, Month::January, 31)));
+    assert_eq!(month_day(2026, Month::April, 31), Some(day(2026, Month::April, 30)));
+    assert_eq!(month_day(2026, Month::February, 30), Some(day(2026, Month::February, 28)));
+    assert_eq!(month_day(2024, Month::February, 30), Some(day(2024, Month::February, 29)));
+    assert_eq!(month_day(2024, Month::February, 29), Some(day(2024, Month::February, 29)));
+    assert_eq!(month_day(2026, Month::March, -1), None);
+  }
+  #[test]
+  fn monthly_occurrences_follow_the_day_of_month() {
+    let recurring = monthly(31, day(2026, Month::January, 1));
+    assert_eq!(
+      recurring.occurrence_from(day(2026, Month::January, 1)),
+      Some(day(2026, Month::January, 31)),
+    );
+    // Past this month's day it is next month's
+    assert_eq!(
+      recurring.occurrence_from(day(2026, Month::April, 30)),
+      Some(day(2026, Month::April, 30)),
+    );
+    assert_eq!(
+      recurring.occurrence_from(day(2026, Month::May, 1)),
+      Some(day(2026, Month::May, 31)),
+    );
+    // The clamped day in shorter months doesn't move the later ones
+    assert_eq!(
+      recurring.occurrence_after(day(2024, Month::February, 29)),
+      Some(day(2024, Month::March, 31)),
+    );
+    assert_eq!(
+      recurring.occurrence_after(day(2026, Month::December, 31)),
+      Some(day(2027, Month::January, 31)),
+    );
+    let recurring = monthly(15, day(2026, Month::January, 1));
+    assert_eq!(
+      recurring.occurrence_from(day(2026, Month::December, 16)),
+      Some(day(2027, Month::January, 15)),
+    );
+  }
+  #[test]
+  fn weekly_occurrences_count_from_the_start_date() {
+    let recurring = weekly(2, day(2026, Month::March, 2));
+    assert_eq!(
+      recurring.occurrence_from(day(2026, Month::January, 1)),
+      Some(day(2026, Month::March, 2)),
+    );
+    assert_eq!(
+      recurring.occurrence_from(day(2026, Month::March, 2)),
+      Some(day(2026, Month::March, 2)),
+    );
+    assert_eq!(
+      recurring.occurrence_from(day(2026, Month::March, 3)),
+      Some(day(2026, Month::March, 16)),
+    );
+    assert_eq!(
+      recurring.occurrence_from(day(2026, Month::March, 16)),
+      Some(day(2026, Month::March, 16)),
+    );
+    assert_eq!(
+      recurring.occurrence_after(day(2026, Month::December, 28)),
+      Some(day(2027, Month::January, 11)),
+    );
+  }
+  #[test]
+  fn occurrences_continue_after_those_generated_until_the_end() {
+    let mut recurring = monthly(31, day(2024, Month::January, 15));
+    recurring.generated_until = Some(day(2024, Month::January, 31));
+    recurring.end_date = Some(day(2024, Month::April, 29));
+    assert_eq!(recurring.occurrences().collect::<Vec<_>>(), vec![
+      day(2024, Month::February, 29),
+      day(2024, Month::March, 31),
+    ]);
+  }
+}
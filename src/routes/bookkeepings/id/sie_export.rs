@@ -0,0 +1,418 @@
+use super::*;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::sie::{self, Field};
+
+#[derive(Debug, Deserialize)]
+pub struct SieExportQuery {
+  // Limit the export to one grouping, instead of the whole bookkeeping
+  grouping: Option<i64>,
+}
+
+#[derive(Debug)]
+struct ExportAccount {
+  id: i64,
+  name: String,
+  number: Option<i32>,
+  r#type: String,
+}
+// A row of a voucher, with the transaction it belongs to
+#[derive(Debug)]
+struct ExportAccountChange {
+  transaction_id: i64,
+  transaction_name: String,
+  transaction_day: Date,
+  account_id: i64,
+  message: String,
+  day: Date,
+  amount: Decimal,
+}
+// Each grouping is exported as its own fiscal year
+#[derive(Debug)]
+struct ExportYear {
+  grouping_id: i64,
+  start: Date,
+  end: Date,
+}
+// What the posted transactions of a grouping did to an account, with the
+// opening balances apart from the rest
+#[derive(Debug)]
+struct Movement {
+  grouping_id: i64,
+  account_id: i64,
+  opening: bool,
+  amount: Decimal,
+}
+// The #IB, #UB and #RES amounts of a year, by account
+#[derive(Debug, Default, PartialEq)]
+struct YearBalances {
+  opening: BTreeMap<i64, Decimal>,
+  closing: BTreeMap<i64, Decimal>,
+  result: BTreeMap<i64, Decimal>,
+}
+
+// How much of the file is gathered before it is sent on
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// SIE identifies accounts by number, so we use the account's number, else the
+// number its name starts with (as from older SIE imports) and fall back to the
+// account id
+fn account_numbers(accounts: &[ExportAccount]) -> HashMap<i64, (String, String)> {
+  // Accounts' own numbers are unique, so they are taken first
+  let mut used: HashSet<String> = accounts.iter()
+    .filter_map(|a| a.number.map(|n| n.to_string()))
+    .collect()
+  ;
+  let mut numbers = HashMap::new();
+  for account in accounts {
+    if let Some(number) = account.number {
+      numbers.insert(account.id, (number.to_string(), account.name.clone()));
+      continue;
+    }
+    let (number, name) = match account.name.split_once(' ') {
+      Some((number, name)) if number.chars().all(|c| c.is_ascii_digit()) => {
+        (number.to_owned(), name.trim().to_owned())
+      },
+      _ if account.name.chars().all(|c| c.is_ascii_digit()) => {
+        (account.name.clone(), String::new())
+      },
+      _ => (account.id.to_string(), account.name.clone()),
+    };
+    let number = if used.contains(&number) { account.id.to_string() } else { number };
+    used.insert(number.clone());
+    numbers.insert(account.id, (number, name));
+  }
+  numbers
+}
+// The account type letters of #KTYP
+fn account_kind(r#type: &str) -> &'static str {
+  match r#type {
+    "Asset" => "T",
+    "Debt" | "Equity" => "S",
+    "Income" => "I",
+    _ => "K",
+  }
+}
+// Follow the balance accounts through the years, in order
+// A year with opening balances starts from them, others from where the year
+// before ended. The opening balances are not part of the year's movement.
+fn year_balances(
+  years: &[ExportYear],
+  movements: &[Movement],
+  balance_accounts: &HashSet<i64>,
+) -> Vec<YearBalances> {
+  let mut balances: BTreeMap<i64, Decimal> = BTreeMap::new();
+  let nonzero = |amounts: &BTreeMap<i64, Decimal>| amounts.iter()
+    .filter(|(_, a)| !a.is_zero())
+    .map(|(id, a)| (*id, *a))
+    .collect()
+  ;
+  years.iter().map(|year| {
+    let year_movements = movements.iter().filter(|m| m.grouping_id == year.grouping_id);
+    if year_movements.clone().any(|m| m.opening) {
+      balances = year_movements.clone()
+        .filter(|m| m.opening && balance_accounts.contains(&m.account_id))
+        .map(|m| (m.account_id, m.amount))
+        .collect()
+      ;
+    }
+    let opening = nonzero(&balances);
+    let mut result = BTreeMap::new();
+    for m in year_movements.filter(|m| !m.opening) {
+      let amounts = if balance_accounts.contains(&m.account_id) { &mut balances } else { &mut result };
+      *amounts.entry(m.account_id).or_default() += m.amount;
+    }
+    YearBalances{
+      opening,
+      closing: nonzero(&balances),
+      result: nonzero(&result),
+    }
+  }).collect()
+}
+// Filenames are put in a header, so keep them to plain ASCII
+fn filename(name: &str) -> String {
+  name.chars()
+    .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+    .collect()
+}
+
+pub async fn sie_export(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+  query: SieExportQuery,
+) -> Result<Response, Error> {
+  let mut export_name = bookkeeping.name.clone();
+  if let Some(grouping_id) = query.grouping {
+    let grouping = sqlx::query!(
+      "SELECT name FROM Groupings WHERE id = $1 AND bookkeeping_id = $2",
+      grouping_id,
+      bookkeeping.id,
+    )
+      .fetch_optional(&state.db)
+      .await?
+      .ok_or(ClientError::GroupingNotFound(grouping_id))?
+    ;
+    export_name = format!("{} {}", export_name, grouping.name);
+  }
+
+  let accounts = sqlx::query_as!(ExportAccount,
+    "SELECT id, name, number, type FROM Accounts WHERE bookkeeping_id = $1 ORDER BY number, name",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  // A year covers the posted transactions of its grouping, and the last year
+  // is the current one. All years are needed to know the balances of the
+  // exported ones.
+  let mut years = sqlx::query_as!(ExportYear,
+    "
+SELECT Groupings.id AS grouping_id,
+    MIN(Transactions.day) AS \"start!\", MAX(Transactions.day) AS \"end!\"
+  FROM Transactions
+  JOIN Groupings ON Groupings.id = Transactions.grouping_id
+WHERE Groupings.bookkeeping_id = $1 AND Transactions.posted
+GROUP BY Groupings.id
+ORDER BY 2, 3, 1
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let today = time::OffsetDateTime::now_utc().date();
+  let exported = |year: &ExportYear| query.grouping.is_none_or(|id| id == year.grouping_id);
+  if !years.iter().any(exported) {
+    // Nothing is posted, so there is only an empty year
+    years.push(ExportYear{ grouping_id: query.grouping.unwrap_or(0), start: today, end: today });
+  }
+  let movements = sqlx::query_as!(Movement,
+    "
+SELECT Groupings.id AS grouping_id, AccountChanges.account_id, Transactions.opening,
+    SUM(AccountChanges.amount) AS \"amount!\"
+  FROM AccountChanges
+  JOIN Transactions ON Transactions.id = AccountChanges.transaction_id
+  JOIN Groupings ON Groupings.id = Transactions.grouping_id
+WHERE Groupings.bookkeeping_id = $1 AND Transactions.posted
+GROUP BY Groupings.id, AccountChanges.account_id, Transactions.opening
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  let balance_accounts: HashSet<i64> = accounts.iter()
+    .filter(|a| is_balance_type(&a.r#type))
+    .map(|a| a.id)
+    .collect()
+  ;
+  let balances = year_balances(&years, &movements, &balance_accounts);
+  let years: Vec<(&ExportYear, YearBalances)> = years.iter()
+    .zip(balances)
+    .filter(|(year, _)| exported(year))
+    .collect()
+  ;
+
+  let numbers = account_numbers(&accounts);
+  let mut sie = sie::Writer::new("bookkeep-web");
+  sie.line("#GEN", &[Field::Value(&sie::format_date(today))]);
+  sie.line("#FNAMN", &[Field::Text(&bookkeeping.name)]);
+  // SIE numbers the current year 0 and the ones before it -1, -2 and so on
+  let year_indexes: Vec<String> = (0..years.len())
+    .map(|i| (i as i64 + 1 - years.len() as i64).to_string())
+    .collect()
+  ;
+  for ((year, _), index) in years.iter().zip(year_indexes.iter()).rev() {
+    sie.line("#RAR", &[
+      Field::Value(index),
+      Field::Value(&sie::format_date(year.start)),
+      Field::Value(&sie::format_date(year.end)),
+    ]);
+  }
+  for account in accounts.iter() {
+    let (number, name) = &numbers[&account.id];
+    sie.line("#KONTO", &[Field::Value(number), Field::Text(name)]);
+    sie.line("#KTYP", &[Field::Value(number), Field::Value(account_kind(&account.r#type))]);
+  }
+  for ((_, balances), index) in years.iter().zip(year_indexes.iter()) {
+    for (label, amounts) in [
+      ("#IB", &balances.opening),
+      ("#UB", &balances.closing),
+      ("#RES", &balances.result),
+    ] {
+      for (account_id, amount) in amounts.iter() {
+        let (number, _) = &numbers[account_id];
+        sie.line(label, &[Field::Value(index), Field::Value(number), Field::Value(&amount.to_string())]);
+      }
+    }
+  }
+
+  // The vouchers are the bulk of the file, so they are sent as they are read
+  let (sender, receiver) = tokio::sync::mpsc::channel::<hyper::body::Bytes>(4);
+  let grouping = query.grouping;
+  tokio::spawn(async move {
+    if let Err(e) = write_vouchers(state, bookkeeping.id, grouping, &numbers, &mut sie, &sender).await {
+      // The response has begun, so the file is just left without its
+      // checksum, which makes importers reject it
+      eprintln!("SIE export of bookkeeping {} failed: {:?}", bookkeeping.id, e);
+      return;
+    }
+    sender.send(sie.finish().into()).await.ok();
+  });
+  let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+    receiver.recv().await.map(|data| (data, receiver))
+  });
+
+  download_stream(
+    stream,
+    "text/plain; charset=IBM437",
+    &format!("{}.se", filename(&export_name)),
+  )
+}
+
+// Writes one #VER per posted transaction, except the opening balances given
+// by #IB, sending the file on as it grows
+async fn write_vouchers(
+  state: &'static State,
+  bookkeeping_id: i64,
+  grouping_id: Option<i64>,
+  numbers: &HashMap<i64, (String, String)>,
+  sie: &mut sie::Writer,
+  sender: &tokio::sync::mpsc::Sender<hyper::body::Bytes>,
+) -> Result<(), Error> {
+  use futures::TryStreamExt;
+  let mut account_changes = sqlx::query_as!(ExportAccountChange,
+    "
+SELECT Transactions.id AS transaction_id, Transactions.name AS transaction_name,
+    Transactions.day AS transaction_day, AccountChanges.account_id,
+    AccountChanges.message, AccountChanges.day, AccountChanges.amount
+  FROM AccountChanges
+  JOIN Transactions ON Transactions.id = AccountChanges.transaction_id
+  JOIN Groupings ON Groupings.id = Transactions.grouping_id
+WHERE Groupings.bookkeeping_id = $1 AND Transactions.posted
+  AND NOT Transactions.opening
+  AND ($2::BIGINT IS NULL OR Groupings.id = $2)
+ORDER BY Transactions.day, Transactions.id, AccountChanges.id
+    ",
+    bookkeeping_id,
+    grouping_id,
+  )
+    .fetch(&state.db)
+  ;
+  let mut current = None;
+  let mut voucher_number = 0;
+  while let Some(change) = account_changes.try_next().await? {
+    if current != Some(change.transaction_id) {
+      if current.is_some() {
+        sie.line("}", &[]);
+        if sie.buffered() >= CHUNK_SIZE && sender.send(sie.take().into()).await.is_err() {
+          // The download was cancelled
+          return Ok(());
+        }
+      }
+      current = Some(change.transaction_id);
+      voucher_number += 1;
+      sie.line("#VER", &[
+        Field::Text("A"),
+        Field::Text(&voucher_number.to_string()),
+        Field::Value(&sie::format_date(change.transaction_day)),
+        Field::Text(&change.transaction_name),
+      ]);
+      sie.line("{", &[]);
+    }
+    let (number, _) = &numbers[&change.account_id];
+    let amount = change.amount.to_string();
+    // The date and text are only given when they add something
+    if change.day == change.transaction_day && change.message.is_empty() {
+      sie.line("#TRANS", &[Field::Value(number), Field::Value("{}"), Field::Value(&amount)]);
+    } else {
+      sie.line("#TRANS", &[
+        Field::Value(number),
+        Field::Value("{}"),
+        Field::Value(&amount),
+        Field::Value(&sie::format_date(change.day)),
+        Field::Text(&change.message),
+      ]);
+    }
+  }
+  if current.is_some() {
+    sie.line("}", &[]);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const BANK: i64 = 1;
+  const EQUITY: i64 = 2;
+  const SALES: i64 = 3;
+
+  fn year(grouping_id: i64, year: i32) -> ExportYear {
+    ExportYear{
+      grouping_id,
+      start: Date::from_calendar_date(year, time::Month::January, 1).unwrap(),
+      end: Date::from_calendar_date(year, time::Month::December, 31).unwrap(),
+    }
+  }
+  fn movement(grouping_id: i64, account_id: i64, opening: bool, amount: i64) -> Movement {
+    Movement{ grouping_id, account_id, opening, amount: Decimal::from(amount) }
+  }
+  fn amounts(amounts: &[(i64, i64)]) -> BTreeMap<i64, Decimal> {
+    amounts.iter().map(|(id, a)| (*id, Decimal::from(*a))).collect()
+  }
+
+  #[test]
+  fn closed_year_and_its_successor() {
+    let years = [year(1, 2025), year(2, 2026)];
+    let movements = [
+      // A sale, and the year-end closing moving its result to equity
+      movement(1, BANK, false, 50),
+      movement(1, SALES, false, -50),
+      movement(1, SALES, false, 50),
+      movement(1, EQUITY, false, -50),
+      // The opening balances the closing carried over, then another sale
+      movement(2, BANK, true, 50),
+      movement(2, EQUITY, true, -50),
+      movement(2, BANK, false, 100),
+      movement(2, SALES, false, -100),
+    ];
+    let balances = year_balances(&years, &movements, &HashSet::from([BANK, EQUITY]));
+    assert_eq!(balances, [
+      YearBalances{
+        opening: amounts(&[]),
+        closing: amounts(&[(BANK, 50), (EQUITY, -50)]),
+        result: amounts(&[]),
+      },
+      // Opened from the opening balances, not from them on top of 2025's end
+      YearBalances{
+        opening: amounts(&[(BANK, 50), (EQUITY, -50)]),
+        closing: amounts(&[(BANK, 150), (EQUITY, -50)]),
+        result: amounts(&[(SALES, -100)]),
+      },
+    ]);
+  }
+  #[test]
+  fn years_without_opening_balances_carry_on() {
+    let years = [year(1, 2025), year(2, 2026), year(3, 2027)];
+    let movements = [
+      // Imported opening balances of the first year
+      movement(1, BANK, true, 10),
+      movement(1, EQUITY, true, -10),
+      movement(1, BANK, false, 5),
+      movement(1, SALES, false, -5),
+      movement(3, BANK, false, -15),
+      movement(3, EQUITY, false, 15),
+    ];
+    let balances = year_balances(&years, &movements, &HashSet::from([BANK, EQUITY]));
+    assert_eq!(balances[0].opening, amounts(&[(BANK, 10), (EQUITY, -10)]));
+    assert_eq!(balances[0].closing, amounts(&[(BANK, 15), (EQUITY, -10)]));
+    assert_eq!(balances[0].result, amounts(&[(SALES, -5)]));
+    assert_eq!(balances[1].opening, balances[0].closing);
+    assert_eq!(balances[1].closing, balances[0].closing);
+    assert_eq!(balances[2].opening, balances[0].closing);
+    assert_eq!(balances[2].closing, amounts(&[(EQUITY, 5)]));
+  }
+}
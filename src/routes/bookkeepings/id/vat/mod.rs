@@ -0,0 +1,958 @@
+use super::*;
+
+use std::collections::BTreeMap;
+
+// VAT codes tell how account changes are taxed, so the VAT lines for them can
+// be created and the Swedish VAT return (momsdeklaration) summed up per box
+
+#[derive(Debug)]
+struct VatCode {
+  name: String,
+  // In percent
+  rate: Decimal,
+  direction: String,
+  kind: String,
+  output_account_id: Option<i64>,
+  input_account_id: Option<i64>,
+}
+
+// The VAT return only has boxes for these rates
+fn is_return_rate(rate: Decimal) -> bool {
+  [Decimal::ZERO, Decimal::from(25), Decimal::from(12), Decimal::from(6)].contains(&rate)
+}
+// Output VAT is charged by the seller on domestic sales and purchases, else
+// the buyer pays it on purchases, where it is both owed and deducted
+fn charged_by_seller(kind: &str) -> bool {
+  kind == "Domestic"
+}
+
+// The accounts and roles of the VAT lines created for an account change
+#[derive(Debug)]
+struct VatLine {
+  account_id: i64,
+  amount: Decimal,
+  role: &'static str,
+}
+// Split an amount in the base currency, with VAT included if it was paid to
+// the seller, into the amount VAT is charged on and the VAT lines for it
+fn vat_lines(
+  code: &VatCode,
+  amount: Decimal,
+  includes_vat: bool,
+) -> Result<(Decimal, Vec<VatLine>), Error> {
+  let hundred = Decimal::ONE_HUNDRED;
+  let (base, vat) = if includes_vat && charged_by_seller(&code.kind) {
+    let base = (amount * hundred / (hundred + code.rate)).round_dp(2);
+    (base, amount - base)
+  } else {
+    (amount, (amount * code.rate / hundred).round_dp(2))
+  };
+  if vat.is_zero() {
+    return Ok((base, Vec::new()));
+  }
+  let account = |id: Option<i64>, which: &str| id.ok_or(ClientError::InvalidVatCode(format!(
+    "{} has no {} VAT account",
+    code.name,
+    which,
+  )));
+  let lines = match (code.direction.as_str(), charged_by_seller(&code.kind)) {
+    ("Output", _) => vec![
+      VatLine{ account_id: account(code.output_account_id, "output")?, amount: vat, role: "Output" },
+    ],
+    (_, true) => vec![
+      VatLine{ account_id: account(code.input_account_id, "input")?, amount: vat, role: "Input" },
+    ],
+    (_, false) => vec![
+      VatLine{ account_id: account(code.output_account_id, "output")?, amount: -vat, role: "Output" },
+      VatLine{ account_id: account(code.input_account_id, "input")?, amount: vat, role: "Input" },
+    ],
+  };
+  Ok((base, lines))
+}
+
+// An account change to create, optionally with a VAT code and whether the
+// amount includes the VAT
+#[derive(Debug)]
+pub struct NewChange<'a> {
+  pub transaction_id: i64,
+  pub account_id: i64,
+  pub day: Date,
+  pub message: &'a str,
+  // In the account's currency
+  pub amount: Decimal,
+  pub base_amount: Option<Decimal>,
+  pub vat: Option<(i64, bool)>,
+}
+// Convert the amount of an account change into the base currency, and with a
+// VAT code split off the VAT lines for it on the code's accounts (which are in
+// the base currency)
+async fn split_change(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping_id: i64,
+  change: &NewChange<'_>,
+) -> Result<(Decimal, Option<Decimal>, Vec<VatLine>), Error> {
+  let (amount, original_amount) = currencies::to_base(
+    transaction,
+    bookkeeping_id,
+    change.account_id,
+    change.day,
+    change.amount,
+    change.base_amount,
+  ).await?;
+  let Some((vat_code_id, includes_vat)) = change.vat else {
+    return Ok((amount, original_amount, Vec::new()));
+  };
+  let code = sqlx::query_as!(VatCode,
+    "
+SELECT name, rate, direction, kind, output_account_id, input_account_id
+  FROM VatCodes
+WHERE id = $1 AND bookkeeping_id = $2
+    ",
+    vat_code_id,
+    bookkeeping_id,
+  )
+    .fetch_optional(&mut **transaction)
+    .await?
+    .ok_or(ClientError::VatCodeNotFound(vat_code_id))?
+  ;
+  let (base, lines) = vat_lines(&code, amount, includes_vat)?;
+  // The original amount is split in the same proportion
+  let original_amount = match base != amount {
+    true => original_amount.map(|o| (o * base / amount).round_dp(2)),
+    false => original_amount,
+  };
+  Ok((base, original_amount, lines))
+}
+async fn insert_vat_lines(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  vat_base_id: i64,
+  change: &NewChange<'_>,
+  lines: Vec<VatLine>,
+) -> Result<(), Error> {
+  for line in lines {
+    sqlx::query!(
+      "
+INSERT INTO AccountChanges(account_id, day, message, amount, transaction_id, vat_code_id, vat_role, vat_base_id)
+  VALUES($1, $2, $3, $4, $5, $6, $7, $8)
+      ",
+      line.account_id,
+      change.day,
+      change.message,
+      line.amount,
+      change.transaction_id,
+      change.vat.map(|(id, _)| id),
+      line.role,
+      vat_base_id,
+    )
+      .execute(&mut **transaction)
+      .await?
+    ;
+  }
+  Ok(())
+}
+// Create the account change, and with a VAT code the VAT lines for it.
+// Returns the id of the account change VAT is charged on.
+pub async fn insert_account_change(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping_id: i64,
+  change: &NewChange<'_>,
+) -> Result<i64, Error> {
+  let (amount, original_amount, lines) = split_change(transaction, bookkeeping_id, change).await?;
+  let vat_code_id = change.vat.map(|(id, _)| id);
+  let vat_role = vat_code_id.map(|_| "Base");
+  let created = sqlx::query!(
+    "
+INSERT INTO AccountChanges(account_id, day, message, amount, original_amount, transaction_id, vat_code_id, vat_role)
+  VALUES($1, $2, $3, $4, $5, $6, $7, $8)
+  RETURNING id
+    ",
+    change.account_id,
+    change.day,
+    change.message,
+    amount,
+    original_amount,
+    change.transaction_id,
+    vat_code_id,
+    vat_role,
+  )
+    .fetch_one(&mut **transaction)
+    .await?
+    .id
+  ;
+  insert_vat_lines(transaction, created, change, lines).await?;
+  Ok(created)
+}
+// Update the account change, recreating its VAT lines for the new amount and
+// VAT code. The VAT lines themselves can't be updated, only recreated.
+pub async fn update_account_change(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping_id: i64,
+  account_change_id: i64,
+  change: &NewChange<'_>,
+) -> Result<(), Error> {
+  let (amount, original_amount, lines) = split_change(transaction, bookkeeping_id, change).await?;
+  sqlx::query!(
+    "DELETE FROM AccountChanges WHERE vat_base_id = $1",
+    account_change_id,
+  )
+    .execute(&mut **transaction)
+    .await?
+  ;
+  let vat_code_id = change.vat.map(|(id, _)| id);
+  let vat_role = vat_code_id.map(|_| "Base");
+  sqlx::query!(
+    "
+UPDATE AccountChanges
+  SET account_id = $2, day = $3, message = $4, amount = $5, original_amount = $6,
+    vat_code_id = $7, vat_role = $8
+WHERE id = $1
+    ",
+    account_change_id,
+    change.account_id,
+    change.day,
+    change.message,
+    amount,
+    original_amount,
+    vat_code_id,
+    vat_role,
+  )
+    .execute(&mut **transaction)
+    .await?
+  ;
+  insert_vat_lines(transaction, account_change_id, change, lines).await
+}
+// Forms send no VAT code as an empty string, and the checkbox only if checked
+pub fn parse_vat(
+  vat_code: &str,
+  includes_vat: &str,
+) -> Result<Option<(i64, bool)>, Error> {
+  match vat_code {
+    "" => Ok(None),
+    id => Ok(Some((id.parse()?, !includes_vat.is_empty()))),
+  }
+}
+
+// The boxes of the VAT return, in the order of the eSKD format, with the
+// names of their elements in it
+const BOXES: &[(u8, &str, &str)] = &[
+  (5, "Momspliktig försäljning som inte ingår i ruta 06, 07 eller 08", "ForsMomsEjAnnan"),
+  (6, "Momspliktiga uttag", "UttagMoms"),
+  (7, "Beskattningsunderlag vid vinstmarginalbeskattning", "UlagMargbesk"),
+  (8, "Hyresinkomster vid frivillig skattskyldighet", "HyrinkomstFriv"),
+  (20, "Inköp av varor från ett annat EU-land", "InkopVaruAnnatEg"),
+  (21, "Inköp av tjänster från ett annat EU-land enligt huvudregeln", "InkopTjanstAnnatEg"),
+  (22, "Inköp av tjänster från ett land utanför EU", "InkopTjanstUtomEg"),
+  (23, "Inköp av varor i Sverige som köparen är skattskyldig för", "InkopVaruSverige"),
+  (24, "Övriga inköp av tjänster", "InkopTjanstSverige"),
+  (50, "Beskattningsunderlag vid import", "MomsUlagImport"),
+  (35, "Försäljning av varor till ett annat EU-land", "ForsVaruAnnatEg"),
+  (36, "Försäljning av varor utanför EU", "ForsVaruUtomEg"),
+  (37, "Mellanmans inköp av varor vid trepartshandel", "InkopVaruMellan3p"),
+  (38, "Mellanmans försäljning av varor vid trepartshandel", "ForsVaruMellan3p"),
+  (39, "Försäljning av tjänster till en näringsidkare i ett annat EU-land enligt huvudregeln", "ForsTjSkskAnnatEg"),
+  (40, "Övrig försäljning av tjänster omsatta utanför Sverige", "ForsTjOvrUtomEg"),
+  (41, "Försäljning när köparen är skattskyldig i Sverige", "ForsKopareSkskSverige"),
+  (42, "Övrig försäljning m.m.", "ForsOvrigt"),
+  (10, "Utgående moms 25 %", "MomsUtgHog"),
+  (11, "Utgående moms 12 %", "MomsUtgMedel"),
+  (12, "Utgående moms 6 %", "MomsUtgLag"),
+  (30, "Utgående moms 25 % på inköp i ruta 20-24", "MomsInkopUtgHog"),
+  (31, "Utgående moms 12 % på inköp i ruta 20-24", "MomsInkopUtgMedel"),
+  (32, "Utgående moms 6 % på inköp i ruta 20-24", "MomsInkopUtgLag"),
+  (60, "Utgående moms 25 % på import", "MomsImportUtgHog"),
+  (61, "Utgående moms 12 % på import", "MomsImportUtgMedel"),
+  (62, "Utgående moms 6 % på import", "MomsImportUtgLag"),
+  (48, "Ingående moms att dra av", "MomsIngAvdr"),
+  (49, "Moms att betala eller få tillbaka", "MomsBetala"),
+];
+// The boxes with output VAT, which box 49 sums up less the input VAT
+const OUTPUT_VAT_BOXES: &[u8] = &[10, 11, 12, 30, 31, 32, 60, 61, 62];
+
+// The box the amounts of a VAT code with the given role are reported in
+fn vat_box(direction: &str, kind: &str, rate: Decimal, role: &str) -> Option<u8> {
+  // Of the boxes for 25, 12 and 6 percent, which follow each other
+  let by_rate = |first: u8| [25, 12, 6].iter()
+    .position(|r| rate == Decimal::from(*r))
+    .map(|i| first + i as u8)
+  ;
+  match (direction, kind, role) {
+    ("Output", "Domestic", "Base") if rate.is_zero() => Some(42),
+    ("Output", "Domestic", "Base") => Some(5),
+    ("Output", "Domestic", "Output") => by_rate(10),
+    ("Output", "ReverseCharge", "Base") => Some(41),
+    ("Output", "EUGoods", "Base") => Some(35),
+    ("Output", "EUServices", "Base") => Some(39),
+    ("Output", "NonEUGoods", "Base") => Some(36),
+    ("Output", "NonEUServices", "Base") => Some(40),
+    ("Input", "ReverseCharge", "Base") => Some(24),
+    ("Input", "EUGoods", "Base") => Some(20),
+    ("Input", "EUServices", "Base") => Some(21),
+    ("Input", "NonEUServices", "Base") => Some(22),
+    ("Input", "NonEUGoods", "Base") => Some(50),
+    ("Input", "NonEUGoods", "Output") => by_rate(60),
+    ("Input", _, "Output") => by_rate(30),
+    ("Input", _, "Input") => Some(48),
+    _ => None,
+  }
+}
+
+#[derive(Debug)]
+struct VatSum {
+  rate: Decimal,
+  direction: String,
+  kind: String,
+  vat_role: String,
+  amount: Decimal,
+}
+// Sum the posted account changes with VAT codes in the period into the boxes,
+// in whole kronor as they are reported with the öre left out
+async fn vat_return(
+  state: &'static State,
+  bookkeeping_id: i64,
+  from: Date,
+  to: Date,
+) -> Result<BTreeMap<u8, Decimal>, Error> {
+  let sums = sqlx::query_as!(VatSum,
+    "
+SELECT VatCodes.rate, VatCodes.direction, VatCodes.kind, AccountChanges.vat_role AS \"vat_role!\",
+    SUM(AccountChanges.amount) AS \"amount!\"
+  FROM AccountChanges
+  INNER JOIN Transactions ON Transactions.id = AccountChanges.transaction_id AND Transactions.posted
+  INNER JOIN VatCodes ON VatCodes.id = AccountChanges.vat_code_id
+WHERE VatCodes.bookkeeping_id = $1 AND AccountChanges.day BETWEEN $2 AND $3
+GROUP BY VatCodes.id, AccountChanges.vat_role
+    ",
+    bookkeeping_id,
+    from,
+    to,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  Ok(sum_boxes(&sums))
+}
+// The sums of VAT codes and roles put into their boxes, with box 49 the VAT
+// to pay (or get back if negative)
+fn sum_boxes(sums: &[VatSum]) -> BTreeMap<u8, Decimal> {
+  let mut boxes: BTreeMap<u8, Decimal> = BOXES.iter().map(|b| (b.0, Decimal::ZERO)).collect();
+  for sum in sums {
+    if let Some(number) = vat_box(&sum.direction, &sum.kind, sum.rate, &sum.vat_role) {
+      // Sales and owed VAT are credited, so they are reported negated
+      let amount = match (sum.direction.as_str(), sum.vat_role.as_str()) {
+        ("Output", _) | (_, "Output") => -sum.amount,
+        _ => sum.amount,
+      };
+      *boxes.entry(number).or_default() += amount;
+    }
+  }
+  for amount in boxes.values_mut() {
+    *amount = amount.trunc();
+  }
+  let output_vat: Decimal = OUTPUT_VAT_BOXES.iter().map(|b| boxes[b]).sum();
+  boxes.insert(49, output_vat - boxes[&48]);
+  boxes
+}
+
+#[derive(Debug)]
+struct VatCodeSummary {
+  id: i64,
+  name: String,
+  rate: Decimal,
+  direction: String,
+  kind: String,
+  output_account: Option<String>,
+  input_account: Option<String>,
+  account_changes: i64,
+}
+#[derive(Debug)]
+struct Account {
+  id: i64,
+  name: String,
+}
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/vat/index.html")]
+struct Index {
+  bookkeeping_name: String,
+  vat_codes: Vec<VatCodeSummary>,
+  accounts: Vec<Account>,
+}
+
+async fn index(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let vat_codes = sqlx::query_as!(VatCodeSummary,
+    "
+SELECT VatCodes.id, VatCodes.name, VatCodes.rate, VatCodes.direction, VatCodes.kind,
+    concat_ws(' ', OutputAccount.number, OutputAccount.name) AS output_account,
+    concat_ws(' ', InputAccount.number, InputAccount.name) AS input_account,
+    (SELECT COUNT(*) FROM AccountChanges WHERE AccountChanges.vat_code_id = VatCodes.id)
+      AS \"account_changes!\"
+  FROM VatCodes
+  LEFT JOIN Accounts AS OutputAccount ON OutputAccount.id = VatCodes.output_account_id
+  LEFT JOIN Accounts AS InputAccount ON InputAccount.id = VatCodes.input_account_id
+WHERE VatCodes.bookkeeping_id = $1
+ORDER BY VatCodes.direction DESC, VatCodes.name
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  // VAT lines are in the base currency
+  let accounts = sqlx::query_as!(Account,
+    "
+SELECT id, concat_ws(' ', number, name) AS \"name!\"
+  FROM Accounts
+WHERE bookkeeping_id = $1 AND currency IS NULL
+ORDER BY number, type, name
+    ",
+    bookkeeping.id,
+  )
+    .fetch_all(&state.db)
+    .await?
+  ;
+  html(Index{
+    bookkeeping_name: bookkeeping.name,
+    vat_codes,
+    accounts,
+  }.render()?)
+}
+
+#[derive(Debug, Deserialize)]
+struct NewVatCode {
+  name: String,
+  rate: Decimal,
+  direction: String,
+  kind: String,
+  // Empty for none
+  #[serde(default)]
+  output_account: String,
+  #[serde(default)]
+  input_account: String,
+}
+impl NewVatCode {
+  // Check that the code can be reported, and has the accounts its VAT lines
+  // are created on
+  async fn accounts(
+    &self,
+    transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+    bookkeeping_id: i64,
+  ) -> Result<(Option<i64>, Option<i64>), Error> {
+    let invalid = |message: &str| -> Error { ClientError::InvalidVatCode(message.to_owned()).into() };
+    if !["Output", "Input"].contains(&self.direction.as_str()) {
+      return Err(invalid("The direction must be Output or Input"));
+    }
+    if !is_return_rate(self.rate) {
+      return Err(invalid("The VAT return only has boxes for rates of 25, 12 and 6 percent"));
+    }
+    if self.direction == "Output" && !charged_by_seller(&self.kind) && !self.rate.is_zero() {
+      return Err(invalid("No VAT is charged on sales abroad or to buyers paying the VAT, so the rate must be 0"));
+    }
+    let output = vat_account(transaction, bookkeeping_id, "output", &self.output_account).await?;
+    let input = vat_account(transaction, bookkeeping_id, "input", &self.input_account).await?;
+    if !self.rate.is_zero() {
+      let needs_output = self.direction == "Output" || !charged_by_seller(&self.kind);
+      let needs_input = self.direction == "Input";
+      if needs_output && output.is_none() {
+        return Err(invalid("VAT owed is put on the output VAT account, which must be given"));
+      }
+      if needs_input && input.is_none() {
+        return Err(invalid("VAT deducted is put on the input VAT account, which must be given"));
+      }
+    }
+    Ok((output, input))
+  }
+}
+// VAT lines are created without original amounts, so their accounts must be
+// in the base currency
+async fn vat_account(
+  transaction: &mut sqlx::Transaction<'static, sqlx::Postgres>,
+  bookkeeping_id: i64,
+  field: &str,
+  value: &str,
+) -> Result<Option<i64>, Error> {
+  let id: i64 = match value {
+    "" => return Ok(None),
+    id => id.parse()?,
+  };
+  let account = sqlx::query!(
+    "SELECT currency FROM Accounts WHERE id = $1 AND bookkeeping_id = $2",
+    id,
+    bookkeeping_id,
+  )
+    .fetch_optional(&mut **transaction)
+    .await?
+    .ok_or(ClientError::AccountNotFound(id))?
+  ;
+  if account.currency.is_some() {
+    return Err(ClientError::InvalidVatCode(format!(
+      "The {} VAT account must be in the base currency",
+      field,
+    )).into());
+  }
+  Ok(Some(id))
+}
+async fn codes_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let new_code: NewVatCode = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let mut transaction = begin_as(state, &session).await?;
+  let (output_account_id, input_account_id) = new_code.accounts(
+    &mut transaction,
+    bookkeeping.id,
+  ).await?;
+  sqlx::query!(
+    "
+INSERT INTO VatCodes(bookkeeping_id, name, rate, direction, kind, output_account_id, input_account_id)
+  VALUES($1, $2, $3, $4, $5, $6, $7)
+    ",
+    bookkeeping.id,
+    new_code.name,
+    new_code.rate,
+    new_code.direction,
+    new_code.kind,
+    output_account_id,
+    input_account_id,
+  )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| -> Error { match e {
+      sqlx::Error::Database(ref dbe) if dbe.is_unique_violation() => {
+        ClientError::AlreadyExists(format!(
+          "A VAT code by name {} already exists in this bookkeeping.",
+          new_code.name,
+        )).into()
+      },
+      sqlx::Error::Database(ref dbe) if dbe.is_check_violation() => {
+        ClientError::InvalidVatCode(format!(
+          "{} is not a kind of VAT code",
+          new_code.kind,
+        )).into()
+      },
+      e => e.into(),
+    }})
+    ?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteVatCode {
+  id: i64,
+}
+async fn delete_code_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  let form: DeleteVatCode = parse_body_urlencoded(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  let mut transaction = begin_as(state, &session).await?;
+  sqlx::query!(
+    "DELETE FROM VatCodes WHERE id = $1 AND bookkeeping_id = $2",
+    form.id,
+    bookkeeping.id,
+  )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| -> Error { match e {
+      sqlx::Error::Database(ref dbe) if dbe.is_foreign_key_violation() => {
+        Error::still_referenced("VAT code", dbe.table())
+      },
+      e => e.into(),
+    }})
+    ?
+  ;
+  transaction.commit().await?;
+  see_other("./")
+}
+
+#[derive(Debug, Deserialize)]
+struct ReportQuery {
+  from: Date,
+  to: Date,
+}
+#[derive(Debug)]
+struct ReportBox {
+  number: u8,
+  name: &'static str,
+  amount: Decimal,
+}
+#[derive(Debug, Template)]
+#[template(path = "bookkeepings/id/vat/report.html")]
+struct Report {
+  bookkeeping_name: String,
+  from: Date,
+  to: Date,
+  boxes: Vec<ReportBox>,
+}
+async fn report(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+  query: ReportQuery,
+) -> Result<Response, Error> {
+  let amounts = vat_return(state, bookkeeping.id, query.from, query.to).await?;
+  let mut boxes: Vec<ReportBox> = BOXES.iter()
+    .map(|(number, name, _)| ReportBox{ number: *number, name, amount: amounts[number] })
+    .collect()
+  ;
+  boxes.sort_by_key(|b| b.number);
+  html(Report{
+    bookkeeping_name: bookkeeping.name,
+    from: query.from,
+    to: query.to,
+    boxes,
+  }.render()?)
+}
+
+#[derive(Debug, Deserialize)]
+struct EskdQuery {
+  from: Date,
+  to: Date,
+  org_number: String,
+}
+// Organisation numbers are given with or without the century and a hyphen,
+// and end with a Luhn check digit
+fn org_number(given: &str) -> Result<String, Error> {
+  let number: String = given.chars()
+    .filter(|c| !matches!(c, '-' | ' '))
+    .collect()
+  ;
+  let invalid = || -> Error { ClientError::InvalidOrganisationNumber(given.to_owned()).into() };
+  if !matches!(number.len(), 10 | 12) || !number.chars().all(|c| c.is_ascii_digit()) {
+    return Err(invalid());
+  }
+  let checksum: u32 = number[number.len() - 10..].bytes()
+    .enumerate()
+    .map(|(i, b)| {
+      let digit = u32::from(b - b'0') * if i % 2 == 0 { 2 } else { 1 };
+      digit / 10 + digit % 10
+    })
+    .sum()
+  ;
+  if !checksum.is_multiple_of(10) {
+    return Err(invalid());
+  }
+  Ok(number)
+}
+// VAT is reported per month, calendar quarter or fiscal year, so the period
+// must be a whole one of those
+fn return_period(from: Date, to: Date) -> Result<(), Error> {
+  let invalid = |reason: &str| -> Error { ClientError::InvalidVatPeriod(format!(
+    "{} to {}: {}",
+    from,
+    to,
+    reason,
+  )).into() };
+  if from > to {
+    return Err(invalid("the period ends before it starts"));
+  }
+  if from.day() != 1 || to.next_day().is_some_and(|d| d.month() == to.month()) {
+    return Err(invalid("the period must start and end with whole months"));
+  }
+  let months = (to.year() - from.year()) * 12 + i32::from(u8::from(to.month()))
+    - i32::from(u8::from(from.month())) + 1;
+  match months {
+    1 | 12 => Ok(()),
+    3 if u8::from(from.month()) % 3 == 1 => Ok(()),
+    _ => Err(invalid("the period must be a month, calendar quarter or year")),
+  }
+}
+// The VAT return as a file for uploading to Skatteverket, for the period
+// ending with the month of the last day
+async fn eskd(
+  state: &'static State,
+  bookkeeping: Bookkeeping,
+  query: EskdQuery,
+) -> Result<Response, Error> {
+  let org_number = org_number(&query.org_number)?;
+  return_period(query.from, query.to)?;
+  let amounts = vat_return(state, bookkeeping.id, query.from, query.to).await?;
+  let period = format!("{}{:02}", query.to.year(), u8::from(query.to.month()));
+  let mut xml = String::from(concat!(
+    "<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\n",
+    "<!DOCTYPE eSKDUpload PUBLIC \"-//Skatteverket, Sweden//DTD Skatteverket eSKDUpload-DTD Version 6.0//SV\" ",
+    "\"https://www1.skatteverket.se/demoeskd/eSKDUpload_6p0.dtd\">\n",
+    "<eSKDUpload Version=\"6.0\">\n",
+  ));
+  xml.push_str(&format!("  <OrgNr>{}</OrgNr>\n  <Moms>\n    <Period>{}</Period>\n", org_number, period));
+  // Empty boxes are left out, except the amount to pay
+  for (number, _, element) in BOXES {
+    let amount = amounts[number];
+    if !amount.is_zero() || *number == 49 {
+      xml.push_str(&format!("    <{0}>{1}</{0}>\n", element, amount));
+    }
+  }
+  xml.push_str("  </Moms>\n</eSKDUpload>\n");
+  download(
+    xml,
+    "application/xml",
+    &format!("moms-{}.xml", period),
+  )
+}
+
+pub async fn route(
+  state: &'static State,
+  req: Request,
+  mut path_vec: Vec<String>,
+  session: SessionData,
+  bookkeeping: Bookkeeping,
+) -> Result<Response, Error> {
+  match path_vec.pop().as_deref() {
+    None => permanent_redirect(&format!("{}/", req.uri().path())),
+    Some("") => {
+      verify_method_path_end(&path_vec, &req, &Method::GET)?;
+      index(state, bookkeeping).await
+    },
+    Some("codes") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      codes_post(state, req, session, bookkeeping).await
+    },
+    Some("delete-code") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      delete_code_post(state, req, session, bookkeeping).await
+    },
+    Some("report") => {
+      verify_method_path_end(&path_vec, &req, &Method::GET)?;
+      let query: ReportQuery = parse_query(&req)?;
+      report(state, bookkeeping, query).await
+    },
+    Some("eskd") => {
+      verify_method_path_end(&path_vec, &req, &Method::GET)?;
+      let query: EskdQuery = parse_query(&req)?;
+      eskd(state, bookkeeping, query).await
+    },
+    _ => Err(Error::path_not_found(&req)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn d(amount: &str) -> Decimal {
+    amount.parse().unwrap()
+  }
+  fn code(rate: &str, direction: &str, kind: &str) -> VatCode {
+    VatCode{
+      name: "Test".to_owned(),
+      rate: d(rate),
+      direction: direction.to_owned(),
+      kind: kind.to_owned(),
+      output_account_id: Some(1),
+      input_account_id: Some(2),
+    }
+  }
+  fn lines(lines: &[VatLine]) -> Vec<(i64, Decimal, &'static str)> {
+    lines.iter().map(|l| (l.account_id, l.amount, l.role)).collect()
+  }
+
+  #[test]
+  fn output_vat_is_added_to_sales() {
+    let (base, vat) = vat_lines(&code("25", "Output", "Domestic"), d("-1000"), false).unwrap();
+    assert_eq!(base, d("-1000"));
+    assert_eq!(lines(&vat), vec![(1, d("-250"), "Output")]);
+  }
+  #[test]
+  fn vat_is_taken_out_of_amounts_including_it() {
+    let (base, vat) = vat_lines(&code("25", "Output", "Domestic"), d("-1250"), true).unwrap();
+    assert_eq!(base, d("-1000"));
+    assert_eq!(lines(&vat), vec![(1, d("-250"), "Output")]);
+    let (base, vat) = vat_lines(&code("25", "Input", "Domestic"), d("1250"), true).unwrap();
+    assert_eq!(base, d("1000"));
+    assert_eq!(lines(&vat), vec![(2, d("250"), "Input")]);
+  }
+  #[test]
+  fn vat_included_is_rounded_so_the_lines_sum_to_the_amount() {
+    // 100 / 1.12 = 89.2857...
+    let (base, vat) = vat_lines(&code("12", "Input", "Domestic"), d("100"), true).unwrap();
+    assert_eq!(base, d("89.29"));
+    assert_eq!(lines(&vat), vec![(2, d("10.71"), "Input")]);
+    // 99.99 / 1.06 = 94.330...
+    let (base, vat) = vat_lines(&code("6", "Output", "Domestic"), d("-99.99"), true).unwrap();
+    assert_eq!(base, d("-94.33"));
+    assert_eq!(lines(&vat), vec![(1, d("-5.66"), "Output")]);
+  }
+  #[test]
+  fn vat_on_amounts_without_it_is_rounded_to_ore() {
+    let (base, vat) = vat_lines(&code("12", "Input", "Domestic"), d("10.05"), false).unwrap();
+    assert_eq!(base, d("10.05"));
+    assert_eq!(lines(&vat), vec![(2, d("1.21"), "Input")]);
+  }
+  #[test]
+  fn buyer_paying_the_vat_both_owes_and_deducts_it() {
+    for kind in ["ReverseCharge", "EUGoods", "EUServices", "NonEUGoods", "NonEUServices"] {
+      // The seller charges no VAT, so it can't be included in the amount
+      for includes_vat in [false, true] {
+        let (base, vat) = vat_lines(&code("25", "Input", kind), d("1000"), includes_vat).unwrap();
+        assert_eq!(base, d("1000"));
+        assert_eq!(lines(&vat), vec![(1, d("-250"), "Output"), (2, d("250"), "Input")]);
+      }
+    }
+  }
+  #[test]
+  fn zero_rate_creates_no_vat_lines() {
+    let mut code = code("0", "Output", "EUServices");
+    code.output_account_id = None;
+    let (base, vat) = vat_lines(&code, d("-1000"), true).unwrap();
+    assert_eq!(base, d("-1000"));
+    assert!(vat.is_empty());
+  }
+  #[test]
+  fn missing_vat_account_is_an_error() {
+    let mut code = code("25", "Input", "EUGoods");
+    code.input_account_id = None;
+    assert!(matches!(
+      vat_lines(&code, d("1000"), false),
+      Err(Error::ClientError(ClientError::InvalidVatCode(_))),
+    ));
+  }
+
+  #[test]
+  fn org_numbers_are_luhn_checked() {
+    assert_eq!(org_number("556036-0793").unwrap(), "5560360793");
+    assert_eq!(org_number("16 556036-0793").unwrap(), "165560360793");
+    assert_eq!(org_number("202100-5489").unwrap(), "2021005489");
+    for invalid in ["556036-0794", "556036-079", "5560360793x", ""] {
+      assert!(org_number(invalid).is_err(), "{}", invalid);
+    }
+  }
+  #[test]
+  fn returns_are_for_a_month_quarter_or_year() {
+    let day = |y: i32, m: u8, d: u8| Date::from_calendar_date(y, m.try_into().unwrap(), d).unwrap();
+    assert!(return_period(day(2026, 2, 1), day(2026, 2, 28)).is_ok());
+    assert!(return_period(day(2024, 2, 1), day(2024, 2, 29)).is_ok());
+    assert!(return_period(day(2026, 4, 1), day(2026, 6, 30)).is_ok());
+    assert!(return_period(day(2026, 1, 1), day(2026, 12, 31)).is_ok());
+    // Fiscal years needn't follow the calendar
+    assert!(return_period(day(2025, 7, 1), day(2026, 6, 30)).is_ok());
+    for (from, to) in [
+      (day(2026, 3, 1), day(2026, 2, 28)),
+      (day(2026, 3, 2), day(2026, 3, 31)),
+      (day(2026, 3, 1), day(2026, 3, 30)),
+      (day(2024, 2, 1), day(2024, 2, 28)),
+      (day(2026, 2, 1), day(2026, 4, 30)),
+      (day(2026, 1, 1), day(2026, 2, 28)),
+      (day(2026, 1, 1), day(2027, 1, 31)),
+    ] {
+      assert!(return_period(from, to).is_err(), "{} to {}", from, to);
+    }
+  }
+
+  #[test]
+  fn every_code_and_role_has_its_box() {
+    let cases = [
+      ("Output", "Domestic", "25", "Base", Some(5)),
+      ("Output", "Domestic", "0", "Base", Some(42)),
+      ("Output", "Domestic", "25", "Output", Some(10)),
+      ("Output", "Domestic", "12", "Output", Some(11)),
+      ("Output", "Domestic", "6", "Output", Some(12)),
+      ("Output", "ReverseCharge", "0", "Base", Some(41)),
+      ("Output", "EUGoods", "0", "Base", Some(35)),
+      ("Output", "EUServices", "0", "Base", Some(39)),
+      ("Output", "NonEUGoods", "0", "Base", Some(36)),
+      ("Output", "NonEUServices", "0", "Base", Some(40)),
+      ("Output", "Domestic", "25", "Input", None),
+      ("Input", "Domestic", "25", "Base", None),
+      ("Input", "Domestic", "25", "Input", Some(48)),
+      ("Input", "ReverseCharge", "25", "Base", Some(24)),
+      ("Input", "EUGoods", "25", "Base", Some(20)),
+      ("Input", "EUServices", "25", "Base", Some(21)),
+      ("Input", "NonEUServices", "25", "Base", Some(22)),
+      ("Input", "NonEUGoods", "25", "Base", Some(50)),
+      ("Input", "ReverseCharge", "25", "Output", Some(30)),
+      ("Input", "EUGoods", "12", "Output", Some(31)),
+      ("Input", "EUServices", "6", "Output", Some(32)),
+      ("Input", "NonEUServices", "25", "Output", Some(30)),
+      ("Input", "NonEUGoods", "25", "Output", Some(60)),
+      ("Input", "NonEUGoods", "12", "Output", Some(61)),
+      ("Input", "NonEUGoods", "6", "Output", Some(62)),
+      ("Input", "EUGoods", "25", "Input", Some(48)),
+      ("Input", "NonEUGoods", "25", "Input", Some(48)),
+    ];
+    for (direction, kind, rate, role, expected) in cases {
+      assert_eq!(
+        vat_box(direction, kind, d(rate), role),
+        expected,
+        "{} {} {} {}", direction, kind, rate, role,
+      );
+    }
+  }
+  #[test]
+  fn every_box_is_in_the_return() {
+    for direction in ["Output", "Input"] {
+      for kind in ["Domestic", "ReverseCharge", "EUGoods", "EUServices", "NonEUGoods", "NonEUServices"] {
+        for rate in ["25", "12", "6", "0"] {
+          for role in ["Base", "Output", "Input"] {
+            if let Some(number) = vat_box(direction, kind, d(rate), role) {
+              assert!(BOXES.iter().any(|b| b.0 == number), "box {}", number);
+            }
+          }
+        }
+      }
+    }
+  }
+
+  fn sum(rate: &str, direction: &str, kind: &str, vat_role: &str, amount: &str) -> VatSum {
+    VatSum{
+      rate: d(rate),
+      direction: direction.to_owned(),
+      kind: kind.to_owned(),
+      vat_role: vat_role.to_owned(),
+      amount: d(amount),
+    }
+  }
+  #[test]
+  fn credited_amounts_are_reported_positive() {
+    let boxes = sum_boxes(&[
+      sum("25", "Output", "Domestic", "Base", "-1000"),
+      sum("25", "Output", "Domestic", "Output", "-250"),
+      sum("25", "Input", "EUServices", "Base", "400"),
+      sum("25", "Input", "EUServices", "Output", "-100"),
+      sum("25", "Input", "EUServices", "Input", "100"),
+    ]);
+    assert_eq!(boxes[&5], d("1000"));
+    assert_eq!(boxes[&10], d("250"));
+    assert_eq!(boxes[&21], d("400"));
+    assert_eq!(boxes[&30], d("100"));
+    assert_eq!(boxes[&48], d("100"));
+  }
+  #[test]
+  fn boxes_are_in_whole_kronor() {
+    let boxes = sum_boxes(&[
+      sum("25", "Output", "Domestic", "Base", "-1000.99"),
+      sum("25", "Output", "Domestic", "Output", "-250.25"),
+      sum("25", "Input", "Domestic", "Input", "100.75"),
+    ]);
+    assert_eq!(boxes[&5], d("1000"));
+    assert_eq!(boxes[&10], d("250"));
+    assert_eq!(boxes[&48], d("100"));
+    assert_eq!(boxes[&49], d("150"));
+  }
+  #[test]
+  fn box_49_is_output_less_input_vat() {
+    let boxes = sum_boxes(&[
+      sum("25", "Output", "Domestic", "Output", "-250"),
+      sum("12", "Output", "Domestic", "Output", "-24"),
+      sum("6", "Input", "EUGoods", "Output", "-6"),
+      sum("25", "Input", "NonEUGoods", "Output", "-50"),
+      sum("25", "Input", "Domestic", "Input", "600"),
+      sum("6", "Input", "EUGoods", "Input", "6"),
+      sum("25", "Input", "NonEUGoods", "Input", "50"),
+    ]);
+    assert_eq!(boxes[&49], d("-326"));
+    // Nothing to pay is reported as zero
+    assert_eq!(sum_boxes(&[])[&49], Decimal::ZERO);
+  }
+}
@@ -1,6 +1,10 @@
 use super::*;
 
+mod chart_templates;
 mod id;
+mod sie_import;
+
+pub use id::generate_recurring_transactions;
 
 #[derive(Debug)]
 struct Bookkeeping {
@@ -13,6 +17,7 @@ struct Bookkeeping {
 struct Index {
   email: String,
   bookkeepings: Vec<Bookkeeping>,
+  templates: &'static [chart_templates::ChartTemplate],
 }
 
 async fn index(
@@ -39,11 +44,15 @@ WHERE bookkeepings.owner_id = $1 OR UsersBookkeepingsAccess.user_id = $1
   html(Index{
     email: session.email,
     bookkeepings,
+    templates: chart_templates::TEMPLATES,
   }.render()?)
 }
 #[derive(Debug,Deserialize)]
 struct NewBookkeeping{
   name: String,
+  // Template id, or "copy:" and a bookkeeping id, empty for no accounts
+  #[serde(default)]
+  chart: String,
 }
 async fn index_post(
   state: &'static State,
@@ -58,12 +67,13 @@ async fn index_post(
 
   // No validation needed, invalid data can't be represented
   // Insert into database
+  let mut transaction = begin_as(state, &session).await?;
   let created = sqlx::query!(
     "INSERT INTO Bookkeepings(name, owner_id) VALUES($1, $2) RETURNING id",
     new_bookkeeping.name,
     session.user_id,
   )
-    .fetch_one(&state.db)
+    .fetch_one(&mut *transaction)
     .await
     .map_err(|e| -> Error { match e {
       sqlx::Error::Database(ref dbe) if dbe.is_unique_violation() => {
@@ -77,6 +87,16 @@ async fn index_post(
     ?
     .id
   ;
+  // Created in the same transaction, so a failure leaves no empty bookkeeping
+  if !new_bookkeeping.chart.is_empty() {
+    chart_templates::create_accounts(
+      &mut transaction,
+      &session,
+      created,
+      &new_bookkeeping.chart,
+    ).await?;
+  }
+  transaction.commit().await?;
 
   // Return a the created object
   see_other(&format!("{}/", created))
@@ -127,6 +147,10 @@ pub async fn route(
         _ => Err(Error::method_not_found(&req)),
       }
     },
+    Some("sie-import") => {
+      verify_method_path_end(&path_vec, &req, &Method::POST)?;
+      sie_import::sie_import_post(state, req, session).await
+    },
     // Parse the path into an integer id and keep routing
     Some(id) => id::route(state, req, path_vec, session, id.parse()?).await,
   }
@@ -0,0 +1,322 @@
+use super::*;
+
+use std::collections::{HashMap, HashSet};
+
+use crate::sie;
+
+#[derive(Debug, Deserialize)]
+struct SieImportForm {
+  // Left empty to use the company name in the file
+  #[serde(default)]
+  name: String,
+}
+
+// Accounts are typed by #KTYP if given, otherwise by the number range of the
+// BAS chart of accounts. #KTYP doesn't tell equity from debt, so that is also
+// taken from the number.
+fn account_type(account: &sie::Account) -> &'static str {
+  let range = account.number.get(..2).and_then(|n| n.parse::<u8>().ok());
+  match (account.kind.as_deref(), range) {
+    (Some("T"), _) => "Asset",
+    (Some("S"), Some(20)) => "Equity",
+    (Some("S"), _) => "Debt",
+    (Some("I"), _) => "Income",
+    (Some("K"), _) => "Expense",
+    (_, Some(10..=19)) => "Asset",
+    (_, Some(20)) => "Equity",
+    (_, Some(21..=29)) => "Debt",
+    (_, Some(30..=39)) => "Income",
+    (_, Some(40..=79)) => "Expense",
+    (_, Some(80..=83)) => "Income",
+    _ => "Expense",
+  }
+}
+// Names are limited to 64 characters in the database
+fn truncate(name: &str) -> String {
+  name.chars().take(64).collect::<String>().trim_end().to_owned()
+}
+// Calendar years are named by the year, others by their first and last day
+fn grouping_name(year: &sie::FiscalYear) -> String {
+  if year.start.year() == year.end.year()
+    && year.start.ordinal() == 1
+    && year.end.month() == time::Month::December
+    && year.end.day() == 31
+  {
+    year.start.year().to_string()
+  } else {
+    format!("{} - {}", year.start, year.end)
+  }
+}
+// The year whose #IB the bookkeeping starts from, which is the first year with
+// vouchers as the later years' balances follow from those vouchers. Files
+// usually give #IB for the year before too, without its vouchers.
+fn opening_year(file: &sie::SieFile) -> Option<&sie::FiscalYear> {
+  let has_vouchers = |year: &sie::FiscalYear| file.vouchers.iter()
+    .any(|v| year.start <= v.date && v.date <= year.end)
+  ;
+  let has_balances = |year: &sie::FiscalYear| file.opening_balances.iter()
+    .any(|b| b.year == year.index)
+  ;
+  let mut years: Vec<&sie::FiscalYear> = file.fiscal_years.iter().collect();
+  years.sort_by_key(|y| y.index);
+  match years.iter().find(|y| has_vouchers(y)) {
+    // A first year without #IB starts from nothing, as for a new company
+    Some(year) => Some(*year).filter(|y| has_balances(y)),
+    // Without vouchers the current balances are the ones to start from
+    None => years.into_iter().rev().find(|y| has_balances(y)),
+  }
+}
+
+pub async fn sie_import_post(
+  state: &'static State,
+  mut req: Request,
+  session: SessionData,
+) -> Result<Response, Error> {
+  // Parse out the submitted name and file
+  let (form, mut files): (SieImportForm, _) = parse_body_multipart(
+    &mut req,
+    state.max_content_len,
+  ).await?;
+  match files.next_file().await? {
+    Some(file) if file.name == "sie_file" => (),
+    _ => return Err(Error::multipart_field_missing("sie_file")),
+  }
+  let file = sie::parse(&files.read_to_end().await?)?;
+
+  let name = match (form.name.trim(), file.company_name.as_deref()) {
+    ("", Some(company_name)) => truncate(company_name),
+    ("", None) => return Err(Error::multipart_field_missing("name")),
+    (name, _) => truncate(name),
+  };
+  if file.fiscal_years.is_empty() {
+    return Err(ClientError::InvalidImportFile(
+      "No fiscal year (#RAR) in file".to_owned()
+    ).into());
+  }
+
+  // Create everything in one transaction, so a failure creates nothing
+  let mut transaction = begin_as(state, &session).await?;
+  let bookkeeping_id = sqlx::query!(
+    "INSERT INTO Bookkeepings(name, owner_id) VALUES($1, $2) RETURNING id",
+    name,
+    session.user_id,
+  )
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|e| -> Error { match e {
+      sqlx::Error::Database(ref dbe) if dbe.is_unique_violation() => {
+        ClientError::AlreadyExists(format!(
+          "A Bookkeeping by name {} already exists.",
+          name,
+        )).into()
+      },
+      e => e.into(),
+    }})
+    ?
+    .id
+  ;
+
+  // Accounts are referred to by number in the file, so map them to our ids
+  // Accounts used without a #KONTO are created with the number as name
+  let mut accounts: HashMap<&str, i64> = HashMap::new();
+  let mut names: HashSet<String> = HashSet::new();
+  let undeclared: Vec<sie::Account> = file.opening_balances.iter()
+    .map(|b| &b.account)
+    .chain(file.vouchers.iter().flat_map(|v| v.transactions.iter().map(|t| &t.account)))
+    .filter(|number| !file.accounts.iter().any(|a| &a.number == *number))
+    .map(|number| sie::Account{
+      number: number.to_owned(),
+      name: String::new(),
+      kind: None,
+    })
+    .collect()
+  ;
+  for account in file.accounts.iter().chain(undeclared.iter()) {
+    if accounts.contains_key(account.number.as_str()) {
+      continue;
+    }
+    // Numbers that don't fit ours are kept in the name instead, as are those
+    // of accounts named like another, since names are unique
+    let number = account.number.parse::<i32>().ok().filter(|n| *n >= 0);
+    let mut name = match (number, account.name.as_str()) {
+      (Some(_), "") => account.number.clone(),
+      (Some(_), name) => truncate(name.trim()),
+      (None, name) => truncate(&format!("{} {}", account.number, name.trim())),
+    };
+    if names.contains(&name) {
+      name = truncate(&format!("{} {}", account.number, name));
+    }
+    let id = sqlx::query!(
+      "INSERT INTO Accounts(bookkeeping_id, name, number, type) VALUES($1, $2, $3, $4) RETURNING id",
+      bookkeeping_id,
+      name,
+      number,
+      account_type(account),
+    )
+      .fetch_one(&mut *transaction)
+      .await
+      .map_err(|e| -> Error { match e {
+        sqlx::Error::Database(ref dbe) if dbe.is_unique_violation() => {
+          ClientError::InvalidImportFile(format!(
+            "Account {} can't be told apart from another by its name {}",
+            account.number,
+            name,
+          )).into()
+        },
+        e => e.into(),
+      }})
+      ?
+      .id
+    ;
+    names.insert(name);
+    accounts.insert(&account.number, id);
+  }
+
+  // One grouping per fiscal year, which the transactions are sorted into
+  let mut groupings = Vec::new();
+  for year in file.fiscal_years.iter() {
+    let id = sqlx::query!(
+      "INSERT INTO Groupings(bookkeeping_id, name) VALUES($1, $2) RETURNING id",
+      bookkeeping_id,
+      grouping_name(year),
+    )
+      .fetch_one(&mut *transaction)
+      .await?
+      .id
+    ;
+    groupings.push((year, id));
+  }
+
+  if let Some((year, grouping_id)) = opening_year(&file)
+    .and_then(|opening| groupings.iter().find(|(y, _)| y.index == opening.index))
+  {
+    // Posted if the balances add up, otherwise left as a draft to look into
+    let balances = file.opening_balances.iter().filter(|b| b.year == year.index);
+    let posted = balances.clone().map(|b| b.amount).sum::<Decimal>().is_zero();
+    let transaction_id = sqlx::query!(
+      "
+INSERT INTO Transactions(name, day, grouping_id, posted, opening)
+  VALUES($1, $2, $3, $4, TRUE)
+  RETURNING id
+      ",
+      "Opening balances",
+      year.start,
+      grouping_id,
+      posted,
+    )
+      .fetch_one(&mut *transaction)
+      .await?
+      .id
+    ;
+    for balance in balances {
+      sqlx::query!(
+        "
+INSERT INTO AccountChanges(transaction_id, account_id, day, amount)
+  VALUES($1, $2, $3, $4)
+        ",
+        transaction_id,
+        accounts[balance.account.as_str()],
+        year.start,
+        balance.amount,
+      )
+        .execute(&mut *transaction)
+        .await?
+      ;
+    }
+  }
+
+  for voucher in file.vouchers.iter() {
+    let grouping_id = groupings.iter()
+      .find(|(y, _)| y.start <= voucher.date && voucher.date <= y.end)
+      .ok_or_else(|| ClientError::InvalidImportFile(format!(
+        "Voucher {}{} dated {} is outside of the fiscal years",
+        voucher.series,
+        voucher.number,
+        voucher.date,
+      )))?
+      .1
+    ;
+    let posted = voucher.transactions.iter().map(|t| t.amount).sum::<Decimal>().is_zero();
+    let transaction_id = sqlx::query!(
+      "
+INSERT INTO Transactions(name, day, grouping_id, posted)
+  VALUES($1, $2, $3, $4)
+  RETURNING id
+      ",
+      truncate(&format!("{}{} {}", voucher.series, voucher.number, voucher.text)),
+      voucher.date,
+      grouping_id,
+      posted,
+    )
+      .fetch_one(&mut *transaction)
+      .await?
+      .id
+    ;
+    for trans in voucher.transactions.iter() {
+      sqlx::query!(
+        "
+INSERT INTO AccountChanges(transaction_id, account_id, message, day, amount)
+  VALUES($1, $2, $3, $4, $5)
+        ",
+        transaction_id,
+        accounts[trans.account.as_str()],
+        trans.text.chars().take(256).collect::<String>(),
+        trans.date.unwrap_or(voucher.date),
+        trans.amount,
+      )
+        .execute(&mut *transaction)
+        .await?
+      ;
+    }
+  }
+  transaction.commit().await?;
+
+  // Send the user to the created bookkeeping
+  see_other(&format!("{}/", bookkeeping_id))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn opening_index(text: &str) -> Option<i32> {
+    let file = sie::parse(text.as_bytes()).unwrap();
+    opening_year(&file).map(|y| y.index)
+  }
+  const YEARS: &str = "#RAR 0 20260101 20261231
+#RAR -1 20250101 20251231
+#IB 0 1930 150
+#IB 0 2099 -150
+#IB -1 1930 100
+#IB -1 2099 -100
+";
+
+  #[test]
+  fn starts_from_the_first_year_with_vouchers() {
+    // Only the current year's vouchers are given, so its #IB is used
+    let current = format!("{YEARS}#VER A 1 20260105 \"\"\n{{\n#TRANS 1930 {{}} 5\n#TRANS 3001 {{}} -5\n}}\n");
+    assert_eq!(opening_index(&current), Some(0));
+    // With the year before's vouchers too, its #IB is the start
+    let both = format!("{current}#VER A 1 20250105 \"\"\n{{\n#TRANS 1930 {{}} 50\n#TRANS 2099 {{}} -50\n}}\n");
+    assert_eq!(opening_index(&both), Some(-1));
+  }
+  #[test]
+  fn without_vouchers_the_current_balances_are_used() {
+    assert_eq!(opening_index(YEARS), Some(0));
+    assert_eq!(opening_index("#RAR 0 20260101 20261231\n#RAR -1 20250101 20251231\n#IB -1 1930 1\n"), Some(-1));
+    assert_eq!(opening_index("#RAR 0 20260101 20261231\n"), None);
+  }
+  #[test]
+  fn first_year_without_balances_starts_from_nothing() {
+    let text = "#RAR 0 20260101 20261231
+#RAR -1 20250101 20251231
+#IB 0 1930 150
+#VER A 1 20250105 \"\"
+{
+#TRANS 1930 {} 150
+#TRANS 2099 {} -150
+}
+";
+    assert_eq!(opening_index(text), None);
+  }
+}
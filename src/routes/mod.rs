@@ -11,6 +11,7 @@ use crate::{
   ClientError,
   Request,
   Response,
+  full,
 };
 
 // A utils file for common operations while routing
@@ -21,6 +22,7 @@ use auth::*;
 
 // And the actual route modules
 mod bookkeepings;
+pub use bookkeepings::generate_recurring_transactions;
 
 const CSS: &'static str = include_str!("styles.css");
 
@@ -0,0 +1,22 @@
+use super::*;
+
+//
+// Database helpers
+//
+
+// Begin a database transaction on behalf of the logged in user, which the
+// audit log triggers record as the user behind the changes made in it
+pub async fn begin_as(
+  state: &'static State,
+  session: &SessionData,
+) -> Result<sqlx::Transaction<'static, sqlx::Postgres>, Error> {
+  let mut transaction = state.db.begin().await?;
+  sqlx::query!(
+    "SELECT set_config('bookkeep.user_id', $1, TRUE)",
+    session.user_id.to_string(),
+  )
+    .fetch_one(&mut *transaction)
+    .await?
+  ;
+  Ok(transaction)
+}
@@ -10,3 +10,5 @@ mod response;
 pub use response::*;
 mod routing;
 pub use routing::*;
+mod database;
+pub use database::*;
@@ -45,6 +45,18 @@ pub fn parse_query<T: DeserializeOwned>(
   let filter: T = serde_urlencoded::from_str(query_str)?;
   Ok(filter)
 }
+// Forms send date inputs left blank as empty strings, which mean no date
+pub fn parse_form_date(
+  field: &str,
+  value: &str,
+) -> Result<Option<Date>, Error> {
+  if value.is_empty() {
+    return Ok(None);
+  }
+  Date::parse(value, &time::format_description::well_known::Iso8601::DATE)
+    .map(Some)
+    .map_err(|e| ClientError::InvalidUrlEncoding(format!("{}: {}", field, e)).into())
+}
 pub fn parse_cookies<'a>(
   req: &'a Request,
 ) -> Result<std::collections::HashMap<&'a str, &'a str>, Error> {
@@ -137,3 +149,384 @@ pub async fn parse_body_urlencoded<T: DeserializeOwned>(
   let data: T = serde_urlencoded::from_bytes(&bytes)?;
   Ok(data)
 }
+// Header information about one part of a multipart/form-data submission
+#[derive(Debug)]
+pub struct MultipartPart {
+  pub name: String,
+  // Only set for file fields
+  pub filename: Option<String>,
+}
+// Where in the multipart body the reader is currently positioned
+#[derive(Debug, PartialEq, Eq)]
+enum MultipartState {
+  Data, // In the data of a part (or the preamble before the first part)
+  Boundary, // Just after a boundary, before the headers of the next part
+  Finished, // After the final boundary
+}
+// Streaming reader over the parts of a multipart/form-data body
+// Created by parse_body_multipart, which also hands out the text fields
+// before the first file, after which this gives access to the files.
+pub struct Multipart<'a, B = hyper::body::Incoming> {
+  body: &'a mut B,
+  // Boundary with its leading newline, which marks the end of each part
+  delimiter: Vec<u8>,
+  // Received bytes not yet handed out
+  buffer: Vec<u8>,
+  received: usize,
+  expected_len: usize,
+  state: MultipartState,
+  // A file part found while parsing text fields, not yet handed out
+  pending_file: Option<MultipartPart>,
+}
+// Largest size of the headers of a part, to not buffer garbage forever
+const MAX_PART_HEADERS_LEN: usize = 8192;
+impl<B> Multipart<'_, B>
+where
+  B: hyper::body::Body<Data = hyper::body::Bytes> + Unpin,
+  Error: From<B::Error>,
+{
+  // Receive another frame of data into the buffer
+  // Returns false if the body has ended
+  async fn fill(&mut self) -> Result<bool, Error> {
+    use http_body_util::BodyExt; // Provides the .frame() future on body
+
+    // Trailer frames mean there won't be more data, same as in get_body
+    let data = match self.body.frame().await {
+      Some(result) => result?.into_data().ok(),
+      None => None,
+    };
+    match data {
+      Some(data) => {
+        self.received += data.len();
+        if self.received > self.expected_len {
+          return Err(Error::content_length_mismatch(self.received, self.expected_len));
+        }
+        self.buffer.extend_from_slice(&data);
+        Ok(true)
+      },
+      None if self.received < self.expected_len => {
+        Err(Error::content_length_mismatch(self.received, self.expected_len))
+      },
+      None => Ok(false),
+    }
+  }
+  // Get the next chunk of data in the current part, None if the part ended
+  pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, Error> {
+    if self.state != MultipartState::Data {
+      return Ok(None);
+    }
+    loop {
+      match find_bytes(&self.buffer, &self.delimiter) {
+        // If the boundary is first in the buffer the part has ended
+        Some(0) => {
+          self.buffer.drain(..self.delimiter.len());
+          self.state = MultipartState::Boundary;
+          return Ok(None);
+        },
+        // Otherwise hand out everything before it
+        Some(end) => {
+          return Ok(Some(self.buffer.drain(..end).collect()));
+        },
+        // Without a boundary we can hand out all but the tail of the buffer,
+        // since the tail could be the beginning of a boundary
+        None => {
+          let safe_len = self.buffer.len()
+            .saturating_sub(self.delimiter.len() - 1)
+          ;
+          if safe_len > 0 {
+            return Ok(Some(self.buffer.drain(..safe_len).collect()));
+          }
+          if !self.fill().await? {
+            return Err(Error::invalid_multipart("Body ended without final boundary"));
+          }
+        },
+      }
+    }
+  }
+  // Get all the remaining data in the current part
+  pub async fn read_to_end(&mut self) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    while let Some(chunk) = self.chunk().await? {
+      data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+  }
+  // Skip any unread data in the current part and parse the headers of the next
+  async fn next_part(&mut self) -> Result<Option<MultipartPart>, Error> {
+    while self.chunk().await?.is_some() {}
+    if self.state == MultipartState::Finished {
+      return Ok(None);
+    }
+    // After each boundary comes either "--" for the end or a newline
+    while self.buffer.len() < 2 {
+      if !self.fill().await? {
+        return Err(Error::invalid_multipart("Body ended without final boundary"));
+      }
+    }
+    if self.buffer.starts_with(b"--") {
+      self.state = MultipartState::Finished;
+      return Ok(None);
+    }
+    if !self.buffer.starts_with(b"\r\n") {
+      return Err(Error::invalid_multipart("Invalid boundary line"));
+    }
+    // Then the part headers, separated from the data by an empty line
+    let header_end = loop {
+      if let Some(end) = find_bytes(&self.buffer[2..], b"\r\n\r\n") {
+        break end + 2;
+      }
+      if self.buffer.len() > MAX_PART_HEADERS_LEN {
+        return Err(Error::invalid_multipart("Part headers too long"));
+      }
+      if !self.fill().await? {
+        return Err(Error::invalid_multipart("Part headers never end"));
+      }
+    };
+    let headers: Vec<u8> = self.buffer.drain(..header_end + 4).collect();
+    let headers = std::str::from_utf8(&headers[2..header_end])
+      .map_err(|_| Error::invalid_multipart("Part headers are not UTF-8"))?
+    ;
+    self.state = MultipartState::Data;
+
+    // Of the headers only Content-Disposition tells us what we need
+    let disposition = headers.split("\r\n")
+      .find_map(|h| {
+        let (name, value) = h.split_once(':')?;
+        if name.eq_ignore_ascii_case("Content-Disposition") {
+          Some(value.trim())
+        } else {
+          None
+        }
+      })
+      .ok_or(Error::invalid_multipart("Part without Content-Disposition"))?
+    ;
+    Ok(Some(MultipartPart{
+      name: get_header_param(disposition, "name")
+        .ok_or(Error::invalid_multipart("Part without name"))?
+        .to_owned(),
+      filename: get_header_param(disposition, "filename").map(|f| f.to_owned()),
+    }))
+  }
+  // Get the next file part, whose data is then read through chunk() or
+  // read_to_end(). Text fields are only accepted before the first file.
+  pub async fn next_file(&mut self) -> Result<Option<MultipartPart>, Error> {
+    if let Some(file) = self.pending_file.take() {
+      return Ok(Some(file));
+    }
+    match self.next_part().await? {
+      Some(part) if part.filename.is_none() => Err(Error::invalid_multipart(
+        &format!("Text field {} sent after a file", part.name)
+      )),
+      part => Ok(part),
+    }
+  }
+}
+// Get the value of a parameter in a header, such as boundary in Content-Type
+fn get_header_param<'a>(
+  header: &'a str,
+  param_name: &str,
+) -> Option<&'a str> {
+  header.split(';').skip(1).find_map(|param| {
+    let (name, value) = param.trim().split_once('=')?;
+    if name.eq_ignore_ascii_case(param_name) {
+      Some(value.trim_matches('"'))
+    } else {
+      None
+    }
+  })
+}
+// Find the first occurence of needle in haystack
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+  haystack.windows(needle.len()).position(|w| w == needle)
+}
+// Try to parse the text fields in a multipart form submission (needed to
+// upload files) into object of type T, just like parse_body_urlencoded.
+// Returns the Multipart positioned at the first file, for streaming the files.
+// Uses validate_get_content_len to verify Content-Length < max_len
+pub async fn parse_body_multipart<T: DeserializeOwned>(
+  req: &mut Request,
+  max_len: usize,
+) -> Result<(T, Multipart<'_>), Error> {
+  // Verify content type and get out the boundary between parts
+  let content_type = get_header(req, "Content-Type")?.unwrap_or("");
+  if !content_type.starts_with("multipart/form-data") {
+    return Err(Error::invalid_content_type(
+      "multipart/form-data",
+      content_type,
+    ));
+  }
+  let boundary = get_header_param(content_type, "boundary")
+    .ok_or(Error::multipart_boundary_missing())?
+    .to_owned()
+  ;
+  let expected_len = validate_get_content_len(req, max_len)?;
+  parse_multipart_fields(req.body_mut(), &boundary, expected_len).await
+}
+// The body reading part of parse_body_multipart, separate so it can be given
+// other bodies than that of a request
+async fn parse_multipart_fields<'a, T, B>(
+  body: &'a mut B,
+  boundary: &str,
+  expected_len: usize,
+) -> Result<(T, Multipart<'a, B>), Error>
+where
+  T: DeserializeOwned,
+  B: hyper::body::Body<Data = hyper::body::Bytes> + Unpin,
+  Error: From<B::Error>,
+{
+  let delimiter = format!("\r\n--{}", boundary).into_bytes();
+  let mut multipart = Multipart{
+    body,
+    delimiter,
+    // The first boundary lacks the leading newline, so we add it to be able to
+    // treat the preamble as data before the first boundary
+    buffer: b"\r\n".to_vec(),
+    received: 0,
+    expected_len,
+    state: MultipartState::Data,
+    pending_file: None,
+  };
+
+  // Collect the text fields until we reach a file or the end
+  let mut fields = Vec::new();
+  while let Some(part) = multipart.next_part().await? {
+    if part.filename.is_some() {
+      multipart.pending_file = Some(part);
+      break;
+    }
+    let value = String::from_utf8(multipart.read_to_end().await?)
+      .map_err(|_| Error::invalid_multipart("Text field is not valid UTF-8"))?
+    ;
+    fields.push((part.name, value));
+  }
+  // Reuse the urlencoded deserializer, to parse exactly like form submissions
+  let encoded = serde_urlencoded::to_string(&fields)
+    .map_err(|e| Error::invalid_multipart(&e.to_string()))?
+  ;
+  let data: T = serde_urlencoded::from_str(&encoded)
+    .map_err(|e| Error::invalid_multipart(&e.to_string()))?
+  ;
+  Ok((data, multipart))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http_body_util::StreamBody;
+  use hyper::body::{Bytes, Frame};
+
+  type TestBody = StreamBody<futures::stream::Iter<std::vec::IntoIter<
+    Result<Frame<Bytes>, std::convert::Infallible>
+  >>>;
+
+  #[derive(Debug, Deserialize)]
+  struct Form {
+    name: String,
+    #[serde(default)]
+    note: String,
+  }
+
+  // Deliver the body in frames of frame_len bytes, so boundaries and headers
+  // end up split between frames
+  fn body(data: &[u8], frame_len: usize) -> TestBody {
+    let frames: Vec<_> = data.chunks(frame_len)
+      .map(|c| Ok(Frame::data(Bytes::copy_from_slice(c))))
+      .collect()
+    ;
+    StreamBody::new(futures::stream::iter(frames))
+  }
+  const FORM: &[u8] = b"--XyZ\r\n\
+Content-Disposition: form-data; name=\"name\"\r\n\
+\r\n\
+Bank \xc3\xa5r\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"file\"; filename=\"a.csv\"\r\n\
+Content-Type: text/csv\r\n\
+\r\n\
+a,b\r\n--X\xff\r\n\
+--XyZ--\r\n";
+
+  #[tokio::test]
+  async fn fields_then_files_across_frames() {
+    for frame_len in [1, 3, 7, FORM.len()] {
+      let mut body = body(FORM, frame_len);
+      let (form, mut files): (Form, _) = parse_multipart_fields(&mut body, "XyZ", FORM.len())
+        .await
+        .unwrap()
+      ;
+      assert_eq!(form.name, "Bank år");
+      assert_eq!(form.note, "");
+      let file = files.next_file().await.unwrap().unwrap();
+      assert_eq!(file.name, "file");
+      assert_eq!(file.filename.as_deref(), Some("a.csv"));
+      // Data resembling the boundary and non-UTF-8 bytes are kept as is
+      assert_eq!(files.read_to_end().await.unwrap(), b"a,b\r\n--X\xff");
+      assert!(files.next_file().await.unwrap().is_none());
+    }
+  }
+  #[tokio::test]
+  async fn unread_files_are_skipped() {
+    let mut body = body(FORM, 5);
+    let (_, mut files): (Form, _) = parse_multipart_fields(&mut body, "XyZ", FORM.len())
+      .await
+      .unwrap()
+    ;
+    assert!(files.next_file().await.unwrap().is_some());
+    assert!(files.next_file().await.unwrap().is_none());
+  }
+  #[tokio::test]
+  async fn missing_final_boundary_is_refused() {
+    let data = &FORM[..FORM.len() - 9];
+    let mut body = body(data, 4);
+    let (_, mut files): (Form, _) = parse_multipart_fields(&mut body, "XyZ", data.len())
+      .await
+      .unwrap()
+    ;
+    files.next_file().await.unwrap();
+    assert!(files.read_to_end().await.is_err());
+  }
+  #[tokio::test]
+  async fn text_field_after_file_is_refused() {
+    let data = b"--b\r\n\
+Content-Disposition: form-data; name=\"name\"\r\n\r\nx\r\n\
+--b\r\n\
+Content-Disposition: form-data; name=\"file\"; filename=\"a\"\r\n\r\ndata\r\n\
+--b\r\n\
+Content-Disposition: form-data; name=\"note\"\r\n\r\nlate\r\n\
+--b--";
+    let mut body = body(data, 16);
+    let (_, mut files): (Form, _) = parse_multipart_fields(&mut body, "b", data.len())
+      .await
+      .unwrap()
+    ;
+    assert!(files.next_file().await.unwrap().is_some());
+    assert!(files.next_file().await.is_err());
+  }
+  #[tokio::test]
+  async fn malformed_parts_are_refused() {
+    let parts: [&[u8]; 3] = [
+      // No Content-Disposition
+      b"--b\r\nContent-Type: text/plain\r\n\r\nx\r\n--b--",
+      // No name
+      b"--b\r\nContent-Disposition: form-data\r\n\r\nx\r\n--b--",
+      // Garbage after the boundary
+      b"--bX\r\nContent-Disposition: form-data; name=\"name\"\r\n\r\nx\r\n--b--",
+    ];
+    for data in parts {
+      let mut body = body(data, 4);
+      let result: Result<(Form, _), _> = parse_multipart_fields(&mut body, "b", data.len()).await;
+      assert!(result.is_err());
+    }
+  }
+  #[tokio::test]
+  async fn body_longer_than_content_length_is_refused() {
+    let mut body = body(FORM, 8);
+    let (_, mut files): (Form, _) = parse_multipart_fields(&mut body, "XyZ", FORM.len() - 1)
+      .await
+      .unwrap()
+    ;
+    // Noticed when the data beyond the length is read
+    files.next_file().await.unwrap();
+    assert!(files.next_file().await.is_err());
+  }
+}
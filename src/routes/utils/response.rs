@@ -6,14 +6,14 @@ use super::*;
 
 // Return an empty OK response (HTTP 204)
 pub fn empty() -> Result<Response, Error> {
-  let mut re = Response::new("".into());
+  let mut re = Response::new(full(""));
   *re.status_mut() = StatusCode::NO_CONTENT;
   Ok(re)
 }
 // Return an empty not modified response
 // Useful to handle http conditional requests
 pub fn not_modified() -> Result<Response, Error> {
-  let mut re = Response::new("".into());
+  let mut re = Response::new(full(""));
   *re.status_mut() = StatusCode::NOT_MODIFIED;
   Ok(re)
 }
@@ -21,7 +21,7 @@ pub fn not_modified() -> Result<Response, Error> {
 pub fn html<B: Into<hyper::body::Bytes>>(
   data: B,
 ) -> Result<Response, Error> {
-  let mut re = Response::new(http_body_util::Full::new(data.into()));
+  let mut re = Response::new(full(data));
   re.headers_mut().insert(
     "Content-Type",
     HeaderValue::from_static("text/html; charset=utf-8")
@@ -32,7 +32,7 @@ pub fn html<B: Into<hyper::body::Bytes>>(
 pub fn redirect(
   target: &str,
 ) -> Result<Response, Error> {
-  let mut re = Response::new("".into());
+  let mut re = Response::new(full(""));
   // Explicitly requires client to GET the given URL
   *re.status_mut() = StatusCode::SEE_OTHER;
   re.headers_mut().insert(
@@ -45,7 +45,7 @@ pub fn redirect(
 pub fn permanent_redirect(
   target: &str,
 ) -> Result<Response, Error> {
-  let mut re = Response::new("".into());
+  let mut re = Response::new(full(""));
   // Explicitly requires client to GET the given URL
   *re.status_mut() = StatusCode::PERMANENT_REDIRECT;
   re.headers_mut().insert(
@@ -58,7 +58,7 @@ pub fn permanent_redirect(
 pub fn see_other(
   target: &str,
 ) -> Result<Response, Error> {
-  let mut re = Response::new("".into());
+  let mut re = Response::new(full(""));
   // Explicitly requires client to GET the given URL
   *re.status_mut() = StatusCode::SEE_OTHER;
   re.headers_mut().insert(
@@ -71,18 +71,58 @@ pub fn see_other(
 pub fn css(
   data: &'static str,
 ) -> Result<Response, Error> {
-  let mut re = Response::new(data.into());
+  let mut re = Response::new(full(data));
   re.headers_mut().insert(
     "Content-Type",
     HeaderValue::from_static("text/css; charset=utf-8")
   );
   Ok(re)
 }
+// Return given data as a file to download
+pub fn download<B: Into<hyper::body::Bytes>>(
+  data: B,
+  content_type: &'static str,
+  filename: &str,
+) -> Result<Response, Error> {
+  attachment(Response::new(full(data)), content_type, filename)
+}
+// Return the data from the stream as a file to download, so large files are
+// sent as they are produced
+pub fn download_stream<S>(
+  stream: S,
+  content_type: &'static str,
+  filename: &str,
+) -> Result<Response, Error>
+where
+  S: futures::Stream<Item = hyper::body::Bytes> + Send + 'static,
+{
+  use futures::StreamExt;
+  use http_body_util::BodyExt;
+  let body = http_body_util::StreamBody::new(
+    stream.map(|data| Ok(hyper::body::Frame::data(data)))
+  );
+  attachment(Response::new(body.boxed_unsync()), content_type, filename)
+}
+fn attachment(
+  mut re: Response,
+  content_type: &'static str,
+  filename: &str,
+) -> Result<Response, Error> {
+  re.headers_mut().insert(
+    "Content-Type",
+    HeaderValue::from_static(content_type),
+  );
+  re.headers_mut().insert(
+    "Content-Disposition",
+    HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))?,
+  );
+  Ok(re)
+}
 // Serialize given struct into json and return it
 pub fn json<T: Serialize + ?Sized>(
   data: &T,
 ) -> Result<Response, Error> {
-  let mut re = Response::new(serde_json::to_string(data)?.into());
+  let mut re = Response::new(full(serde_json::to_string(data)?));
   re.headers_mut().insert(
     "Content-Type",
     HeaderValue::from_static("application/json; charset=utf-8"),
@@ -0,0 +1,54 @@
+// Code page 437 (IBM PC 8-bit, called PC8 in SIE), which SIE files must use
+
+// The characters for bytes 0x80 to 0xFF, as the lower half is plain ASCII
+const UPPER_HALF: [char; 128] = [
+  'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+  'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+  'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+  '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+  '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+  '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+  'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+  '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+pub fn decode(bytes: &[u8]) -> String {
+  bytes.iter()
+    .map(|b| match b {
+      0..=0x7F => *b as char,
+      _ => UPPER_HALF[(*b - 0x80) as usize],
+    })
+    .collect()
+}
+// Characters that don't exist in CP437 are replaced with ?
+pub fn encode(text: &str) -> Vec<u8> {
+  text.chars()
+    .map(|c| match c {
+      '\0'..='\x7F' => c as u8,
+      c => UPPER_HALF.iter()
+        .position(|u| *u == c)
+        .map(|i| i as u8 + 0x80)
+        .unwrap_or(b'?'),
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn swedish_letters() {
+    assert_eq!(encode("åäöÅÄÖé"), [0x86, 0x84, 0x94, 0x8F, 0x8E, 0x99, 0x82]);
+    assert_eq!(decode(&[0x86, 0x84, 0x94, 0x8F, 0x8E, 0x99, 0x82]), "åäöÅÄÖé");
+  }
+  #[test]
+  fn every_byte_survives_a_round_trip() {
+    let bytes: Vec<u8> = (0..=255).collect();
+    assert_eq!(encode(&decode(&bytes)), bytes);
+  }
+  #[test]
+  fn missing_characters_become_question_marks() {
+    assert_eq!(encode("5€ ✓"), b"5? ?");
+  }
+}
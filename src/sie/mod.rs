@@ -0,0 +1,440 @@
+use rust_decimal::Decimal;
+use time::Date;
+
+use crate::Error;
+
+// Reading and writing of the Swedish SIE 4 exchange format for bookkeepings
+// The format is line based, each line a #LABEL followed by space separated
+// fields, where fields can be "quoted" and object lists are given in {}.
+
+mod cp437;
+
+#[derive(Debug)]
+pub struct FiscalYear {
+  // 0 is the current year, -1 the previous and so on
+  pub index: i32,
+  pub start: Date,
+  pub end: Date,
+}
+#[derive(Debug)]
+pub struct Account {
+  pub number: String,
+  pub name: String,
+  // From #KTYP: T (asset), S (debt), I (income) or K (expense)
+  pub kind: Option<String>,
+}
+#[derive(Debug)]
+pub struct Balance {
+  pub year: i32,
+  pub account: String,
+  pub amount: Decimal,
+}
+#[derive(Debug)]
+pub struct Transaction {
+  pub account: String,
+  pub amount: Decimal,
+  // Defaults to the date of the voucher
+  pub date: Option<Date>,
+  pub text: String,
+}
+// A #VER, which is what we call a transaction
+#[derive(Debug)]
+pub struct Voucher {
+  pub series: String,
+  pub number: String,
+  pub date: Date,
+  pub text: String,
+  pub transactions: Vec<Transaction>,
+}
+#[derive(Debug, Default)]
+pub struct SieFile {
+  pub company_name: Option<String>,
+  pub fiscal_years: Vec<FiscalYear>,
+  pub accounts: Vec<Account>,
+  pub opening_balances: Vec<Balance>,
+  pub vouchers: Vec<Voucher>,
+}
+
+// Split a line into its fields, unquoting strings and keeping {} lists whole
+fn split_fields(line: &str) -> Vec<String> {
+  let mut fields = Vec::new();
+  let mut chars = line.trim().chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      ' ' | '\t' => continue,
+      '"' => {
+        let mut field = String::new();
+        while let Some(c) = chars.next() {
+          match c {
+            '\\' => if let Some(escaped) = chars.next() { field.push(escaped) },
+            '"' => break,
+            c => field.push(c),
+          }
+        }
+        fields.push(field);
+      },
+      '{' => {
+        let mut field = String::from('{');
+        for c in chars.by_ref() {
+          field.push(c);
+          if c == '}' { break; }
+        }
+        fields.push(field);
+      },
+      c => {
+        let mut field = String::from(c);
+        while let Some(c) = chars.next_if(|c| *c != ' ' && *c != '\t') {
+          field.push(c);
+        }
+        fields.push(field);
+      },
+    }
+  }
+  fields
+}
+fn parse_date(raw: &str) -> Result<Date, String> {
+  let invalid = || format!("Invalid date {raw}");
+  if raw.len() != 8 || !raw.chars().all(|c| c.is_ascii_digit()) {
+    return Err(invalid());
+  }
+  let month = time::Month::try_from(raw[4..6].parse::<u8>().map_err(|_| invalid())?)
+    .map_err(|_| invalid())?
+  ;
+  Date::from_calendar_date(
+    raw[..4].parse().map_err(|_| invalid())?,
+    month,
+    raw[6..].parse().map_err(|_| invalid())?,
+  ).map_err(|_| invalid())
+}
+fn parse_amount(raw: &str) -> Result<Decimal, String> {
+  raw.parse().map_err(|e: rust_decimal::Error| format!("Invalid amount {raw}: {e}"))
+}
+
+// Parse a SIE 4 file, as raw bytes since it is encoded in CP437
+pub fn parse(
+  data: &[u8],
+) -> Result<SieFile, Error> {
+  let text = cp437::decode(data);
+  let mut file = SieFile::default();
+  // The voucher whose {} block we are in, if any
+  let mut voucher: Option<Voucher> = None;
+
+  for (i, line) in text.lines().enumerate() {
+    let line_number = i + 1;
+    let invalid = |e: String| Error::invalid_import_file(line_number, &e);
+    let fields = split_fields(line);
+    let field = |index: usize| fields.get(index)
+      .map(|f| f.as_str())
+      .ok_or(invalid(format!("Missing field {index}")))
+    ;
+    let Some(label) = fields.first() else { continue };
+    match label.as_str() {
+      "#FNAMN" => file.company_name = Some(field(1)?.to_owned()),
+      "#RAR" => file.fiscal_years.push(FiscalYear{
+        index: field(1)?.parse().map_err(|_| invalid("Invalid year index".to_owned()))?,
+        start: parse_date(field(2)?).map_err(invalid)?,
+        end: parse_date(field(3)?).map_err(invalid)?,
+      }),
+      "#KONTO" => file.accounts.push(Account{
+        number: field(1)?.to_owned(),
+        name: field(2)?.to_owned(),
+        kind: None,
+      }),
+      "#KTYP" => {
+        let number = field(1)?;
+        let kind = field(2)?.to_owned();
+        match file.accounts.iter_mut().find(|a| a.number == number) {
+          Some(account) => account.kind = Some(kind),
+          None => file.accounts.push(Account{
+            number: number.to_owned(),
+            name: String::new(),
+            kind: Some(kind),
+          }),
+        }
+      },
+      "#IB" => file.opening_balances.push(Balance{
+        year: field(1)?.parse().map_err(|_| invalid("Invalid year index".to_owned()))?,
+        account: field(2)?.to_owned(),
+        amount: parse_amount(field(3)?).map_err(invalid)?,
+      }),
+      "#VER" => {
+        if voucher.is_some() {
+          return Err(invalid("#VER within another #VER".to_owned()));
+        }
+        voucher = Some(Voucher{
+          series: fields.get(1).cloned().unwrap_or_default(),
+          number: fields.get(2).cloned().unwrap_or_default(),
+          date: parse_date(field(3)?).map_err(invalid)?,
+          text: fields.get(4).cloned().unwrap_or_default(),
+          transactions: Vec::new(),
+        });
+      },
+      // The lines of a voucher are given within a {} block after it
+      "{" if voucher.is_none() => return Err(invalid("{ without #VER".to_owned())),
+      "}" => match voucher.take() {
+        Some(v) => file.vouchers.push(v),
+        None => return Err(invalid("} without #VER".to_owned())),
+      },
+      "#TRANS" => {
+        let v = voucher.as_mut()
+          .ok_or(invalid("#TRANS outside of #VER".to_owned()))?
+        ;
+        // Fields after the amount are optional, and may be given empty
+        let date = match fields.get(4).map(|f| f.as_str()) {
+          None | Some("") => None,
+          Some(date) => Some(parse_date(date).map_err(invalid)?),
+        };
+        v.transactions.push(Transaction{
+          account: field(1)?.to_owned(),
+          amount: parse_amount(field(3)?).map_err(invalid)?,
+          date,
+          text: fields.get(5).cloned().unwrap_or_default(),
+        });
+      },
+      // Added and removed transactions are for audit trails, the resulting
+      // voucher is described by the #TRANS lines alone
+      "#RTRANS" | "#BTRANS" => (),
+      // Other labels aren't needed to recreate the bookkeeping
+      _ => (),
+    }
+  }
+  if voucher.is_some() {
+    return Err(Error::invalid_import_file(text.lines().count(), "#VER without closing }"));
+  }
+  Ok(file)
+}
+
+// A field to write, values are written as is and texts are quoted
+pub enum Field<'a> {
+  Value(&'a str),
+  Text(&'a str),
+}
+pub fn format_date(date: Date) -> String {
+  format!("{:04}{:02}{:02}", date.year(), date.month() as u8, date.day())
+}
+
+// CRC-32 as used by zip and PNG, which is what #KSUMMA uses
+fn crc32_update(mut crc: u32, bytes: &[u8]) -> u32 {
+  for byte in bytes {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+    }
+  }
+  crc
+}
+
+// Writes a SIE 4 file encoded in CP437, with a #KSUMMA checksum
+// The checksum covers the labels and field contents from the first #KSUMMA,
+// without the spaces, quotes and line breaks between them.
+pub struct Writer {
+  data: Vec<u8>,
+  // Only what comes after the opening #KSUMMA is checksummed
+  checksumming: bool,
+  crc: u32,
+}
+impl Writer {
+  // Starts the file with the labels every SIE 4 file begins with
+  pub fn new(program: &str) -> Self {
+    let mut writer = Self{
+      data: Vec::new(),
+      checksumming: false,
+      crc: !0,
+    };
+    writer.line("#FLAGGA", &[Field::Value("0")]);
+    writer.line("#KSUMMA", &[]);
+    writer.line("#PROGRAM", &[Field::Text(program), Field::Text(env!("CARGO_PKG_VERSION"))]);
+    writer.line("#FORMAT", &[Field::Value("PC8")]);
+    writer.line("#SIETYP", &[Field::Value("4")]);
+    writer
+  }
+  pub fn line(&mut self, label: &str, fields: &[Field]) {
+    let label = cp437::encode(label);
+    self.checksumming |= label == b"#KSUMMA";
+    if self.checksumming { self.crc = crc32_update(self.crc, &label); }
+    self.data.extend_from_slice(&label);
+    for field in fields {
+      self.data.push(b' ');
+      let (content, quoted) = match field {
+        Field::Value(value) => (cp437::encode(value), false),
+        Field::Text(text) => (cp437::encode(text), true),
+      };
+      if self.checksumming { self.crc = crc32_update(self.crc, &content); }
+      if quoted {
+        self.data.push(b'"');
+        for byte in content {
+          if byte == b'"' || byte == b'\\' { self.data.push(b'\\'); }
+          self.data.push(byte);
+        }
+        self.data.push(b'"');
+      } else {
+        self.data.extend_from_slice(&content);
+      }
+    }
+    self.data.extend_from_slice(b"\r\n");
+  }
+  // How much has been written since the last take
+  pub fn buffered(&self) -> usize {
+    self.data.len()
+  }
+  // Takes what has been written so far, to send it while writing goes on
+  pub fn take(&mut self) -> Vec<u8> {
+    std::mem::take(&mut self.data)
+  }
+  // Ends the file with the checksum
+  pub fn finish(mut self) -> Vec<u8> {
+    let checksum = (!self.crc as i32).to_string();
+    self.line("#KSUMMA", &[Field::Value(&checksum)]);
+    self.data
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn day(year: i32, month: time::Month, day: u8) -> Date {
+    Date::from_calendar_date(year, month, day).unwrap()
+  }
+  fn parse_text(text: &str) -> Result<SieFile, Error> {
+    parse(&cp437::encode(text))
+  }
+
+  #[test]
+  fn crc32_check_value() {
+    assert_eq!(!crc32_update(!0, b"123456789"), 0xCBF4_3926);
+    // Updating in parts gives the same as all at once
+    assert_eq!(crc32_update(crc32_update(!0, b"1234"), b"56789"), crc32_update(!0, b"123456789"));
+  }
+  #[test]
+  fn fields_are_split_and_unquoted() {
+    assert_eq!(
+      split_fields("  #TRANS\t1930 {1 \"a b\"} -5.00 \"\" \"Say \\\"hi\\\"\" "),
+      ["#TRANS", "1930", "{1 \"a b\"}", "-5.00", "", "Say \"hi\""],
+    );
+    // An unterminated quote runs to the end of the line
+    assert_eq!(split_fields("#FNAMN \"AB"), ["#FNAMN", "AB"]);
+  }
+  #[test]
+  fn amounts_and_dates() {
+    assert_eq!(parse_amount("-1234.5"), Ok(Decimal::new(-12345, 1)));
+    // Only a decimal point, so a comma could be either and is refused
+    assert!(parse_amount("100,50").is_err());
+    assert!(parse_amount("1,234.50").is_err());
+    assert!(parse_amount("12.5.5").is_err());
+    assert!(parse_amount("").is_err());
+    assert_eq!(parse_date("20260229").ok(), None);
+    assert_eq!(parse_date("20240229"), Ok(day(2024, time::Month::February, 29)));
+    for date in ["2024-02-29", "202402291", "2024o229", "20241301"] {
+      assert!(parse_date(date).is_err(), "{date}");
+    }
+  }
+  #[test]
+  fn parses_a_file() {
+    let file = parse_text("#FLAGGA 0\r
+#FNAMN \"Åkeri AB\"\r
+#RAR 0 20260101 20261231\r
+#RAR -1 20250101 20251231\r
+#KONTO 1930 \"Företagskonto\"\r
+#KTYP 1930 T\r
+#KTYP 3001 I\r
+#IB 0 1930 1500.00\r
+#UB 0 1930 1000.00\r
+#VER A 1 20260115 \"Försäljning\"\r
+{\r
+  #TRANS 1930 {} 500\r
+  #TRANS 3001 {} -500 \"\" \"\"\r
+}\r
+#VER \"B\" \"2\" 20260116\r
+{\r
+  #RTRANS 1930 {} -100\r
+  #TRANS 1930 {} -100 20260117 \"Fee\"\r
+  #TRANS 6570 {} 100\r
+}\r
+").unwrap();
+    assert_eq!(file.company_name.as_deref(), Some("Åkeri AB"));
+    assert_eq!(file.fiscal_years.len(), 2);
+    assert_eq!(file.fiscal_years[1].index, -1);
+    assert_eq!(file.fiscal_years[1].end, day(2025, time::Month::December, 31));
+    // #KTYP sets the type of a listed account, or adds an unnamed one
+    assert_eq!(file.accounts.len(), 2);
+    assert_eq!(file.accounts[0].name, "Företagskonto");
+    assert_eq!(file.accounts[0].kind.as_deref(), Some("T"));
+    assert_eq!(file.accounts[1].number, "3001");
+    assert_eq!(file.accounts[1].name, "");
+    assert_eq!(file.opening_balances.len(), 1);
+    assert_eq!(file.opening_balances[0].amount, Decimal::new(150000, 2));
+
+    assert_eq!(file.vouchers.len(), 2);
+    let voucher = &file.vouchers[0];
+    assert_eq!((voucher.series.as_str(), voucher.number.as_str()), ("A", "1"));
+    assert_eq!(voucher.text, "Försäljning");
+    assert_eq!(voucher.transactions[1].amount, Decimal::from(-500));
+    assert_eq!(voucher.transactions[1].date, None);
+    // #RTRANS lines are left out
+    let voucher = &file.vouchers[1];
+    assert_eq!(voucher.transactions.len(), 2);
+    assert_eq!(voucher.transactions[0].date, Some(day(2026, time::Month::January, 17)));
+    assert_eq!(voucher.transactions[0].text, "Fee");
+  }
+  #[test]
+  fn malformed_files_are_refused() {
+    for text in [
+      "#TRANS 1930 {} 1",
+      "{\n}",
+      "}",
+      "#VER A 1 20260101\n{\n#VER A 2 20260101\n{\n}\n}",
+      "#VER A 1 20260101\n{\n#TRANS 1930 {} 1",
+      "#VER A 1 20260101\n{\n#TRANS 1930 {}\n}",
+      "#VER A 1 20260101\n{\n#TRANS 1930 {} 1kr\n}",
+      "#VER A 1 2026-01-01\n{\n}",
+      "#RAR now 20260101 20261231",
+      "#IB 0 1930",
+      "#KONTO 1930",
+    ] {
+      assert!(parse_text(text).is_err(), "{text}");
+    }
+  }
+  #[test]
+  fn written_files_read_back() {
+    let mut writer = Writer::new("test");
+    writer.line("#FNAMN", &[Field::Text("Bolaget \"Å\" AB")]);
+    writer.line("#VER", &[
+      Field::Text("A"),
+      Field::Text("1"),
+      Field::Value("20260101"),
+      Field::Text("Back\\slash"),
+    ]);
+    writer.line("{", &[]);
+    writer.line("#TRANS", &[Field::Value("1930"), Field::Value("{}"), Field::Value("-1.50")]);
+    writer.line("}", &[]);
+    let data = writer.finish();
+    assert!(data.windows(12).any(|w| w == b"\"Bolaget \\\"\x8f"));
+    assert!(data.ends_with(b"\r\n"));
+    let file = parse(&data).unwrap();
+    assert_eq!(file.company_name.as_deref(), Some("Bolaget \"Å\" AB"));
+    assert_eq!(file.vouchers[0].text, "Back\\slash");
+    assert_eq!(file.vouchers[0].transactions[0].amount, Decimal::new(-150, 2));
+  }
+  #[test]
+  fn checksum_covers_fields_after_the_first_ksumma() {
+    let checksum = |name: &str| {
+      let mut writer = Writer::new("test");
+      writer.line("#FNAMN", &[Field::Text(name)]);
+      let data = writer.finish();
+      let text = cp437::decode(&data);
+      text.lines().last().unwrap().to_owned()
+    };
+    // Quotes and spaces aren't part of the checksum, only the contents up to
+    // the closing #KSUMMA
+    let mut expected = !0;
+    for part in [
+      "#KSUMMA", "#PROGRAM", "test", env!("CARGO_PKG_VERSION"), "#FORMAT", "PC8",
+      "#SIETYP", "4", "#FNAMN", "AB",
+    ] {
+      expected = crc32_update(expected, part.as_bytes());
+    }
+    assert_eq!(checksum("AB"), format!("#KSUMMA {}", !expected as i32));
+    assert_ne!(checksum("AB"), checksum("BA"));
+  }
+}
@@ -1,6 +1,14 @@
-pub type Response = hyper::Response<http_body_util::Full<hyper::body::Bytes>>;
+// Boxed, so responses can be either given whole or streamed
+pub type Body = http_body_util::combinators::UnsyncBoxBody<hyper::body::Bytes, std::convert::Infallible>;
+pub type Response = hyper::Response<Body>;
 pub type Request = hyper::Request<hyper::body::Incoming>;
 
+// A body with all the data given at once
+pub fn full<B: Into<hyper::body::Bytes>>(data: B) -> Body {
+  use http_body_util::BodyExt;
+  http_body_util::Full::new(data.into()).boxed_unsync()
+}
+
 pub trait Reply {
   fn into_response(self) -> Response;
 }